@@ -13,6 +13,11 @@ export type UserQuery = {
     password: string
 };
 
+/**
+ * Represents a role which grants access to privileged endpoints.
+ */
+export type Role = 'admin' | 'treasurer' | 'moderator' | 'kiosk_manager';
+
 /**
  * Represents a user and their information.
  */
@@ -21,7 +26,18 @@ export type User = {
     username: string,
     balance_cents: number,
     created_at: Date,
-    is_admin: boolean,
+    deleted_at: Date | null,
+    credit_limit_cents: number,
+    roles: Role[],
+    in_debt: boolean,
+};
+
+/**
+ * Represents users with a negative balance.
+ */
+export type DebtorsReport = {
+    debtors: Omit<User, 'roles' | 'in_debt'>[],
+    total_debt_cents: number,
 };
 
 /**
@@ -29,9 +45,39 @@ export type User = {
  */
 export type ItemQuery = {
     search_term: string | null,
-    offset: number | null,
+    /** `next_cursor` of the previous page, to get the next one */
+    cursor: string | null,
     limit: number | null,
     get_items_without_stock: boolean | null,
+    include_archived: boolean | null,
+    category_id: number | null,
+    tags: string[] | null,
+    sort: ItemSort | null,
+    min_price_cents: number | null,
+    max_price_cents: number | null,
+    seller_id: number | null,
+    seller: string | null,
+};
+
+/**
+ * Orders items can be listed in. Relevance only applies when searching.
+ */
+export type ItemSort = 'relevance' | 'newest' | 'oldest' | 'price_asc' | 'price_desc' | 'popular';
+
+/**
+ * Represents an item category.
+ */
+export type Category = {
+    id: number,
+    name: string,
+    created_at: Date,
+};
+
+/**
+ * Represents a category along with the amount of items in it.
+ */
+export type CategoryFacet = Category & {
+    item_count: number,
 };
 
 /**
@@ -57,7 +103,34 @@ export type ItemResult = {
     amount: number,
     seller_id: number,
     created_at: Date,
-    attachments: Attachment[]
+    version: number,
+    archived_at: Date | null,
+    deleted_at: Date | null,
+    category_id: number | null,
+    units_sold: number,
+    last_sold_at: Date | null,
+    barcode: string | null,
+    attachments: Attachment[],
+    tags: string[],
+    /** Highlighted part of the description matching the search term, safe to show as HTML */
+    snippet?: string,
+};
+
+/**
+ * Represents a single item along with its seller.
+ */
+export type ItemDetail = ItemResult & {
+    seller_username: string,
+};
+
+/**
+ * Represents a list of items, along with item counts per category.
+ */
+export type ItemListResult = {
+    items: ItemResult[],
+    total: number,
+    next_cursor: string | null,
+    categories: CategoryFacet[],
 };
 
 /**
@@ -69,6 +142,33 @@ export type NewItemQuery = {
     amount: number,
     price: string,
     attachments: number[],
+    category_id: number | null,
+    tags: string[],
+    barcode: string | null,
+};
+
+/**
+ * Represents the parameters for editing an item. Fields left null are not changed.
+ */
+export type EditItemQuery = {
+    item_id: number,
+    version: number,
+    title: string | null,
+    description: string | null,
+    price: string | null,
+    /** Null removes the item from its category, leaving this out keeps the category */
+    category_id?: number | null,
+    tags: string[] | null,
+    /** Empty barcode removes the barcode of the item */
+    barcode: string | null,
+};
+
+/**
+ * Represents the parameters for adding or removing stock of an item.
+ */
+export type StockQuery = {
+    item_id: number,
+    change: number,
 };
 
 /**
@@ -79,14 +179,97 @@ export type BuyQuery = {
     amount: number,
 };
 
+/**
+ * Represents a product of the imported catalogue, used to prefill new items.
+ */
+export type Product = {
+    barcode: string,
+    title: string,
+    description: string,
+    imported_at: Date,
+};
+
+/**
+ * Represents a catalogue import running in the background, or its outcome.
+ */
+export type ProductImport = {
+    id: number,
+    file_name: string,
+    started_by: number,
+    started_at: Date,
+    finished_at: Date | null,
+    imported: number,
+    skipped: number,
+    failure: string | null,
+};
+
+/**
+ * Represents the query parameters for buying the cheapest item with a scanned barcode.
+ */
+export type ScanBuyQuery = {
+    barcode: string,
+    amount: number,
+};
+
+/**
+ * Represents the query parameters for adding an item to the cart.
+ */
+export type CartQuery = {
+    item_id: number,
+    amount: number,
+};
+
+/**
+ * Represents the query parameters for removing an item from the cart.
+ * Leaving out the amount removes the whole line.
+ */
+export type RemoveFromCartQuery = {
+    item_id: number,
+    amount?: number,
+};
+
+/**
+ * Represents an item in the cart, reserved for the user until `reserved_until`.
+ */
+export type CartLine = {
+    item: ItemResult,
+    amount: number,
+    reserved_until: string,
+};
+
+/**
+ * Represents the contents of the cart.
+ */
+export type CartResult = {
+    lines: CartLine[],
+    total_cents: number,
+};
+
+/**
+ * Represents a checked out cart.
+ */
+export type Order = {
+    id: number,
+    buyer_id: number,
+    total_cents: number,
+    created_at: string,
+};
+
 /**
  * Represents the query parameters for giving balance to a user.
  */
 export type AdminGiveQuery = {
     user_id: number | null,
     amount_cents: number,
+    kind: AdjustmentKind,
+    reason: string,
 };
 
+/**
+ * Represents the kind of an admin balance adjustment.
+ */
+export type AdjustmentKind = 'cash_deposit' | 'cash_withdrawal' | 'correction' | 'refund';
+
 /**
  * Represents the query parameters for validating a form value.
  */
@@ -100,9 +283,113 @@ export type ValidateQuery = {
 export type TransferQuery = {
     amount_cents: number,
     recipient: string,
+    memo?: string,
+};
+
+/**
+ * Represents the query parameters for asking another user for money.
+ */
+export type NewPaymentRequestQuery = {
+    amount_cents: number,
+    payer: string,
+    memo?: string,
+};
+
+/**
+ * Represents a request for money, and the username of the other party.
+ */
+export type PaymentRequest = {
+    id: number,
+    requester_id: number,
+    payer_id: number,
+    amount_cents: number,
+    memo: string | null,
+    status: 'pending' | 'accepted' | 'declined' | 'cancelled',
+    created_at: string,
+    resolved_at: string | null,
+    transaction_id: number | null,
+    bill_id: number | null,
+    counterparty: string,
+};
+
+/**
+ * Represents a participant of a bill. Either every participant has a share, or the bill is
+ * split evenly between the participants and the creator.
+ */
+export type BillParticipant = {
+    username: string,
+    share_cents?: number,
+};
+
+/**
+ * Represents the query parameters for splitting a bill.
+ */
+export type NewBillQuery = {
+    total_cents: number,
+    participants: BillParticipant[],
+    memo?: string,
+};
+
+/**
+ * Represents a split bill and the payment request of each participant.
+ */
+export type Bill = {
+    id: number,
+    creator_id: number,
+    total_cents: number,
+    memo: string | null,
+    created_at: string,
+    requests: PaymentRequest[],
+    settled_cents: number,
+    status: 'open' | 'settled' | 'closed',
+};
+
+/**
+ * Represents the pending payment requests of the user, to pay and to be paid.
+ */
+export type PaymentRequestList = {
+    incoming: PaymentRequest[],
+    outgoing: PaymentRequest[],
+};
+
+/**
+ * Represents the query parameters for refunding a purchase. Leaving out the amount refunds
+ * everything not refunded yet.
+ */
+export type RefundQuery = {
+    transaction_id: number,
+    amount_cents?: number,
+    item_amount?: number,
+    restock: boolean,
+    reason: string,
 };
 
 const api = (() => {
+    /**
+     * Posts a money moving request, retrying on network errors. Every attempt
+     * carries the same idempotency key, so the server carries the request out
+     * only once.
+     * @param {string} url Url to post to
+     * @param {any} query Request body
+     * @param {number} attempts How many times to try before giving up
+     */
+    const postIdempotent = async (url: string, query: any, attempts: number = 3): Promise<Response> => {
+        const idempotentHeaders = { ...headers, 'Idempotency-Key': crypto.randomUUID() };
+        for (let attempt = 1; ; attempt++) {
+            try {
+                return await fetch(url, {
+                    body: JSON.stringify(query),
+                    method: 'POST',
+                    headers: idempotentHeaders,
+                });
+            } catch (err) {
+                if (attempt >= attempts) {
+                    throw err;
+                }
+            }
+        }
+    };
+
     /**
      * Updates `userInfo` with fresh information from the server
      */
@@ -112,7 +399,7 @@ const api = (() => {
             userInfo.isLoggedIn = true;
             userInfo.username = user.username;
             userInfo.balance = (user.balance_cents / 100.0).toString();
-            userInfo.isAdmin = user.is_admin;
+            userInfo.isAdmin = user.roles.length > 0;
         } catch (err) {
             userInfo.isLoggedIn = false;
             userInfo.username = "";
@@ -207,13 +494,17 @@ const api = (() => {
     };
 
     /**
-     * Get list of items for sale. List can be filtered, limited and offset by provided ItemQuery.
-     * @param {ItemQuery | null} query Optional request filtering, result limiting and offset information.
-     * @returns {ItemResult[]} List of items matching query
+     * Get list of items for sale. List can be filtered, sorted and paged by provided ItemQuery.
+     * @param {ItemQuery | null} query Optional request filtering, sorting and paging information.
+     * @returns {ItemListResult} List of items matching query, and item counts per category
      */
-    const getItems = async (query: ItemQuery | null = null): Promise<ItemResult[]> => {
+    const getItems = async (query: ItemQuery | null = null): Promise<ItemListResult> => {
         if (query == null) {
-            query = { limit: null, offset: null, search_term: null, get_items_without_stock: false };
+            query = {
+                limit: null, cursor: null, search_term: null, get_items_without_stock: false, include_archived: false,
+                category_id: null, tags: null, sort: null, min_price_cents: null, max_price_cents: null,
+                seller_id: null, seller: null,
+            };
         }
         const response = await fetch(`${apiUrl}/item/list`, {
             body: JSON.stringify(query),
@@ -226,6 +517,23 @@ const api = (() => {
         return await response.json();
     };
 
+    /**
+     * Gets a single item, including items out of stock and archived items.
+     * The browser revalidates cached responses with the item's ETag.
+     * @param {Number} itemId Id of the item
+     * @returns {ItemDetail} Item information along with its seller
+     */
+    const getItem = async (itemId: Number): Promise<ItemDetail> => {
+        const response = await fetch(`${apiUrl}/item/${itemId}`, {
+            method: 'GET',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
     /**
      * Sells an item with provided information.
      * @param {NewItemQuery} query New item information
@@ -243,6 +551,71 @@ const api = (() => {
         return await response.json();
     };
 
+    /**
+     * Edits an item. Fails if the item has been edited since `query.version`,
+     * in which case the item should be reloaded before trying again.
+     * @param {EditItemQuery} query Item id, version and changed information
+     * @returns {ItemResult} Edited item information
+     */
+    const editItem = async (query: EditItemQuery): Promise<ItemResult> => {
+        const response = await fetch(`${apiUrl}/item/edit`, {
+            body: JSON.stringify(query),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Adds or removes stock of an item
+     * @param {StockQuery} query Item id and change in stock
+     * @returns {ItemResult} Item information with updated stock
+     */
+    const changeStock = async (query: StockQuery): Promise<ItemResult> => {
+        const response = await fetch(`${apiUrl}/item/stock`, {
+            body: JSON.stringify(query),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Archives an item, hiding it from listings
+     * @param {Number} itemId Id of the item to archive
+     */
+    const archiveItem = async (itemId: Number): Promise<void> => {
+        const response = await fetch(`${apiUrl}/item/archive`, {
+            body: JSON.stringify({ item_id: itemId }),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Deletes an item. Deleted items stay visible in transaction history only.
+     * @param {Number} itemId Id of the item to delete
+     */
+    const deleteItem = async (itemId: Number): Promise<void> => {
+        const response = await fetch(`${apiUrl}/item/delete`, {
+            body: JSON.stringify({ item_id: itemId }),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
     /**
      * Uploads a new attachment which can be used in `newItem()`
      * @param {File} file Attachment file to be uploaded
@@ -266,7 +639,91 @@ const api = (() => {
      * @param {BuyQuery} query Item id and amount to be bought
      */
     const buyItem = async (query: BuyQuery): Promise<void> => {
-        const response = await fetch(`${apiUrl}/item/buy`, {
+        const response = await postIdempotent(`${apiUrl}/item/buy`, query);
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Lists items with a barcode, cheapest first
+     * @param {string} barcode EAN-13 or UPC-A barcode
+     * @returns {ItemResult[]} Items with the barcode
+     */
+    const getItemsByBarcode = async (barcode: string): Promise<ItemResult[]> => {
+        const response = await fetch(`${apiUrl}/item/barcode/${encodeURIComponent(barcode)}`, {
+            method: 'GET',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Buys the cheapest item in stock with a scanned barcode
+     * @param {ScanBuyQuery} query Barcode and amount to be bought
+     */
+    const scanToBuy = async (query: ScanBuyQuery): Promise<void> => {
+        const response = await postIdempotent(`${apiUrl}/item/barcode/buy`, query);
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Looks a product up from the imported catalogue
+     * @param {string} barcode EAN-13 or UPC-A barcode
+     * @returns {Product} Product to prefill a new item with
+     */
+    const getProduct = async (barcode: string): Promise<Product> => {
+        const response = await fetch(`${apiUrl}/product/${encodeURIComponent(barcode)}`, {
+            method: 'GET',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Fetches the contents of the cart
+     * @returns {CartResult} Items in the cart and their total price
+     */
+    const getCart = async (): Promise<CartResult> => {
+        const response = await fetch(`${apiUrl}/cart`, {
+            method: 'GET',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Adds an item to the cart, reserving it for a while
+     * @param {CartQuery} query Item id and amount to be added
+     */
+    const addToCart = async (query: CartQuery): Promise<void> => {
+        const response = await fetch(`${apiUrl}/cart/add`, {
+            body: JSON.stringify(query),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Removes an item from the cart
+     * @param {RemoveFromCartQuery} query Item id and amount to be removed
+     */
+    const removeFromCart = async (query: RemoveFromCartQuery): Promise<void> => {
+        const response = await fetch(`${apiUrl}/cart/remove`, {
             body: JSON.stringify(query),
             method: 'POST',
             headers,
@@ -277,15 +734,94 @@ const api = (() => {
     };
 
     /**
-     * Promotes user to admin status. Tries to promote user logged in if no
-     * user is proveded. The endpoint normally requires admin status, but
-     * in debug builds does not.
-     * @param {Number | null} userId User id to promote
+     * Buys everything in the cart at once
+     * @returns {Order} Created order
      */
-    const adminPromote = async (userId: Number | null = null): Promise<void> => {
-        const response = await fetch(`${apiUrl}/admin/promote`, {
+    const checkout = async (): Promise<Order> => {
+        const response = await postIdempotent(`${apiUrl}/cart/checkout`, null);
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Creates a new item category. Requires admin role.
+     * @param {string} name Name of the category
+     * @returns {Category} Created category
+     */
+    const adminNewCategory = async (name: string): Promise<Category> => {
+        const response = await fetch(`${apiUrl}/admin/category/new`, {
+            body: JSON.stringify({ name }),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Starts importing a product catalogue file from the catalogue directory of the server in the background. Requires admin role.
+     * @param {string} file Name of the catalogue file
+     * @returns {ProductImport} The started import
+     */
+    const adminImportProducts = async (file: string): Promise<ProductImport> => {
+        const response = await fetch(`${apiUrl}/admin/products/import`, {
+            body: JSON.stringify({ file }),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 202) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Gets the progress or outcome of a catalogue import. Requires admin role.
+     * @param {number} import_id Id of the import
+     * @returns {ProductImport} The import, with `finished_at` set once it has ended
+     */
+    const adminGetProductImport = async (import_id: number): Promise<ProductImport> => {
+        const response = await fetch(`${apiUrl}/admin/products/import/status`, {
+            body: JSON.stringify({ import_id }),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Deletes an item category, leaving its items without one. Requires admin role.
+     * @param {Number} categoryId Id of the category to delete
+     */
+    const adminDeleteCategory = async (categoryId: Number): Promise<void> => {
+        const response = await fetch(`${apiUrl}/admin/category/delete`, {
+            body: JSON.stringify({ category_id: categoryId }),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Grants a role to user. The endpoint normally requires admin role, but
+     * in development mode does not.
+     * @param {Number} userId User id to grant the role to
+     * @param {Role} role Role to grant
+     */
+    const adminGrantRole = async (userId: Number, role: Role): Promise<void> => {
+        const response = await fetch(`${apiUrl}/admin/roles/grant`, {
             body: JSON.stringify({
-                user_id: userId
+                user_id: userId,
+                role,
             }),
             method: 'POST',
             headers,
@@ -296,13 +832,17 @@ const api = (() => {
     };
 
     /**
-     * Gives user currency. Adds currency to currently logged in user if no
-     * user is provided. The endpoint normally requires admin status, but
-     * in debug builds does not.
+     * Sets how far below zero the balance of a user may go. The endpoint normally requires
+     * treasurer role, but in development mode does not.
+     * @param {Number} userId User id to set the limit of
+     * @param {Number} creditLimitCents New credit limit
      */
-    const adminGive = async (query: AdminGiveQuery): Promise<void> => {
-        const response = await fetch(`${apiUrl}/admin/give`, {
-            body: JSON.stringify(query),
+    const adminSetCreditLimit = async (userId: Number, creditLimitCents: Number): Promise<void> => {
+        const response = await fetch(`${apiUrl}/admin/credit_limit`, {
+            body: JSON.stringify({
+                user_id: userId,
+                credit_limit_cents: creditLimitCents,
+            }),
             method: 'POST',
             headers,
         });
@@ -311,6 +851,34 @@ const api = (() => {
         }
     };
 
+    /**
+     * Fetches users with a negative balance. The endpoint normally requires treasurer role,
+     * but in development mode does not.
+     * @returns {DebtorsReport} Debtors, deepest in debt first
+     */
+    const adminGetDebtors = async (): Promise<DebtorsReport> => {
+        const response = await fetch(`${apiUrl}/admin/debtors`, {
+            method: 'GET',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Gives user currency. Adds currency to currently logged in user if no
+     * user is provided. The endpoint normally requires admin status, but
+     * in development mode does not.
+     */
+    const adminGive = async (query: AdminGiveQuery): Promise<void> => {
+        const response = await postIdempotent(`${apiUrl}/admin/give`, query);
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
     /**
      * Validates a given value against a specific type of field.
      * @param {string} type - The type of validation to perform. Must be one of 'password', 'currency',
@@ -339,19 +907,145 @@ const api = (() => {
      * @param {TransferQuery} query - User to transfer currency to, and amount to transfer.
      */
     const transfer = async (query: TransferQuery): Promise<void> => {
-        const response = await fetch(`${apiUrl}/transfer`, {
+        const response = await postIdempotent(`${apiUrl}/transfer`, query);
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Refunds a purchase, fully or partially. Requires being the seller or an admin.
+     * @param {RefundQuery} query - Purchase to refund, amounts to refund and the reason for it.
+     */
+    const refund = async (query: RefundQuery): Promise<void> => {
+        const response = await postIdempotent(`${apiUrl}/refund`, query);
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Cancels a purchase of the user logged in, shortly after making it.
+     * @param {Number} transactionId - Id of the purchase transaction to cancel.
+     */
+    const cancelPurchase = async (transactionId: Number): Promise<void> => {
+        const response = await postIdempotent(`${apiUrl}/cancel`, { transaction_id: transactionId });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Asks another user for money.
+     * @param {NewPaymentRequestQuery} query - User to ask, amount to ask for and what it is for.
+     */
+    const requestPayment = async (query: NewPaymentRequestQuery): Promise<void> => {
+        const response = await fetch(`${apiUrl}/request/new`, {
+            body: JSON.stringify(query),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Fetches pending payment requests of the user logged in.
+     * @returns {PaymentRequestList} Requests to pay and requests made.
+     */
+    const getPaymentRequests = async (): Promise<PaymentRequestList> => {
+        const response = await fetch(`${apiUrl}/request/list`, {
+            method: 'GET',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Pays a payment request made to the user logged in.
+     * @param {Number} requestId - Id of the request to pay.
+     */
+    const acceptPaymentRequest = async (requestId: Number): Promise<void> => {
+        const response = await postIdempotent(`${apiUrl}/request/accept`, { request_id: requestId });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Declines a payment request made to the user logged in, or cancels one made by them.
+     * @param {'decline' | 'cancel'} action - Decline as the payer or cancel as the requester.
+     * @param {Number} requestId - Id of the request.
+     */
+    const resolvePaymentRequest = async (action: 'decline' | 'cancel', requestId: Number): Promise<void> => {
+        const response = await fetch(`${apiUrl}/request/${action}`, {
+            body: JSON.stringify({ request_id: requestId }),
             method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+    };
+
+    /**
+     * Splits a bill paid by the user logged in, requesting a share from each participant.
+     * @param {NewBillQuery} query - Total, participants and what the bill is for.
+     * @returns {Bill} Created bill
+     */
+    const newBill = async (query: NewBillQuery): Promise<Bill> => {
+        const response = await fetch(`${apiUrl}/bill/new`, {
             body: JSON.stringify(query),
+            method: 'POST',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Fetches bills created by the user logged in.
+     * @returns {Bill[]} Bills, newest first
+     */
+    const getBills = async (): Promise<Bill[]> => {
+        const response = await fetch(`${apiUrl}/bill/list`, {
+            method: 'GET',
+            headers,
+        });
+        if (response.status != 200) {
+            throw new Error(await response.text());
+        }
+        return await response.json();
+    };
+
+    /**
+     * Fetches a bill created by the user logged in.
+     * @param {Number} billId - Id of the bill.
+     * @returns {Bill} Bill and the state of each share
+     */
+    const getBill = async (billId: Number): Promise<Bill> => {
+        const response = await fetch(`${apiUrl}/bill/${billId}`, {
+            method: 'GET',
             headers,
         });
         if (response.status != 200) {
             throw new Error(await response.text());
         }
+        return await response.json();
     };
 
     return {
-        login, logout, newUser, getUserInfo, update, getItems, newItem, newAttachment, buyItem, adminGive,
-        adminPromote, validate, transfer
+        login, logout, newUser, getUserInfo, update, getItems, getItem, newItem, editItem, changeStock, archiveItem,
+        deleteItem, newAttachment, buyItem, getItemsByBarcode, scanToBuy, getProduct, getCart, addToCart, removeFromCart,
+        checkout, adminGive, adminGrantRole, adminNewCategory, adminDeleteCategory, adminImportProducts, adminGetProductImport, validate,
+        transfer, refund, cancelPurchase, requestPayment, getPaymentRequests, acceptPaymentRequest, resolvePaymentRequest,
+        newBill, getBills, getBill, adminSetCreditLimit, adminGetDebtors
     };
 })();
 
@@ -0,0 +1,5 @@
+/*
+Deleted accounts are anonymised instead of being removed, so that items, attachments
+and transactions referring to them stay intact. `deleted_at` marks such accounts.
+*/
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,13 @@
+CREATE TABLE sessions (
+    id SERIAL PRIMARY KEY,
+    key_hash VARCHAR NOT NULL UNIQUE,
+    state VARCHAR NOT NULL,
+    user_id INTEGER REFERENCES users(id),
+    user_agent VARCHAR,
+    ip_address VARCHAR,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,13 @@
+/*
+Failed login attempts, counted separately per username and per ip address. `kind` tells
+which one `subject` is.
+*/
+CREATE TABLE login_throttles (
+    id SERIAL PRIMARY KEY,
+    kind VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    failures INTEGER DEFAULT 0 NOT NULL,
+    last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    blocked_until TIMESTAMP WITH TIME ZONE,
+    UNIQUE (kind, subject)
+);
@@ -0,0 +1,9 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_admin BOOLEAN DEFAULT false NOT NULL;
+
+UPDATE users u
+SET is_admin = true
+FROM user_roles ur, roles r
+WHERE ur.user_id = u.id AND ur.role_id = r.id AND r.name = 'admin';
+
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS roles;
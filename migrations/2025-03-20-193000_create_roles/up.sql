@@ -0,0 +1,29 @@
+CREATE TABLE roles (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE,
+    description VARCHAR NOT NULL
+);
+
+INSERT INTO roles (name, description) VALUES
+    ('admin', 'Manages roles of other users, and has every permission'),
+    ('treasurer', 'Adjusts balances and reads reports'),
+    ('moderator', 'Takes down items'),
+    ('kiosk_manager', 'Manages stock');
+
+CREATE TABLE user_roles (
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    role_id INTEGER NOT NULL REFERENCES roles(id),
+    granted_by INTEGER REFERENCES users(id),
+    granted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, role_id)
+);
+
+/*
+Transfer admin status from the old `is_admin` flag *before* dropping it
+*/
+INSERT INTO user_roles (user_id, role_id, granted_at)
+SELECT u.id, r.id, NOW()
+FROM users u, roles r
+WHERE u.is_admin AND r.name = 'admin';
+
+ALTER TABLE users DROP COLUMN is_admin;
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS transactions_receiver_id_idx;
+DROP INDEX IF EXISTS transactions_payer_id_idx;
+ALTER TABLE transactions DROP COLUMN IF EXISTS kind;
@@ -0,0 +1,17 @@
+/*
+Kind tells what a transaction was made for, so that history can be filtered
+by it. Rows made before this are purchases if they refer to an item, and
+transfers otherwise.
+*/
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS kind VARCHAR;
+
+UPDATE transactions
+SET kind = CASE WHEN item_id IS NULL THEN 'transfer' ELSE 'purchase' END;
+
+ALTER TABLE transactions ALTER COLUMN kind SET NOT NULL;
+
+/*
+History is queried per user, newest first
+*/
+CREATE INDEX IF NOT EXISTS transactions_payer_id_idx ON transactions (payer_id, id);
+CREATE INDEX IF NOT EXISTS transactions_receiver_id_idx ON transactions (receiver_id, id);
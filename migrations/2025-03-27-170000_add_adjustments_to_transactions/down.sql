@@ -0,0 +1,11 @@
+/* Withdrawals can't be dropped from the ledger without breaking balances */
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM transactions WHERE receiver_id IS NULL) THEN
+        RAISE EXCEPTION 'Can''t roll back while cash withdrawals exist';
+    END IF;
+END $$;
+ALTER TABLE transactions ALTER COLUMN receiver_id SET NOT NULL;
+ALTER TABLE transactions DROP COLUMN IF EXISTS reason;
+ALTER TABLE transactions DROP COLUMN IF EXISTS adjustment_kind;
+ALTER TABLE transactions DROP COLUMN IF EXISTS actor_id;
@@ -0,0 +1,14 @@
+/*
+Admin adjustments move money between a user and cash outside the system.
+The outside side of such a transaction is left empty: a deposit has no payer
+and a withdrawal has no receiver.
+*/
+ALTER TABLE transactions ALTER COLUMN receiver_id DROP NOT NULL;
+
+/*
+Adjustments record the admin who made them, what kind of adjustment it was
+and why it was made
+*/
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS actor_id INTEGER REFERENCES users(id);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS adjustment_kind VARCHAR;
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS reason VARCHAR;
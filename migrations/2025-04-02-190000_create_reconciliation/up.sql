@@ -0,0 +1,30 @@
+/*
+Results of comparing user balances against the transaction ledger. Runs made
+by cron have no `triggered_by`.
+*/
+CREATE TABLE reconciliation_runs (
+    id SERIAL PRIMARY KEY,
+    ran_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    triggered_by INTEGER REFERENCES users(id),
+    money_supply_cents BIGINT NOT NULL,
+    ledger_supply_cents BIGINT NOT NULL,
+    discrepant_users INTEGER NOT NULL,
+    /* Change of money supply since the previous run, NULL on the first run */
+    money_supply_drift_cents BIGINT
+);
+
+/*
+A row is stored for every user whose balance doesn't match the ledger, and
+for users whose discrepancy got resolved since the previous run
+*/
+CREATE TABLE reconciliation_discrepancies (
+    id SERIAL PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES reconciliation_runs(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    balance_cents BIGINT NOT NULL,
+    ledger_cents BIGINT NOT NULL,
+    discrepancy_cents BIGINT NOT NULL,
+    drift_cents BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS reconciliation_discrepancies_run_id_idx ON reconciliation_discrepancies (run_id);
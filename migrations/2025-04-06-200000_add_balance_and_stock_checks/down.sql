@@ -0,0 +1,3 @@
+ALTER TABLE transactions DROP CONSTRAINT IF EXISTS transactions_amount_cents_check;
+ALTER TABLE items DROP CONSTRAINT IF EXISTS items_amount_check;
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_balance_cents_check;
@@ -0,0 +1,8 @@
+/*
+Last line of defence against overselling and overspending, should a check
+in the backend ever be raced past. Existing rows are not validated, so that
+the migration can't fail on data written before these existed.
+*/
+ALTER TABLE users ADD CONSTRAINT users_balance_cents_check CHECK (balance_cents >= 0) NOT VALID;
+ALTER TABLE items ADD CONSTRAINT items_amount_check CHECK (amount >= 0) NOT VALID;
+ALTER TABLE transactions ADD CONSTRAINT transactions_amount_cents_check CHECK (amount_cents >= 0) NOT VALID;
@@ -0,0 +1,17 @@
+/*
+Keys sent by clients with money moving requests, along with the result of
+the first request made with each. Response columns are NULL while the first
+request is still being handled.
+*/
+CREATE TABLE idempotency_keys (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    key VARCHAR NOT NULL,
+    request_hash VARCHAR NOT NULL,
+    response_status INTEGER,
+    response_content_type VARCHAR,
+    response_body BYTEA,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    UNIQUE (user_id, key)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE items DROP COLUMN archived_at;
+ALTER TABLE items DROP COLUMN version;
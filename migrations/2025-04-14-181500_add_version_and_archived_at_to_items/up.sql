@@ -0,0 +1,7 @@
+/*
+Version is bumped on every edit, so that edits made from a stale copy of an
+item can be refused. Archived items are hidden from listings and can't be
+bought.
+*/
+ALTER TABLE items ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE items ADD COLUMN archived_at TIMESTAMP WITH TIME ZONE;
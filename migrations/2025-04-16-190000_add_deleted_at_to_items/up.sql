@@ -0,0 +1,5 @@
+/*
+Deleted items are kept, so that transactions referring to them can still be
+resolved. Deleting an item also archives it.
+*/
+ALTER TABLE items ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS item_tags;
+ALTER TABLE items DROP COLUMN category_id;
+DROP TABLE IF EXISTS categories;
@@ -0,0 +1,16 @@
+/* Categories are managed by admins, and items belong to at most one */
+CREATE TABLE categories (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+ALTER TABLE items ADD COLUMN category_id INTEGER REFERENCES categories(id) ON DELETE SET NULL;
+CREATE INDEX items_category_id_idx ON items (category_id);
+
+/* Free-form tags given to items by their sellers, stored in lowercase */
+CREATE TABLE item_tags (
+    item_id INTEGER NOT NULL REFERENCES items(id),
+    tag VARCHAR NOT NULL,
+    PRIMARY KEY (item_id, tag)
+);
+CREATE INDEX item_tags_tag_idx ON item_tags (tag);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS items_title_trgm_idx;
+ALTER TABLE items DROP COLUMN search_vector;
@@ -0,0 +1,16 @@
+/*
+Full text search over titles and descriptions in both Finnish and English,
+with titles weighted above descriptions. The column is generated, so it is
+left out of the diesel schema and only used through raw SQL.
+*/
+ALTER TABLE items ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('finnish'::regconfig, title), 'A') ||
+    setweight(to_tsvector('english'::regconfig, title), 'A') ||
+    setweight(to_tsvector('finnish'::regconfig, description), 'B') ||
+    setweight(to_tsvector('english'::regconfig, description), 'B')
+) STORED;
+CREATE INDEX items_search_vector_idx ON items USING GIN (search_vector);
+
+/* Trigram index on titles, used for fuzzy search when full text search finds nothing */
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX items_title_trgm_idx ON items USING GIN (title gin_trgm_ops);
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS items_units_sold_id_idx;
+DROP INDEX IF EXISTS items_created_at_id_idx;
+DROP INDEX IF EXISTS items_price_cents_id_idx;
+ALTER TABLE items DROP COLUMN last_sold_at;
+ALTER TABLE items DROP COLUMN units_sold;
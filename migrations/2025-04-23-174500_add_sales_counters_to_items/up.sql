@@ -0,0 +1,19 @@
+/*
+Sales counters of items, kept up to date on every purchase so that items can
+be sorted by popularity without summing up transactions.
+*/
+ALTER TABLE items ADD COLUMN units_sold INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE items ADD COLUMN last_sold_at TIMESTAMP WITH TIME ZONE;
+UPDATE items SET units_sold = sales.units_sold, last_sold_at = sales.last_sold_at
+FROM (
+    SELECT item_id, SUM(item_amount) AS units_sold, MAX(transacted_at) AS last_sold_at
+    FROM transactions
+    WHERE kind = 'purchase' AND item_id IS NOT NULL
+    GROUP BY item_id
+) AS sales
+WHERE items.id = sales.item_id;
+
+/* Indexes for paging through items in each sort order */
+CREATE INDEX items_price_cents_id_idx ON items (price_cents, id);
+CREATE INDEX items_created_at_id_idx ON items (created_at, id);
+CREATE INDEX items_units_sold_id_idx ON items (units_sold, id);
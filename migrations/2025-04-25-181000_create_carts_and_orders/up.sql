@@ -0,0 +1,24 @@
+/*
+Items users have put in their carts. Each line reserves its amount of the
+item from other buyers until `reserved_until`.
+*/
+CREATE TABLE cart_items (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    item_id INTEGER NOT NULL REFERENCES items(id),
+    amount INTEGER NOT NULL CHECK (amount > 0),
+    added_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    reserved_until TIMESTAMP WITH TIME ZONE NOT NULL,
+    UNIQUE (user_id, item_id)
+);
+CREATE INDEX cart_items_item_id_idx ON cart_items (item_id, reserved_until);
+
+/* Checkouts of carts, grouping the transactions of every bought line */
+CREATE TABLE orders (
+    id SERIAL PRIMARY KEY,
+    buyer_id INTEGER NOT NULL REFERENCES users(id),
+    total_cents INTEGER NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+ALTER TABLE transactions ADD COLUMN order_id INTEGER REFERENCES orders(id);
+CREATE INDEX transactions_order_id_idx ON transactions (order_id);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS items_barcode_idx;
+ALTER TABLE items DROP COLUMN barcode;
@@ -0,0 +1,6 @@
+/*
+Barcode of the good being sold, normalized to EAN-13. Many sellers can list
+the same good, so barcodes are not unique.
+*/
+ALTER TABLE items ADD COLUMN barcode VARCHAR(13);
+CREATE INDEX items_barcode_idx ON items (barcode) WHERE barcode IS NOT NULL;
@@ -0,0 +1,11 @@
+/*
+Product catalogue imported from files such as Open Food Facts dumps, used to
+prefill listings of barcoded goods
+*/
+CREATE TABLE products (
+    barcode VARCHAR(13) PRIMARY KEY,
+    title VARCHAR NOT NULL,
+    description VARCHAR NOT NULL,
+    image_url VARCHAR,
+    imported_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
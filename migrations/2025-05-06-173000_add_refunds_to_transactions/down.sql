@@ -0,0 +1,8 @@
+/* Refunds can't be dropped from the ledger without breaking balances */
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM transactions WHERE reverses_id IS NOT NULL) THEN
+        RAISE EXCEPTION 'Can''t roll back while refunds or cancellations exist';
+    END IF;
+END $$;
+ALTER TABLE transactions DROP COLUMN reverses_id;
@@ -0,0 +1,6 @@
+/*
+Refunds reverse a part or the whole of an earlier purchase, which they
+reference
+*/
+ALTER TABLE transactions ADD COLUMN reverses_id INTEGER REFERENCES transactions(id);
+CREATE INDEX transactions_reverses_id_idx ON transactions (reverses_id);
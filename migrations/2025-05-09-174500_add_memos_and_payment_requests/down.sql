@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS payment_requests;
+ALTER TABLE transactions DROP COLUMN memo;
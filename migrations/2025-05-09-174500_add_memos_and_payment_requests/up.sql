@@ -0,0 +1,20 @@
+/* What a transfer was made for */
+ALTER TABLE transactions ADD COLUMN memo VARCHAR;
+
+/*
+Users asking other users for money. `transaction_id` refers to the transfer
+made when the request was accepted.
+*/
+CREATE TABLE payment_requests (
+    id SERIAL PRIMARY KEY,
+    requester_id INTEGER NOT NULL REFERENCES users(id),
+    payer_id INTEGER NOT NULL REFERENCES users(id),
+    amount_cents INTEGER NOT NULL CHECK (amount_cents > 0),
+    memo VARCHAR,
+    status VARCHAR NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    transaction_id INTEGER REFERENCES transactions(id)
+);
+CREATE INDEX payment_requests_payer_id_idx ON payment_requests (payer_id, status);
+CREATE INDEX payment_requests_requester_id_idx ON payment_requests (requester_id, status);
@@ -0,0 +1,2 @@
+ALTER TABLE payment_requests DROP COLUMN bill_id;
+DROP TABLE IF EXISTS bills;
@@ -0,0 +1,15 @@
+/*
+Bills split between users. Each participant's share is a payment request
+referring to the bill.
+*/
+CREATE TABLE bills (
+    id SERIAL PRIMARY KEY,
+    creator_id INTEGER NOT NULL REFERENCES users(id),
+    total_cents INTEGER NOT NULL CHECK (total_cents > 0),
+    memo VARCHAR,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX bills_creator_id_idx ON bills (creator_id);
+
+ALTER TABLE payment_requests ADD COLUMN bill_id INTEGER REFERENCES bills(id);
+CREATE INDEX payment_requests_bill_id_idx ON payment_requests (bill_id);
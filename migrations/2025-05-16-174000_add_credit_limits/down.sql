@@ -0,0 +1,3 @@
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_balance_cents_check;
+ALTER TABLE users DROP COLUMN credit_limit_cents;
+ALTER TABLE users ADD CONSTRAINT users_balance_cents_check CHECK (balance_cents >= 0) NOT VALID;
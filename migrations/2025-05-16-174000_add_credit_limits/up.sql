@@ -0,0 +1,7 @@
+/*
+How far below zero a user's balance may go. Replaces the check keeping
+balances non-negative.
+*/
+ALTER TABLE users ADD COLUMN credit_limit_cents INTEGER NOT NULL DEFAULT 0 CHECK (credit_limit_cents >= 0);
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_balance_cents_check;
+ALTER TABLE users ADD CONSTRAINT users_balance_cents_check CHECK (balance_cents >= -credit_limit_cents) NOT VALID;
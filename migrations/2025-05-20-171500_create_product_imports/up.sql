@@ -0,0 +1,15 @@
+/*
+Catalogue imports run in the background, so their progress and outcome are
+recorded here. `finished_at` stays empty while an import is running.
+*/
+CREATE TABLE product_imports (
+    id SERIAL PRIMARY KEY,
+    file_name VARCHAR NOT NULL,
+    started_by INTEGER NOT NULL REFERENCES users(id),
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    imported INTEGER NOT NULL DEFAULT 0,
+    skipped INTEGER NOT NULL DEFAULT 0,
+    /* Why the import stopped early, if it did */
+    failure VARCHAR
+);
@@ -0,0 +1,5 @@
+/*
+Catalogue images were links to other sites, which new items can't use since
+they only take uploaded attachments. Sellers upload their own pictures instead.
+*/
+ALTER TABLE products DROP COLUMN image_url;
@@ -3,10 +3,18 @@ use actix_web::{get, web, Error, HttpResponse};
 
 pub mod user;
 pub mod item;
+pub mod cart;
+pub mod product;
 pub mod attachment;
 pub mod transactions;
+pub mod payment_request;
+pub mod bill;
 pub mod admin;
 pub mod validation;
+pub mod login_throttle;
+pub mod idempotency;
+pub mod roles;
+pub mod reconciliation;
 
 #[get("/hello")]
 pub async fn hello_world() -> Result<HttpResponse, Error> {
@@ -20,16 +28,56 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .service(hello_world)
             .service(user::login)
             .service(user::logout)
+            .service(user::logout_everywhere)
+            .service(user::list_sessions)
+            .service(user::revoke_session)
             .service(user::user_info)
             .service(user::new_user)
+            .service(user::change_password)
+            .service(user::delete_user)
             .service(admin::clear_db)
             .service(admin::give_balance)
-            .service(admin::promote)
+            .service(admin::set_credit_limit)
+            .service(admin::get_debtors)
+            .service(admin::grant_role)
+            .service(admin::revoke_role)
+            .service(admin::new_category)
+            .service(admin::delete_category)
+            .service(admin::import_products)
+            .service(admin::get_product_import)
+            .service(admin::get_user_transactions)
+            .service(admin::run_reconciliation)
+            .service(admin::list_reconciliations)
+            .service(admin::get_reconciliation)
+            .service(admin::revoke_sessions)
+            .service(admin::unlock_login)
             .service(item::get_items)
             .service(item::new_item)
             .service(item::buy_item)
+            .service(item::scan_to_buy)
+            .service(item::get_items_by_barcode)
+            .service(item::get_item)
+            .service(item::edit_item)
+            .service(item::change_stock)
+            .service(item::archive_item)
+            .service(item::delete_item)
+            .service(product::get_product)
+            .service(cart::get_cart)
+            .service(cart::add_to_cart)
+            .service(cart::remove_from_cart)
+            .service(cart::checkout)
             .service(transactions::get_transactions)
             .service(transactions::transfer)
+            .service(transactions::refund_purchase)
+            .service(transactions::cancel_purchase)
+            .service(payment_request::new_payment_request)
+            .service(payment_request::list_payment_requests)
+            .service(payment_request::accept_payment_request)
+            .service(payment_request::decline_payment_request)
+            .service(payment_request::cancel_payment_request)
+            .service(bill::new_bill)
+            .service(bill::list_bills)
+            .service(bill::get_bill)
             .service(validation::validate_username)
             .service(validation::validate_currency)
             .service(validation::validate_password)
@@ -45,7 +93,7 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 #[cfg(test)]
 mod tests {
     use reqwest::Result;
-    const URL: &str = "http://backend:3030";
+    const URL: &str = "http://localhost:3030";
 
     // Test if api is available
     #[test]
@@ -1,78 +1,82 @@
-use actix_session::Session;
 use actix_web::post;
-use actix_web::{error, get, web, Error, HttpResponse};
+use actix_web::{error, get, web, Error, HttpRequest, HttpResponse};
 use diesel::prelude::*;
 use diesel::ExpressionMethods;
+use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
 use futures::try_join;
 use serde::Deserialize;
 use serde::Serialize;
 
-use crate::api::user::get_login_uid;
-use crate::models::User;
+use crate::api::idempotency;
+use crate::api::login_throttle;
+use crate::api::product;
+use crate::api::reconciliation::{self, ReconciliationResult};
+use crate::api::transactions::{load_history, AdjustmentKind, HistoryQuery, TransactionKind};
+use crate::api::roles::{Admin, Authorized, Role, Treasurer};
+use crate::models::{Category, ProductImport, ReconciliationDiscrepancy, ReconciliationRun, User};
+use crate::settings::dev_mode;
 use crate::BB8Pool;
 
-/// Returns Ok(true) if session user is admin, Ok(false) if not
-pub async fn session_is_admin(session: &Session, pool: web::Data<BB8Pool>) -> Result<bool, Error> {
-    use crate::schema::users::dsl::*;
-
-    // Allow any user to act as admin if running a debug build
-    if cfg!(debug_assertions) {
-        return Ok(true);
-    }
-
-    let uid = if let Ok(Some(uid)) = get_login_uid(session) {
-        uid
-    } else {
-        return Err(error::ErrorBadRequest("Not logged in"));
-    };
-
-    // Aquire connection to db
-    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
-
-    let result = users
-        .filter(id.eq(uid))
-        .select(User::as_select())
-        .load(&mut con)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
-    if let [user] = &result[..] {
-        Ok(user.is_admin)
-    } else {
-        Err(error::ErrorBadRequest("User not found"))
-    }
-}
-
-/// Clears the whole database. This endpoint is only accessible in debug builds.
+/// Clears the whole database. This endpoint is only accessible in development mode.
 #[get("/admin/db/clear")]
-pub async fn clear_db(pool: web::Data<BB8Pool>, session: Session) -> Result<HttpResponse, Error> {
+pub async fn clear_db(pool: web::Data<BB8Pool>) -> Result<HttpResponse, Error> {
     use crate::schema::attachments::dsl::*;
+    use crate::schema::bills::dsl::*;
+    use crate::schema::cart_items::dsl::*;
+    use crate::schema::categories::dsl::*;
+    use crate::schema::idempotency_keys::dsl::*;
+    use crate::schema::item_tags::dsl::*;
     use crate::schema::items::dsl::*;
+    use crate::schema::login_throttles::dsl::*;
+    use crate::schema::orders::dsl::*;
+    use crate::schema::payment_requests::dsl::*;
+    use crate::schema::product_imports::dsl::*;
+    use crate::schema::products::dsl::*;
+    use crate::schema::reconciliation_runs::dsl::*;
+    use crate::schema::sessions::dsl::*;
     use crate::schema::transactions::dsl::*;
+    use crate::schema::user_roles::dsl::*;
     use crate::schema::users::dsl::*;
 
-    // Require admin privileges
-    if !session_is_admin(&session, pool.clone()).await? {
-        return Err(error::ErrorForbidden("Insufficent privileges"));
+    if !dev_mode() {
+        return Err(error::ErrorNotFound("Feature only available in development mode"));
     }
 
     // Aquire db connection handle
     let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
 
     // Remove everything ( in correct order! )
+    diesel::delete(payment_requests)
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
     try_join!(
         diesel::delete(attachments).execute(&mut con),
-        diesel::delete(transactions).execute(&mut con)
+        diesel::delete(bills).execute(&mut con),
+        diesel::delete(item_tags).execute(&mut con),
+        diesel::delete(cart_items).execute(&mut con),
+        diesel::delete(products).execute(&mut con),
+        diesel::delete(product_imports).execute(&mut con),
+        diesel::delete(transactions).execute(&mut con),
+        diesel::delete(sessions).execute(&mut con),
+        diesel::delete(login_throttles).execute(&mut con),
+        diesel::delete(idempotency_keys).execute(&mut con),
+        diesel::delete(user_roles).execute(&mut con),
+        diesel::delete(reconciliation_runs).execute(&mut con) // Cascades to discrepancies
+    )
+    .map_err(error::ErrorInternalServerError)?;
+    try_join!(
+        diesel::delete(items).execute(&mut con),
+        diesel::delete(orders).execute(&mut con)
+    )
+    .map_err(error::ErrorInternalServerError)?;
+    try_join!(
+        diesel::delete(users).execute(&mut con),
+        diesel::delete(categories).execute(&mut con)
     )
     .map_err(error::ErrorInternalServerError)?;
-    diesel::delete(items)
-        .execute(&mut con)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
-    diesel::delete(users)
-        .execute(&mut con)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
 
     Ok(HttpResponse::Ok().body("OK"))
 }
@@ -81,84 +85,588 @@ pub async fn clear_db(pool: web::Data<BB8Pool>, session: Session) -> Result<Http
 pub struct AdminGiveQuery {
     pub user_id: Option<i32>,
     pub amount_cents: i32,
+    pub kind: AdjustmentKind,
+    pub reason: String,
 }
 
 /// Appends given amount of cents to users balance. If the user is not
 /// specified, currently logged in user is used. Note that this endpoint
 /// can also be used to reduct balance by providing negative values.
+/// Every adjustment is logged as a transaction along with the acting
+/// admin and the reason given. Repeats of a request sent with the same
+/// `Idempotency-Key` header are only carried out once. Requires treasurer
+/// role.
 #[post("/admin/give")]
 pub async fn give_balance(
     pool: web::Data<BB8Pool>,
     query: web::Json<AdminGiveQuery>,
-    session: Session,
+    auth: Authorized<Treasurer>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
-    use crate::schema::users::dsl::*;
+    let actor_id = auth.user_id;
+    idempotency::run(&pool, &req, actor_id, &*query, |con| {
+        give(con, &query, actor_id).scope_boxed_local()
+    })
+    .await
+}
+
+async fn give(
+    con: &mut AsyncPgConnection,
+    query: &AdminGiveQuery,
+    actor_id: i32,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{transactions, users};
+
+    // Gather and validate input
+    let uid = query.user_id.unwrap_or(actor_id); // Use currently logged in users id if no query was provided
+    let amount = query.amount_cents;
+    if amount == 0 {
+        return Err(error::ErrorBadRequest("Amount can't be zero"));
+    }
+    let adjustment_kind = query.kind;
+    match adjustment_kind {
+        AdjustmentKind::CashDeposit if amount < 0 => {
+            return Err(error::ErrorBadRequest("Cash deposits must be positive"));
+        }
+        AdjustmentKind::CashWithdrawal if amount > 0 => {
+            return Err(error::ErrorBadRequest("Cash withdrawals must be negative"));
+        }
+        _ => (),
+    }
+    let reason = query.reason.trim().to_string();
+    if reason.is_empty() {
+        return Err(error::ErrorBadRequest("Reason is required"));
+    }
+
+    let result: Result<Result<(), &str>, diesel::result::Error> = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                let user = users::table
+                    .filter(users::columns::id.eq(uid))
+                    .filter(users::columns::deleted_at.is_null())
+                    .select(User::as_select())
+                    .for_update()
+                    .get_result(con)
+                    .await
+                    .optional()?;
+                let Some(user) = user else {
+                    return Ok(Err("User not found"));
+                };
+                // Credit is only for buying and transfers, not for paying out cash
+                let new_balance = i64::from(user.balance_cents) + i64::from(amount);
+                if amount < 0 && new_balance < 0 {
+                    return Ok(Err("Insufficient funds"));
+                }
+                if new_balance > i64::from(i32::MAX) {
+                    return Ok(Err("Balance would get too large"));
+                }
+
+                // Money comes from or goes to cash outside the system, which
+                // is left out of the transaction
+                let (payer, receiver) = if amount > 0 {
+                    (None, Some(uid))
+                } else {
+                    (Some(uid), None)
+                };
+                try_join!(
+                    diesel::update(users::table)
+                        .filter(users::columns::id.eq(uid))
+                        .set(users::columns::balance_cents.eq(users::columns::balance_cents + amount))
+                        .execute(con),
+                    diesel::insert_into(transactions::table)
+                        .values((
+                            transactions::columns::payer_id.eq(payer),
+                            transactions::columns::receiver_id.eq(receiver),
+                            transactions::columns::amount_cents.eq(amount.abs()),
+                            transactions::columns::transacted_at.eq(chrono::offset::Utc::now()),
+                            transactions::columns::kind.eq(TransactionKind::Adjustment.name()),
+                            transactions::columns::actor_id.eq(actor_id),
+                            transactions::columns::adjustment_kind.eq(adjustment_kind.name()),
+                            transactions::columns::reason.eq(reason),
+                        ))
+                        .execute(con),
+                )?;
+
+                Ok(Ok(()))
+            })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminCreditLimitQuery {
+    pub user_id: i32,
+    pub credit_limit_cents: i32,
+}
+
+/// Sets how far below zero given users balance may go. The limit can't be
+/// lowered below what the user already owes. Requires treasurer role.
+#[post("/admin/credit_limit")]
+pub async fn set_credit_limit(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<AdminCreditLimitQuery>,
+    _auth: Authorized<Treasurer>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::users;
+
+    let limit = query.credit_limit_cents;
+    if limit < 0 {
+        return Err(error::ErrorBadRequest("Credit limit can't be negative"));
+    }
 
     // Aquire db connection handle
     let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
 
-    // Require admin privileges
-    if !session_is_admin(&session, pool.clone()).await? {
-        return Err(error::ErrorForbidden("Insufficent privileges"));
+    let user = users::table
+        .filter(users::columns::id.eq(query.user_id))
+        .filter(users::columns::deleted_at.is_null());
+    let updated = diesel::update(user)
+        .filter(users::columns::balance_cents.ge(-limit))
+        .set(users::columns::credit_limit_cents.eq(limit))
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if updated == 0 {
+        let exists = user
+            .select(users::columns::id)
+            .get_result::<i32>(&mut con)
+            .await
+            .optional()
+            .map_err(error::ErrorInternalServerError)?
+            .is_some();
+        return Err(error::ErrorBadRequest(if exists {
+            "User owes more than the new credit limit"
+        } else {
+            "User not found"
+        }));
     }
 
-    let uid: i32 = match query.user_id {
-        Some(user_id) => {
-            // Validate provided user id
-            let result = users
-                .filter(id.eq(user_id))
-                .select(User::as_select())
-                .load(&mut con)
-                .await
-                .map_err(error::ErrorInternalServerError)?;
-            if let [_] = &result[..] {
-                user_id
-            } else {
-                return Err(error::ErrorBadRequest("User not found"));
-            }
-        }
-        None => {
-            // Get currently logged in users id if no query was provided
-            get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?
-        }
-    };
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DebtorsReport {
+    /// Users with a negative balance, deepest in debt first
+    pub debtors: Vec<User>,
+    pub total_debt_cents: i64,
+}
+
+/// Lists users whose balance is below zero. Requires treasurer role.
+#[get("/admin/debtors")]
+pub async fn get_debtors(
+    pool: web::Data<BB8Pool>,
+    _auth: Authorized<Treasurer>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::users;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let debtors = users::table
+        .filter(users::columns::balance_cents.lt(0))
+        .order((users::columns::balance_cents, users::columns::id))
+        .select(User::as_select())
+        .load(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let total_debt_cents = debtors
+        .iter()
+        .map(|debtor| -(debtor.balance_cents as i64))
+        .sum();
+    Ok(HttpResponse::Ok().json(DebtorsReport {
+        debtors,
+        total_debt_cents,
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewCategoryQuery {
+    pub name: String,
+}
+
+/// Creates a new item category. Requires admin role.
+#[post("/admin/category/new")]
+pub async fn new_category(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<NewCategoryQuery>,
+    _auth: Authorized<Admin>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::categories;
+
+    const MAX_NAME_LENGTH: usize = 50;
+
+    let category_name = query.name.trim();
+    if category_name.is_empty() || category_name.len() > MAX_NAME_LENGTH {
+        return Err(error::ErrorBadRequest(format!(
+            "Name must be at least 1 and at most {MAX_NAME_LENGTH} characters long"
+        )));
+    }
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let category = diesel::insert_into(categories::table)
+        .values((
+            categories::columns::name.eq(category_name),
+            categories::columns::created_at.eq(chrono::offset::Utc::now()),
+        ))
+        .on_conflict_do_nothing()
+        .returning(Category::as_returning())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("Category already exists"))?;
+
+    Ok(HttpResponse::Ok().json(category))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteCategoryQuery {
+    pub category_id: i32,
+}
+
+/// Deletes an item category. Items in the category are left without one.
+/// Requires admin role.
+#[post("/admin/category/delete")]
+pub async fn delete_category(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<DeleteCategoryQuery>,
+    _auth: Authorized<Admin>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::categories;
 
-    diesel::update(users)
-        .filter(id.eq(uid))
-        .set(balance_cents.eq(balance_cents + query.amount_cents))
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let deleted = diesel::delete(categories::table)
+        .filter(categories::columns::id.eq(query.category_id))
         .execute(&mut con)
         .await
         .map_err(error::ErrorInternalServerError)?;
+    if deleted == 0 {
+        return Err(error::ErrorBadRequest("Category not found"));
+    }
 
     Ok(HttpResponse::Ok().body("OK"))
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct AdminPromoteQuery {
+pub struct ImportProductsQuery {
+    /// Name of the catalogue file in the catalogue directory
+    pub file: String,
+}
+
+/// Starts importing a product catalogue file, such as an Open Food Facts
+/// dump, from the catalogue directory on the server. The import runs in the
+/// background, and its outcome can be followed with
+/// `/admin/products/import/status`. Requires admin role.
+#[post("/admin/products/import")]
+pub async fn import_products(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<ImportProductsQuery>,
+    auth: Authorized<Admin>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::product_imports::dsl::*;
+
+    let catalogue = product::open_catalogue(&query.file).await?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let import = diesel::insert_into(product_imports)
+        .values((
+            file_name.eq(&query.file),
+            started_by.eq(auth.user_id),
+            started_at.eq(chrono::offset::Utc::now()),
+        ))
+        .returning(ProductImport::as_returning())
+        .get_result(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    actix_web::rt::spawn(product::import_catalogue(
+        pool.get_ref().clone(),
+        import.id,
+        catalogue,
+    ));
+    Ok(HttpResponse::Accepted().json(import))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProductImportQuery {
+    pub import_id: i32,
+}
+
+/// Returns the progress or outcome of a catalogue import. Requires admin
+/// role.
+#[post("/admin/products/import/status")]
+pub async fn get_product_import(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<ProductImportQuery>,
+    _auth: Authorized<Admin>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::product_imports::dsl::*;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let import = product_imports
+        .filter(id.eq(query.import_id))
+        .select(ProductImport::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("Catalogue import not found"))?;
+    Ok(HttpResponse::Ok().json(import))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminRoleQuery {
     pub user_id: i32,
+    pub role: Role,
 }
 
-/// Promotes given user to admin status. Requires a session with admin
-/// level privileges.
-#[post("/admin/promote")]
-pub async fn promote(
+/// Grants a role to given user. Requires admin role.
+#[post("/admin/roles/grant")]
+pub async fn grant_role(
     pool: web::Data<BB8Pool>,
-    query: web::Json<AdminPromoteQuery>,
-    session: Session,
+    query: web::Json<AdminRoleQuery>,
+    auth: Authorized<Admin>,
 ) -> Result<HttpResponse, Error> {
-    use crate::schema::users::dsl::*;
+    use crate::schema::{roles, user_roles, users};
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    // Validate user id
+    let user = users::table
+        .filter(users::columns::id.eq(query.user_id))
+        .filter(users::columns::deleted_at.is_null())
+        .select(User::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?;
+    if user.is_none() {
+        return Err(error::ErrorBadRequest("User not found"));
+    }
+
+    let role_id: i32 = roles::table
+        .filter(roles::columns::name.eq(query.role.name()))
+        .select(roles::columns::id)
+        .get_result(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    diesel::insert_into(user_roles::table)
+        .values((
+            user_roles::columns::user_id.eq(query.user_id),
+            user_roles::columns::role_id.eq(role_id),
+            user_roles::columns::granted_by.eq(auth.user_id),
+            user_roles::columns::granted_at.eq(chrono::offset::Utc::now()),
+        ))
+        .on_conflict_do_nothing()
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+/// Revokes a role from given user. The last admin can't be demoted, so
+/// that roles can always be managed. Requires admin role.
+#[post("/admin/roles/revoke")]
+pub async fn revoke_role(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<AdminRoleQuery>,
+    _auth: Authorized<Admin>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{roles, user_roles};
+
+    let revoked_user_id = query.user_id;
+    let revoked_role = query.role;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let result: Result<Result<(), &str>, diesel::result::Error> = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                // Lock the role's rows, so that two admins can't demote each other at once
+                let holders: Vec<i32> = user_roles::table
+                    .inner_join(roles::table)
+                    .filter(roles::columns::name.eq(revoked_role.name()))
+                    .select(user_roles::columns::user_id)
+                    .for_update()
+                    .load(con)
+                    .await?;
+                if !holders.contains(&revoked_user_id) {
+                    return Ok(Err("User does not have the role"));
+                }
+                if revoked_role == Role::Admin && holders.len() == 1 {
+                    return Ok(Err("Can't demote the last admin"));
+                }
+
+                let role_id = roles::table
+                    .filter(roles::columns::name.eq(revoked_role.name()))
+                    .select(roles::columns::id);
+                diesel::delete(user_roles::table)
+                    .filter(user_roles::columns::user_id.eq(revoked_user_id))
+                    .filter(user_roles::columns::role_id.eq_any(role_id))
+                    .execute(con)
+                    .await?;
+
+                Ok(Ok(()))
+            })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminHistoryQuery {
+    pub user_id: i32,
+    #[serde(flatten)]
+    pub query: HistoryQuery,
+}
+
+/// Returns transaction history of any user. Takes the same filters and
+/// pagination as `/log`. Requires treasurer role.
+#[post("/admin/log")]
+pub async fn get_user_transactions(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<AdminHistoryQuery>,
+    _auth: Authorized<Treasurer>,
+) -> Result<HttpResponse, Error> {
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let query = query.into_inner();
+    let history = load_history(&mut con, query.user_id, query.query).await?;
+    Ok(HttpResponse::Ok().json(history))
+}
+
+/// Compares every user's balance to their transactions and stores the
+/// results. Requires treasurer role.
+#[post("/admin/reconciliation/run")]
+pub async fn run_reconciliation(
+    pool: web::Data<BB8Pool>,
+    auth: Authorized<Treasurer>,
+) -> Result<HttpResponse, Error> {
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
 
-    // Require admin privileges
-    if !session_is_admin(&session, pool.clone()).await? {
-        return Err(error::ErrorForbidden("Insufficent privileges"));
+    let result = reconciliation::reconcile(&mut con, Some(auth.user_id))
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminReconciliationListQuery {
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Lists past reconciliation runs, newest first. Requires treasurer role.
+#[post("/admin/reconciliation/list")]
+pub async fn list_reconciliations(
+    pool: web::Data<BB8Pool>,
+    query: Option<web::Json<AdminReconciliationListQuery>>,
+    _auth: Authorized<Treasurer>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::reconciliation_runs::dsl::*;
+
+    let (offset, limit) = match query {
+        Some(query) => (query.offset.unwrap_or(0), query.limit.unwrap_or(20)),
+        None => (0, 20),
+    };
+    if offset < 0 || !(1..=100).contains(&limit) {
+        return Err(error::ErrorBadRequest("Invalid offset or limit"));
     }
 
     // Aquire db connection handle
     let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
 
-    diesel::update(users)
-        .filter(id.eq(query.user_id))
-        .set(is_admin.eq(true))
+    let runs = reconciliation_runs
+        .order(id.desc())
+        .offset(offset)
+        .limit(limit)
+        .select(ReconciliationRun::as_select())
+        .load(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(runs))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminReconciliationQuery {
+    pub run_id: i32,
+}
+
+/// Returns a past reconciliation run along with its discrepancies. Requires
+/// treasurer role.
+#[post("/admin/reconciliation")]
+pub async fn get_reconciliation(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<AdminReconciliationQuery>,
+    _auth: Authorized<Treasurer>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{reconciliation_discrepancies, reconciliation_runs};
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let run = reconciliation_runs::table
+        .filter(reconciliation_runs::columns::id.eq(query.run_id))
+        .select(ReconciliationRun::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("Reconciliation run not found"))?;
+    let discrepancies = ReconciliationDiscrepancy::belonging_to(&run)
+        .order(reconciliation_discrepancies::columns::user_id)
+        .select(ReconciliationDiscrepancy::as_select())
+        .load(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(ReconciliationResult { run, discrepancies }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminRevokeSessionsQuery {
+    pub user_id: i32,
+}
+
+/// Logs given user out from every session. Requires admin role.
+#[post("/admin/sessions/revoke")]
+pub async fn revoke_sessions(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<AdminRevokeSessionsQuery>,
+    _auth: Authorized<Admin>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::sessions::dsl::*;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    diesel::delete(sessions)
+        .filter(user_id.eq(query.user_id))
         .execute(&mut con)
         .await
         .map_err(error::ErrorInternalServerError)?;
@@ -166,15 +674,53 @@ pub async fn promote(
     Ok(HttpResponse::Ok().body("OK"))
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct AdminUnlockLoginQuery {
+    pub username: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// Lifts login blocks caused by failed login attempts, for a username
+/// and/or an ip address. Requires admin role.
+#[post("/admin/login/unlock")]
+pub async fn unlock_login(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<AdminUnlockLoginQuery>,
+    _auth: Authorized<Admin>,
+) -> Result<HttpResponse, Error> {
+    if query.username.is_none() && query.ip_address.is_none() {
+        return Err(error::ErrorBadRequest("Provide a username or an ip address to unlock"));
+    }
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let cleared = login_throttle::unlock(
+        &mut con,
+        query.username.as_deref(),
+        query.ip_address.as_deref(),
+    )
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+    if cleared == 0 {
+        return Err(error::ErrorBadRequest("Nothing to unlock"));
+    }
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
 #[cfg(test)]
 mod tests {
     use reqwest::Result;
     use std::sync::Arc;
 
-    use crate::api::user::UserQuery;
+    use crate::api::item::{BuyQuery, NewItemQuery};
+    use crate::api::transactions::{HistoryResult, HistoryType, RefundQuery, TransferQuery};
+    use crate::api::user::{DeleteUserQuery, UserQuery, UserResult};
+    use crate::models::Item;
 
     use super::*;
-    const URL: &str = "http://backend:3030";
+    const URL: &str = "http://localhost:3030";
 
     // Test admin operations
     #[test]
@@ -194,7 +740,7 @@ mod tests {
         assert_eq!(
             result.status(),
             200,
-            "Could not clear db. Make sure the server is compiled in debug mode."
+            "Could not clear db. Make sure the server is running in development mode."
         );
 
         // Create a new user
@@ -204,29 +750,413 @@ mod tests {
             .send()?;
         assert_eq!(result.status(), 200, "Could not create a new user");
 
+        // Adjustments require a reason
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: 111,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: " ".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could give currency without a reason");
+
         // Admin give currency
         let result = client
             .post(format!("{URL}/api/admin/give"))
-            .json(&AdminGiveQuery{amount_cents: 111, user_id: None})
+            .json(&AdminGiveQuery {
+                amount_cents: 111,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "paid in cash".to_string(),
+            })
             .send()?;
         assert_eq!(result.status(), 200, "Could not give currency to user via admin give query");
 
+        // Balance can't be debited below zero
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: -112,
+                user_id: None,
+                kind: AdjustmentKind::Correction,
+                reason: "too much".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could debit balance below zero");
+
+        // Cash deposits and withdrawals must move money in their direction
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: -1,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "paid in cash".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could deposit a negative amount");
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: 1,
+                user_id: None,
+                kind: AdjustmentKind::CashWithdrawal,
+                reason: "paid out".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could withdraw a positive amount");
+
+        // Balance can't grow past what the database can store
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: i32::MAX,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "paid in cash".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could overflow the balance");
+
         // Validate that user has the correct amount of currency
         let result = client.post(format!("{URL}/api/user")).send()?;
         let user: User = result.json()?;
         assert_eq!(user.balance_cents, 111, "User didn't get given currency");
 
-        // Promote admin
+        // Validate that the adjustment was logged
+        let result = client.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        let [entry] = &history.entries[..] else {
+            panic!("Adjustment was not logged exactly once");
+        };
+        assert_eq!(entry.entry_type, HistoryType::AdminAdjustment);
+        assert_eq!(entry.transaction.actor_id, Some(user.id));
+        assert_eq!(entry.transaction.adjustment_kind.as_deref(), Some("cash_deposit"));
+        assert_eq!(entry.transaction.reason.as_deref(), Some("paid in cash"));
+        assert_eq!(entry.delta_cents, 111);
+
+        // Grant roles
+        for role in [Role::Admin, Role::Treasurer] {
+            let result = client
+                .post(format!("{URL}/api/admin/roles/grant"))
+                .json(&AdminRoleQuery { user_id: user.id, role })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not grant role to user");
+        }
+
+        // Validate that user has the roles
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: UserResult = result.json()?;
+        assert_eq!(user.roles, vec![Role::Admin, Role::Treasurer], "User didn't aquire roles");
+        let user = user.user;
+
+        // Demote user
         let result = client
-            .post(format!("{URL}/api/admin/promote"))
-            .json(&AdminPromoteQuery{user_id: user.id})
+            .post(format!("{URL}/api/admin/roles/revoke"))
+            .json(&AdminRoleQuery { user_id: user.id, role: Role::Treasurer })
             .send()?;
-        assert_eq!(result.status(), 200, "Could not promote user to admin status");
+        assert_eq!(result.status(), 200, "Could not revoke role from user");
+
+        // The last admin can't be demoted
+        let result = client
+            .post(format!("{URL}/api/admin/roles/revoke"))
+            .json(&AdminRoleQuery { user_id: user.id, role: Role::Admin })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could demote the last admin");
 
-        // Validate that user has the correct amount of currency
         let result = client.post(format!("{URL}/api/user")).send()?;
-        let user: User = result.json()?;
-        assert!(user.is_admin, "User didn't aquire admin status");
+        let user: UserResult = result.json()?;
+        assert_eq!(user.roles, vec![Role::Admin], "Role was not revoked");
+        let user = user.user;
+
+        // The last admin can't delete their account either
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: -111,
+                user_id: None,
+                kind: AdjustmentKind::CashWithdrawal,
+                reason: "closing account".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not withdraw currency");
+        let result = client
+            .post(format!("{URL}/api/user/delete"))
+            .json(&DeleteUserQuery { password: user_query.password.clone() })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could delete the last admin");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        assert_eq!(result.status(), 200, "Last admin was logged out");
+
+        // Revoke sessions of user
+        let result = client
+            .post(format!("{URL}/api/admin/sessions/revoke"))
+            .json(&AdminRevokeSessionsQuery { user_id: user.id })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not revoke sessions of user");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        assert_eq!(result.status(), 401, "User stayed logged in after revoking sessions");
+
+        Ok(())
+    }
+
+    // Test reconciliation runs and history
+    #[test]
+    fn reconciliation_operations() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Create a new user
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+
+        // Reconcile after a deposit
+        let give = |amount_cents| {
+            client
+                .post(format!("{URL}/api/admin/give"))
+                .json(&AdminGiveQuery {
+                    amount_cents,
+                    user_id: None,
+                    kind: AdjustmentKind::CashDeposit,
+                    reason: "test".to_string(),
+                })
+                .send()
+        };
+        assert_eq!(give(500)?.status(), 200, "Could not give currency");
+        let result = client
+            .post(format!("{URL}/api/admin/reconciliation/run"))
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not run reconciliation");
+        let first: ReconciliationResult = result.json()?;
+        assert_eq!(first.run.money_supply_cents, 500);
+        assert_eq!(first.run.ledger_supply_cents, 500);
+        assert_eq!(first.run.discrepant_users, 0);
+        assert!(first.discrepancies.is_empty(), "Ledger and balances don't match");
+        assert_eq!(first.run.money_supply_drift_cents, None);
+
+        // Drift is reported against the previous run
+        assert_eq!(give(100)?.status(), 200, "Could not give currency");
+        let result = client
+            .post(format!("{URL}/api/admin/reconciliation/run"))
+            .send()?;
+        let second: ReconciliationResult = result.json()?;
+        assert_eq!(second.run.money_supply_drift_cents, Some(100));
+
+        // History
+        let result = client
+            .post(format!("{URL}/api/admin/reconciliation/list"))
+            .send()?;
+        let runs: Vec<ReconciliationRun> = result.json()?;
+        assert_eq!(runs, vec![second.run, first.run], "Unexpected reconciliation history");
+        let result = client
+            .post(format!("{URL}/api/admin/reconciliation"))
+            .json(&AdminReconciliationQuery { run_id: runs[1].id })
+            .send()?;
+        let fetched: ReconciliationResult = result.json()?;
+        assert_eq!(fetched.run, runs[1]);
+
+        Ok(())
+    }
+
+    // Test spending on credit and listing debtors
+    #[test]
+    fn credit_limits() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users, the second of which sells an item
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        let result = client2
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "coffee".to_string(),
+                description: "test description".to_string(),
+                amount: 5,
+                price: "0.2".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: UserResult = result.json()?;
+        let set_limit = |credit_limit_cents| {
+            client
+                .post(format!("{URL}/api/admin/credit_limit"))
+                .json(&AdminCreditLimitQuery {
+                    user_id: user.user.id,
+                    credit_limit_cents,
+                })
+                .send()
+        };
+        let transfer = |amount_cents| {
+            client
+                .post(format!("{URL}/api/transfer"))
+                .json(&TransferQuery {
+                    amount_cents,
+                    recipient: "test2".to_string(),
+                    memo: None,
+                })
+                .send()
+        };
+        let buy = || {
+            client
+                .post(format!("{URL}/api/item/buy"))
+                .json(&BuyQuery {
+                    item_id: item.id,
+                    amount: None,
+                })
+                .send()
+        };
+
+        // Without a limit nothing can be spent
+        assert_eq!(transfer(1)?.status(), 400, "Spent without balance or credit");
+        assert_eq!(set_limit(-1)?.status(), 400, "Set a negative credit limit");
+
+        // Spend down to the limit, but not past it
+        assert_eq!(set_limit(100)?.status(), 200, "Could not set credit limit");
+        assert_eq!(transfer(90)?.status(), 200, "Could not transfer on credit");
+        assert_eq!(buy()?.status(), 400, "Bought past the credit limit");
+        assert_eq!(transfer(10)?.status(), 200, "Could not spend all credit");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user_info: UserResult = result.json()?;
+        assert_eq!(user_info.user.balance_cents, -100);
+        assert!(user_info.in_debt, "User in debt isn't flagged");
+
+        // The limit can't be lowered below the debt
+        assert_eq!(set_limit(50)?.status(), 400, "Limit was lowered below the debt");
+        let result = client.get(format!("{URL}/api/admin/debtors")).send()?;
+        let report: DebtorsReport = result.json()?;
+        assert_eq!(report.debtors.len(), 1);
+        assert_eq!(report.debtors[0].username, "test");
+        assert_eq!(report.total_debt_cents, 100);
+
+        // Paying back clears the debt, and spending continues on credit
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: 110,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "paid back".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not pay back debt");
+        assert_eq!(buy()?.status(), 200, "Could not buy on credit");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user_info: UserResult = result.json()?;
+        assert_eq!(user_info.user.balance_cents, -10);
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: -5,
+                user_id: None,
+                kind: AdjustmentKind::CashWithdrawal,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Cash was paid out on credit");
+        assert_eq!(set_limit(0)?.status(), 400, "Limit was removed from a debtor");
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: 10,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "paid back".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not pay back debt");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user_info: UserResult = result.json()?;
+        assert!(!user_info.in_debt, "User without debt is flagged");
+        let result = client.get(format!("{URL}/api/admin/debtors")).send()?;
+        let report: DebtorsReport = result.json()?;
+        assert!(report.debtors.is_empty(), "Paid back debt is still listed");
+
+        // Sellers can't refund out of their credit
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let seller: UserResult = result.json()?;
+        let result = client
+            .post(format!("{URL}/api/admin/credit_limit"))
+            .json(&AdminCreditLimitQuery {
+                user_id: seller.user.id,
+                credit_limit_cents: 100,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not set credit limit");
+        let result = client2
+            .post(format!("{URL}/api/transfer"))
+            .json(&TransferQuery {
+                amount_cents: seller.user.balance_cents,
+                recipient: "test".to_string(),
+                memo: None,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not transfer currency");
+        let result = client2.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        let sale = history
+            .entries
+            .iter()
+            .find(|entry| entry.entry_type == HistoryType::Sale)
+            .expect("Sale is missing from history");
+        let result = client2
+            .post(format!("{URL}/api/refund"))
+            .json(&RefundQuery {
+                transaction_id: sale.transaction.id,
+                amount_cents: None,
+                item_amount: None,
+                restock: false,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Seller refunded on credit");
 
         Ok(())
     }
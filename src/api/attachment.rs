@@ -141,7 +141,7 @@ mod tests {
     use crate::api::{item::NewItemQuery, user::UserQuery};
 
     use super::*;
-    const URL: &str = "http://backend:3030";
+    const URL: &str = "http://localhost:3030";
 
     // Test attachment uploading
     #[test]
@@ -176,7 +176,7 @@ mod tests {
         assert_eq!(
             result.status(),
             200,
-            "Could not clear db. Make sure the server is compiled in debug mode."
+            "Could not clear db. Make sure the server is running in development mode."
         );
 
         // Register test users and log them in to their clients
@@ -232,6 +232,9 @@ mod tests {
                 amount: 3,
                 price: "1.11".to_string(),
                 attachments: vec![attachment_id],
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
             })
             .send()?;
         assert_eq!(
@@ -249,6 +252,9 @@ mod tests {
                 amount: 1,
                 price: "1,00".to_string(),
                 attachments: vec![attachment_id2],
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
             })
             .send()?;
         assert_ne!(
@@ -266,6 +272,9 @@ mod tests {
                 amount: 3,
                 price: "1.11".to_string(),
                 attachments: vec![attachment_id],
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
             })
             .send()?;
         assert_ne!(
@@ -0,0 +1,469 @@
+use actix_session::Session;
+use actix_web::{error, web, Error, HttpResponse};
+use actix_web::{get, post};
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::api::payment_request::{PaymentRequestEntry, PaymentRequestStatus};
+use crate::api::transactions::validate_memo;
+use crate::api::user::get_login_uid;
+use crate::models::{Bill, PaymentRequest};
+use crate::BB8Pool;
+
+pub const MAX_BILL_PARTICIPANTS: usize = 50;
+
+#[derive(Serialize, Deserialize)]
+pub struct BillParticipant {
+    pub username: String,
+    /// Custom share of the participant. Either every participant has one, or
+    /// the bill is split evenly.
+    #[serde(default)]
+    pub share_cents: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewBillQuery {
+    pub total_cents: i32,
+    /// Users chipping in, not including the creator
+    pub participants: Vec<BillParticipant>,
+    /// What the bill is for
+    #[serde(default)]
+    pub memo: Option<String>,
+}
+
+/// How far a bill has got, following the payment requests of its shares
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BillStatus {
+    /// Some shares are still waiting for an answer
+    Open,
+    /// Every share has been paid
+    Settled,
+    /// Every share has been answered, but some were declined or cancelled
+    Closed,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BillResult {
+    #[serde(flatten)]
+    pub bill: Bill,
+    /// Payment request of each participant, with the participant as the counterparty
+    pub requests: Vec<PaymentRequestEntry>,
+    /// Sum of the shares paid
+    pub settled_cents: i64,
+    pub status: BillStatus,
+}
+
+/// Gathers payment requests of given bills
+pub async fn load_bill_results(
+    con: &mut AsyncPgConnection,
+    bills: Vec<Bill>,
+) -> QueryResult<Vec<BillResult>> {
+    use crate::schema::{payment_requests, users};
+
+    let bill_ids: Vec<i32> = bills.iter().map(|bill| bill.id).collect();
+    let rows = payment_requests::table
+        .inner_join(users::table.on(users::columns::id.eq(payment_requests::columns::payer_id)))
+        .filter(payment_requests::columns::bill_id.eq_any(bill_ids))
+        .order(payment_requests::columns::id)
+        .select((PaymentRequest::as_select(), users::columns::username))
+        .load::<(PaymentRequest, String)>(con)
+        .await?;
+    let mut requests: HashMap<i32, Vec<PaymentRequestEntry>> = HashMap::new();
+    for (request, counterparty) in rows {
+        let Some(bill_id) = request.bill_id else {
+            continue;
+        };
+        requests
+            .entry(bill_id)
+            .or_default()
+            .push(PaymentRequestEntry {
+                request,
+                counterparty,
+            });
+    }
+
+    Ok(bills
+        .into_iter()
+        .map(|bill| {
+            let requests = requests.remove(&bill.id).unwrap_or_default();
+            let accepted = PaymentRequestStatus::Accepted.name();
+            let pending = PaymentRequestStatus::Pending.name();
+            let settled_cents = requests
+                .iter()
+                .filter(|entry| entry.request.status == accepted)
+                .map(|entry| entry.request.amount_cents as i64)
+                .sum();
+            let status = if requests.iter().any(|entry| entry.request.status == pending) {
+                BillStatus::Open
+            } else if requests
+                .iter()
+                .all(|entry| entry.request.status == accepted)
+            {
+                BillStatus::Settled
+            } else {
+                BillStatus::Closed
+            };
+            BillResult {
+                bill,
+                requests,
+                settled_cents,
+                status,
+            }
+        })
+        .collect())
+}
+
+/// Splits a bill paid by the logged in user between them and the
+/// participants, and sends each participant a payment request for their
+/// share. An even split rounds the shares down, leaving the leftover cents
+/// to the creator.
+#[post("/bill/new")]
+pub async fn new_bill(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<NewBillQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{bills, payment_requests, users};
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    // Gather and validate input
+    let total_cents = query.total_cents;
+    if total_cents <= 0 {
+        return Err(error::ErrorBadRequest("Bill total must be positive"));
+    }
+    let memo = validate_memo(query.memo.as_deref())?;
+    let participants = &query.participants;
+    if participants.is_empty() {
+        return Err(error::ErrorBadRequest(
+            "Bill needs at least one participant",
+        ));
+    }
+    if participants.len() > MAX_BILL_PARTICIPANTS {
+        return Err(error::ErrorBadRequest(format!(
+            "Bill can have at most {MAX_BILL_PARTICIPANTS} participants"
+        )));
+    }
+    let usernames: HashSet<&str> = participants
+        .iter()
+        .map(|participant| participant.username.as_str())
+        .collect();
+    if usernames.len() != participants.len() {
+        return Err(error::ErrorBadRequest("Participant listed twice"));
+    }
+    let shares: Vec<i32> = match participants
+        .iter()
+        .map(|participant| participant.share_cents)
+        .collect::<Option<Vec<i32>>>()
+    {
+        Some(shares) => {
+            if shares.iter().any(|share| *share <= 0) {
+                return Err(error::ErrorBadRequest("Shares must be positive"));
+            }
+            if shares.iter().map(|share| *share as i64).sum::<i64>() > total_cents as i64 {
+                return Err(error::ErrorBadRequest("Shares exceed the bill total"));
+            }
+            shares
+        }
+        None if participants.iter().any(|p| p.share_cents.is_some()) => {
+            return Err(error::ErrorBadRequest(
+                "Give a share to every participant or to none",
+            ));
+        }
+        None => {
+            // The creator pays a share too
+            let share = total_cents / (participants.len() as i32 + 1);
+            if share == 0 {
+                return Err(error::ErrorBadRequest("Bill total is too small to split"));
+            }
+            vec![share; participants.len()]
+        }
+    };
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let result: Result<Result<Bill, &str>, diesel::result::Error> = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                let payer_ids: HashMap<String, i32> = users::table
+                    .filter(users::columns::username.eq_any(usernames))
+                    .filter(users::columns::deleted_at.is_null())
+                    .select((users::columns::username, users::columns::id))
+                    .load::<(String, i32)>(con)
+                    .await?
+                    .into_iter()
+                    .collect();
+                if payer_ids.len() != participants.len() {
+                    return Ok(Err("Participant does not exist"));
+                }
+                if payer_ids.values().any(|payer_id| *payer_id == uid) {
+                    return Ok(Err("Can't request money from yourself"));
+                }
+
+                let now = chrono::offset::Utc::now();
+                let bill = diesel::insert_into(bills::table)
+                    .values((
+                        bills::columns::creator_id.eq(uid),
+                        bills::columns::total_cents.eq(total_cents),
+                        bills::columns::memo.eq(&memo),
+                        bills::columns::created_at.eq(now),
+                    ))
+                    .returning(Bill::as_returning())
+                    .get_result(con)
+                    .await?;
+                let requests: Vec<_> = participants
+                    .iter()
+                    .zip(shares)
+                    .map(|(participant, share)| {
+                        (
+                            payment_requests::columns::requester_id.eq(uid),
+                            payment_requests::columns::payer_id
+                                .eq(payer_ids[&participant.username]),
+                            payment_requests::columns::amount_cents.eq(share),
+                            payment_requests::columns::memo.eq(memo.clone()),
+                            payment_requests::columns::status
+                                .eq(PaymentRequestStatus::Pending.name()),
+                            payment_requests::columns::created_at.eq(now),
+                            payment_requests::columns::bill_id.eq(bill.id),
+                        )
+                    })
+                    .collect();
+                diesel::insert_into(payment_requests::table)
+                    .values(requests)
+                    .execute(con)
+                    .await?;
+
+                Ok(Ok(bill))
+            })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    let bill = result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    let mut bill_results = load_bill_results(&mut con, vec![bill])
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(bill_results.remove(0)))
+}
+
+/// Lists bills created by the logged in user, newest first
+#[get("/bill/list")]
+pub async fn list_bills(pool: web::Data<BB8Pool>, session: Session) -> Result<HttpResponse, Error> {
+    use crate::schema::bills;
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let bills = bills::table
+        .filter(bills::columns::creator_id.eq(uid))
+        .order(bills::columns::id.desc())
+        .select(Bill::as_select())
+        .load(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let bill_results = load_bill_results(&mut con, bills)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(bill_results))
+}
+
+/// Returns a bill created by the logged in user, along with the state of
+/// each share
+#[get("/bill/{bill_id}")]
+pub async fn get_bill(
+    pool: web::Data<BB8Pool>,
+    bill_id: web::Path<i32>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::bills;
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let bill = bills::table
+        .filter(bills::columns::id.eq(*bill_id))
+        .filter(bills::columns::creator_id.eq(uid))
+        .select(Bill::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Bill not found"))?;
+    let mut bill_results = load_bill_results(&mut con, vec![bill])
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(bill_results.remove(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Result;
+    use std::sync::Arc;
+
+    use crate::api::admin::AdminGiveQuery;
+    use crate::api::payment_request::PaymentRequestQuery;
+    use crate::api::transactions::AdjustmentKind;
+    use crate::api::user::UserQuery;
+
+    use super::*;
+    const URL: &str = "http://localhost:3030";
+
+    // Test splitting bills and settling the shares
+    #[test]
+    fn bill_splitting() -> Result<()> {
+        // Set things up for testing
+        let clients = (0..3)
+            .map(|_| {
+                reqwest::blocking::ClientBuilder::new()
+                    .cookie_provider(Arc::new(reqwest::cookie::Jar::default()))
+                    .build()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Clear database for testing
+        let result = clients[0].get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users, the participants of which have some balance
+        for (client, username) in clients.iter().zip(["test", "test2", "test3"]) {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        for client in &clients[1..] {
+            let result = client
+                .post(format!("{URL}/api/admin/give"))
+                .json(&AdminGiveQuery {
+                    user_id: None,
+                    amount_cents: 1000,
+                    kind: AdjustmentKind::CashDeposit,
+                    reason: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not add balance to user");
+        }
+
+        let split = |total_cents: i32, participants: &[(&str, Option<i32>)]| {
+            clients[0]
+                .post(format!("{URL}/api/bill/new"))
+                .json(&NewBillQuery {
+                    total_cents,
+                    participants: participants
+                        .iter()
+                        .map(|(username, share_cents)| BillParticipant {
+                            username: username.to_string(),
+                            share_cents: *share_cents,
+                        })
+                        .collect(),
+                    memo: Some("pizza".to_string()),
+                })
+                .send()
+        };
+
+        // Invalid bills are rejected
+        for participants in [
+            &[][..],
+            &[("test", None)],
+            &[("test2", None), ("test2", None)],
+            &[("test2", None), ("nobody", None)],
+            &[("test2", Some(100)), ("test3", None)],
+            &[("test2", Some(600)), ("test3", Some(500))],
+        ] {
+            let result = split(1000, participants)?;
+            assert_eq!(result.status(), 400, "Invalid bill was accepted");
+        }
+
+        // An even split leaves the creator a share and the leftover cents
+        let result = split(1000, &[("test2", None), ("test3", None)])?;
+        assert_eq!(result.status(), 200, "Could not create a bill");
+        let bill: BillResult = result.json()?;
+        assert_eq!(bill.requests.len(), 2);
+        assert!(bill
+            .requests
+            .iter()
+            .all(|entry| entry.request.amount_cents == 333));
+        assert_eq!(bill.requests[0].counterparty, "test2");
+        assert_eq!(bill.requests[0].request.memo.as_deref(), Some("pizza"));
+        assert_eq!(bill.status, BillStatus::Open);
+
+        // Participants settle their shares one at a time
+        for (i, entry) in bill.requests.iter().enumerate() {
+            let result = clients[i + 1]
+                .post(format!("{URL}/api/request/accept"))
+                .json(&PaymentRequestQuery {
+                    request_id: entry.request.id,
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not pay share of a bill");
+            let result = clients[0]
+                .get(format!("{URL}/api/bill/{}", bill.bill.id))
+                .send()?;
+            let bill: BillResult = result.json()?;
+            assert_eq!(bill.settled_cents, 333 * (i as i64 + 1));
+            let status = if i == 1 {
+                BillStatus::Settled
+            } else {
+                BillStatus::Open
+            };
+            assert_eq!(bill.status, status, "Bill status is wrong");
+        }
+
+        // Custom shares are requested as given
+        let result = split(700, &[("test2", Some(500))])?;
+        assert_eq!(result.status(), 200, "Could not create a bill");
+        let bill: BillResult = result.json()?;
+        assert_eq!(bill.requests[0].request.amount_cents, 500);
+
+        // A declined share closes the bill instead of leaving it open
+        let result = clients[1]
+            .post(format!("{URL}/api/request/decline"))
+            .json(&PaymentRequestQuery {
+                request_id: bill.requests[0].request.id,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not decline share of a bill");
+        let result = clients[0]
+            .get(format!("{URL}/api/bill/{}", bill.bill.id))
+            .send()?;
+        let closed: BillResult = result.json()?;
+        assert_eq!(closed.settled_cents, 0);
+        assert_eq!(closed.status, BillStatus::Closed, "Bill wasn't closed");
+
+        // Only the creator can see their bills
+        let result = clients[1]
+            .get(format!("{URL}/api/bill/{}", bill.bill.id))
+            .send()?;
+        assert_eq!(result.status(), 404, "Bill was shown to a participant");
+        let result = clients[0].get(format!("{URL}/api/bill/list")).send()?;
+        let bills: Vec<BillResult> = result.json()?;
+        assert_eq!(bills.len(), 2);
+        assert_eq!(bills[0].bill.id, bill.bill.id, "Bills aren't newest first");
+
+        Ok(())
+    }
+}
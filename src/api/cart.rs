@@ -0,0 +1,519 @@
+use actix_session::Session;
+use actix_web::{error, web, Error, HttpRequest, HttpResponse};
+use actix_web::{get, post};
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::api::idempotency;
+use crate::api::item::{load_item_results, purchase, ItemResult};
+use crate::api::user::get_login_uid;
+use crate::models::{CartItem, Item, Order, Transaction};
+use crate::BB8Pool;
+
+/// Items put in a cart are reserved from other buyers for this long
+pub const CART_RESERVATION: Duration = Duration::from_secs(60 * 15);
+
+/// Sums amounts of given items reserved in carts of users other than `uid`
+pub async fn reserved_amounts(
+    con: &mut AsyncPgConnection,
+    item_ids: &[i32],
+    uid: i32,
+    now: chrono::DateTime<chrono::Utc>,
+) -> QueryResult<HashMap<i32, i64>> {
+    use crate::schema::cart_items::dsl::*;
+
+    let reserved: Vec<(i32, Option<i64>)> = cart_items
+        .filter(item_id.eq_any(item_ids))
+        .filter(user_id.ne(uid))
+        .filter(reserved_until.gt(now))
+        .group_by(item_id)
+        .select((item_id, diesel::dsl::sum(amount)))
+        .load(con)
+        .await?;
+    Ok(reserved
+        .into_iter()
+        .map(|(reserved_item_id, reserved_amount)| (reserved_item_id, reserved_amount.unwrap_or(0)))
+        .collect())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CartLine {
+    pub item: ItemResult,
+    pub amount: i32,
+    /// The amount is reserved for the user until this. After that the line
+    /// stays in the cart, but can only be bought if there's still stock left.
+    pub reserved_until: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CartResult {
+    pub lines: Vec<CartLine>,
+    pub total_cents: i32,
+}
+
+/// Lists the contents of logged in users cart
+#[get("/cart")]
+pub async fn get_cart(pool: web::Data<BB8Pool>, session: Session) -> Result<HttpResponse, Error> {
+    use crate::schema::{cart_items, items};
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let rows = cart_items::table
+        .inner_join(items::table)
+        .filter(cart_items::columns::user_id.eq(uid))
+        .order(cart_items::columns::added_at)
+        .select((CartItem::as_select(), Item::as_select()))
+        .load::<(CartItem, Item)>(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let (cart_rows, item_rows): (Vec<CartItem>, Vec<Item>) = rows.into_iter().unzip();
+    let item_results = load_item_results(&mut con, item_rows)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let lines: Vec<CartLine> = cart_rows
+        .into_iter()
+        .zip(item_results)
+        .map(|(line, item)| CartLine {
+            item,
+            amount: line.amount,
+            reserved_until: line.reserved_until,
+        })
+        .collect();
+    let total_cents = lines
+        .iter()
+        .map(|line| line.amount * line.item.item.price_cents)
+        .sum();
+
+    Ok(HttpResponse::Ok().json(CartResult { lines, total_cents }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CartQuery {
+    pub item_id: i32,
+    pub amount: Option<i32>,
+}
+
+/// Adds given amount of an item to logged in users cart, defaulting to one,
+/// and reserves the whole amount of the item in the cart for the user for
+/// [`CART_RESERVATION`].
+#[post("/cart/add")]
+pub async fn add_to_cart(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<CartQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{cart_items, items};
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    let item_id = query.item_id;
+    let added_amount = query.amount.unwrap_or(1);
+    if added_amount <= 0 {
+        return Err(error::ErrorBadRequest("Amount must be positive"));
+    }
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let result: Result<Result<CartItem, &str>, diesel::result::Error> = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                // Lock the item, so that parallel reservations can't both
+                // pass the stock check
+                let locked_items = items::table
+                    .filter(items::columns::id.eq(item_id))
+                    .filter(items::columns::archived_at.is_null())
+                    .select(Item::as_select())
+                    .for_update()
+                    .load(con)
+                    .await?;
+                let item = match &locked_items[..] {
+                    [item] => item,
+                    _ => return Ok(Err("Item not found")),
+                };
+
+                let in_cart: Option<i32> = cart_items::table
+                    .filter(cart_items::columns::user_id.eq(uid))
+                    .filter(cart_items::columns::item_id.eq(item_id))
+                    .select(cart_items::columns::amount)
+                    .first(con)
+                    .await
+                    .optional()?;
+                let Some(new_amount) = in_cart.unwrap_or(0).checked_add(added_amount) else {
+                    return Ok(Err("Not enough item in stock"));
+                };
+
+                let now = chrono::offset::Utc::now();
+                let reserved = reserved_amounts(con, &[item_id], uid, now).await?;
+                let reserved_amount = reserved.get(&item_id).copied().unwrap_or(0);
+                if i64::from(item.amount) - reserved_amount < i64::from(new_amount) {
+                    return Ok(Err("Not enough item in stock"));
+                }
+
+                let line = diesel::insert_into(cart_items::table)
+                    .values((
+                        cart_items::columns::user_id.eq(uid),
+                        cart_items::columns::item_id.eq(item_id),
+                        cart_items::columns::amount.eq(new_amount),
+                        cart_items::columns::added_at.eq(now),
+                        cart_items::columns::reserved_until.eq(now + CART_RESERVATION),
+                    ))
+                    .on_conflict((cart_items::columns::user_id, cart_items::columns::item_id))
+                    .do_update()
+                    .set((
+                        cart_items::columns::amount.eq(new_amount),
+                        cart_items::columns::reserved_until.eq(now + CART_RESERVATION),
+                    ))
+                    .returning(CartItem::as_returning())
+                    .get_result(con)
+                    .await?;
+
+                Ok(Ok(line))
+            })
+        })
+        .await;
+
+    let line = result
+        .map_err(error::ErrorInternalServerError)?
+        .map_err(error::ErrorBadRequest)?;
+
+    Ok(HttpResponse::Ok().json(line))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RemoveFromCartQuery {
+    pub item_id: i32,
+    /// Removes the whole line if not given
+    pub amount: Option<i32>,
+}
+
+/// Removes given amount of an item from logged in users cart, releasing its
+/// reservation. Lines left without any amount are removed.
+#[post("/cart/remove")]
+pub async fn remove_from_cart(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<RemoveFromCartQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::cart_items::dsl::*;
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    if query.amount.is_some_and(|removed| removed <= 0) {
+        return Err(error::ErrorBadRequest("Amount must be positive"));
+    }
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let line = cart_items
+        .filter(user_id.eq(uid))
+        .filter(item_id.eq(query.item_id));
+    let changed = match query.amount {
+        Some(removed) => diesel::update(line)
+            .filter(amount.gt(removed))
+            .set(amount.eq(amount - removed))
+            .execute(&mut con)
+            .await
+            .map_err(error::ErrorInternalServerError)?,
+        None => 0,
+    };
+    if changed == 0 {
+        let removed_lines = diesel::delete(line)
+            .execute(&mut con)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        if removed_lines == 0 {
+            return Err(error::ErrorBadRequest("Item not in cart"));
+        }
+    }
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OrderResult {
+    #[serde(flatten)]
+    pub order: Order,
+    pub transactions: Vec<Transaction>,
+}
+
+/// Buys everything in logged in users cart at once, as a single order. Either
+/// every line is bought or nothing is. Repeats of a request sent with the
+/// same `Idempotency-Key` header are only carried out once.
+#[post("/cart/checkout")]
+pub async fn checkout(
+    pool: web::Data<BB8Pool>,
+    session: Session,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let buyer_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    idempotency::run(&pool, &req, buyer_id, &(), |con| {
+        checkout_cart(con, buyer_id).scope_boxed_local()
+    })
+    .await
+}
+
+async fn checkout_cart(con: &mut AsyncPgConnection, buyer_id: i32) -> Result<HttpResponse, Error> {
+    use crate::schema::{cart_items, orders, transactions};
+
+    let result: Result<Result<OrderResult, &str>, diesel::result::Error> = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                // Lines added while checking out are left in the cart
+                let lines = cart_items::table
+                    .filter(cart_items::columns::user_id.eq(buyer_id))
+                    .select(CartItem::as_select())
+                    .for_update()
+                    .load(con)
+                    .await?;
+                if lines.is_empty() {
+                    return Ok(Err("Cart is empty"));
+                }
+
+                let purchase_lines: Vec<(i32, i32)> = lines
+                    .iter()
+                    .map(|line| (line.item_id, line.amount))
+                    .collect();
+                let purchases = match purchase(con, buyer_id, &purchase_lines).await? {
+                    Ok(purchases) => purchases,
+                    Err(reason) => return Ok(Err(reason)),
+                };
+
+                // Group the purchases under an order
+                let order = diesel::insert_into(orders::table)
+                    .values((
+                        orders::columns::buyer_id.eq(buyer_id),
+                        orders::columns::total_cents
+                            .eq(purchases.iter().map(|p| p.amount_cents).sum::<i32>()),
+                        orders::columns::created_at.eq(chrono::offset::Utc::now()),
+                    ))
+                    .returning(Order::as_returning())
+                    .get_result(con)
+                    .await?;
+                let purchase_ids: Vec<i32> = purchases.iter().map(|p| p.id).collect();
+                let transactions = diesel::update(transactions::table)
+                    .filter(transactions::columns::id.eq_any(purchase_ids))
+                    .set(transactions::columns::order_id.eq(order.id))
+                    .returning(Transaction::as_returning())
+                    .get_results(con)
+                    .await?;
+
+                let line_ids: Vec<i32> = lines.iter().map(|line| line.id).collect();
+                diesel::delete(cart_items::table)
+                    .filter(cart_items::columns::id.eq_any(line_ids))
+                    .execute(con)
+                    .await?;
+
+                Ok(Ok(OrderResult {
+                    order,
+                    transactions,
+                }))
+            })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    let order = result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    Ok(HttpResponse::Ok().json(order))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Result;
+    use std::sync::Arc;
+
+    use crate::api::admin::AdminGiveQuery;
+    use crate::api::item::{BuyQuery, NewItemQuery};
+    use crate::api::transactions::AdjustmentKind;
+    use crate::api::user::UserQuery;
+    use crate::models::User;
+
+    use super::*;
+    const URL: &str = "http://localhost:3030";
+
+    // Test reserving items in carts and checking them out
+    #[test]
+    fn cart_checkout() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users, the first of which sells two items
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        let mut items = Vec::new();
+        for (title, amount, price) in [("test item", 2, "1"), ("other item", 1, "2")] {
+            let result = client
+                .post(format!("{URL}/api/item/new"))
+                .json(&NewItemQuery {
+                    title: title.to_string(),
+                    description: "test description".to_string(),
+                    amount,
+                    price: price.to_string(),
+                    attachments: Vec::new(),
+                    category_id: None,
+                    tags: Vec::new(),
+                    barcode: None,
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new item");
+            items.push(result.json::<Item>()?);
+        }
+        let result = client2
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                user_id: None,
+                amount_cents: 300,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add balance to user");
+
+        // Stock reserved in a cart can't be taken by others
+        let result = client2
+            .post(format!("{URL}/api/cart/add"))
+            .json(&CartQuery {
+                item_id: items[0].id,
+                amount: Some(2),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add item to cart");
+        let result = client2
+            .post(format!("{URL}/api/cart/add"))
+            .json(&CartQuery {
+                item_id: items[0].id,
+                amount: Some(i32::MAX),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Cart amount overflowed");
+        let result = client
+            .post(format!("{URL}/api/cart/add"))
+            .json(&CartQuery {
+                item_id: items[0].id,
+                amount: None,
+            })
+            .send()?;
+        assert_eq!(
+            result.status(),
+            400,
+            "Reserved item was added to another cart"
+        );
+        let result = client
+            .post(format!("{URL}/api/item/buy"))
+            .json(&BuyQuery {
+                item_id: items[0].id,
+                amount: None,
+            })
+            .send()?;
+        assert_eq!(
+            result.status(),
+            400,
+            "Reserved item was bought by another user"
+        );
+
+        // Checking out more than the balance covers buys nothing
+        let result = client2
+            .post(format!("{URL}/api/cart/add"))
+            .json(&CartQuery {
+                item_id: items[1].id,
+                amount: None,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add item to cart");
+        let result = client2.get(format!("{URL}/api/cart")).send()?;
+        let cart: CartResult = result.json()?;
+        assert_eq!(cart.lines.len(), 2);
+        assert_eq!(cart.total_cents, 400);
+        let result = client2.post(format!("{URL}/api/cart/checkout")).send()?;
+        assert_eq!(result.status(), 400, "Checked out more than balance");
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 300, "Failed checkout charged the buyer");
+        let result = client2.get(format!("{URL}/api/cart")).send()?;
+        let cart: CartResult = result.json()?;
+        assert_eq!(cart.lines.len(), 2, "Failed checkout emptied the cart");
+        assert_eq!(
+            cart.lines[0].item.item.amount, 2,
+            "Failed checkout changed stock"
+        );
+
+        // Reduce the cart to fit the balance and check it out
+        let result = client2
+            .post(format!("{URL}/api/cart/remove"))
+            .json(&RemoveFromCartQuery {
+                item_id: items[0].id,
+                amount: Some(1),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not remove item from cart");
+        let result = client2.post(format!("{URL}/api/cart/checkout")).send()?;
+        assert_eq!(result.status(), 200, "Could not check out cart");
+        let order: OrderResult = result.json()?;
+        assert_eq!(order.order.total_cents, 300);
+        assert_eq!(order.transactions.len(), 2);
+        assert!(order
+            .transactions
+            .iter()
+            .all(|transaction| transaction.order_id == Some(order.order.id)));
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 0, "Checkout charged a wrong amount");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 300, "Seller didn't get paid");
+
+        // The cart is empty afterwards, and the stock left over is free again
+        let result = client2.get(format!("{URL}/api/cart")).send()?;
+        let cart: CartResult = result.json()?;
+        assert!(cart.lines.is_empty(), "Checkout didn't empty the cart");
+        let result = client2.post(format!("{URL}/api/cart/checkout")).send()?;
+        assert_eq!(result.status(), 400, "Checked out an empty cart");
+        let result = client
+            .post(format!("{URL}/api/cart/add"))
+            .json(&CartQuery {
+                item_id: items[0].id,
+                amount: None,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add released item to cart");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use actix_web::body::{self, BoxBody};
+use actix_web::http::{header, StatusCode};
+use actix_web::{error, Error, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::scoped_futures::ScopedLocalBoxFuture;
+use diesel_async::RunQueryDsl;
+use diesel_async::{AnsiTransactionManager, AsyncPgConnection, TransactionManager};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::models::IdempotencyKey;
+use crate::BB8Pool;
+
+/// Header clients use to mark repeats of the same request
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Header set on responses replayed from a stored result
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+/// Stored results are replayed for this long, after which the key can be reused
+pub const KEY_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24);
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Runs `handler` unless the request carries an `Idempotency-Key` header
+/// already seen from the same user. For repeats the stored result of the
+/// first request is replayed, and a repeat sent with a different body is
+/// rejected. Requests without the header are always run.
+///
+/// The key is claimed, the handler run and its result stored in a single
+/// database transaction on the connection given to the handler, so a request
+/// that never finishes leaves nothing behind. Repeats sent meanwhile wait for
+/// the first request to finish. Server errors are not stored, so that
+/// requests failing because of them can be retried with the same key.
+pub async fn run<'a, T, F>(
+    pool: &BB8Pool,
+    req: &HttpRequest,
+    uid: i32,
+    query: &T,
+    handler: F,
+) -> Result<HttpResponse, Error>
+where
+    T: Serialize,
+    F: for<'r> FnOnce(
+            &'r mut AsyncPgConnection,
+        ) -> ScopedLocalBoxFuture<'a, 'r, Result<HttpResponse, Error>>
+        + 'a,
+{
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let Some(header_value) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return handler(&mut con).await;
+    };
+    let request_key = header_value
+        .to_str()
+        .ok()
+        .filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LENGTH)
+        .ok_or_else(|| error::ErrorBadRequest("Invalid Idempotency-Key header"))?
+        .to_string();
+    let body = serde_json::to_string(query).map_err(error::ErrorInternalServerError)?;
+    let hash = blake3::hash(format!("{} {body}", req.path()).as_bytes()).to_string();
+
+    // Transactions the handler makes become savepoints of this one
+    AnsiTransactionManager::begin_transaction(&mut *con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let result = run_claimed(&mut con, uid, &request_key, &hash, handler).await;
+    match result {
+        Ok(response) if !response.status().is_server_error() => {
+            AnsiTransactionManager::commit_transaction(&mut *con)
+                .await
+                .map_err(error::ErrorInternalServerError)?;
+            Ok(response)
+        }
+        result => {
+            AnsiTransactionManager::rollback_transaction(&mut *con)
+                .await
+                .map_err(error::ErrorInternalServerError)?;
+            result
+        }
+    }
+}
+
+/// Claims the key and runs the handler, or replays the stored result if the
+/// key has been claimed before. Has to be run inside of a transaction.
+async fn run_claimed<'a, F>(
+    con: &mut AsyncPgConnection,
+    uid: i32,
+    request_key: &str,
+    hash: &str,
+    handler: F,
+) -> Result<HttpResponse, Error>
+where
+    F: for<'r> FnOnce(
+            &'r mut AsyncPgConnection,
+        ) -> ScopedLocalBoxFuture<'a, 'r, Result<HttpResponse, Error>>
+        + 'a,
+{
+    use crate::schema::idempotency_keys::dsl::*;
+
+    let now = chrono::offset::Utc::now();
+
+    // Expired keys are free to be used again
+    diesel::delete(idempotency_keys)
+        .filter(user_id.eq(uid))
+        .filter(key.eq(request_key))
+        .filter(expires_at.lt(now))
+        .execute(con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    // Waits for a transaction holding the same key to finish
+    let claimed = diesel::insert_into(idempotency_keys)
+        .values((
+            user_id.eq(uid),
+            key.eq(request_key),
+            request_hash.eq(hash),
+            created_at.eq(now),
+            expires_at.eq(now + KEY_LIFETIME),
+        ))
+        .on_conflict((user_id, key))
+        .do_nothing()
+        .execute(con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    if claimed == 0 {
+        let stored = idempotency_keys
+            .filter(user_id.eq(uid))
+            .filter(key.eq(request_key))
+            .select(IdempotencyKey::as_select())
+            .get_result(con)
+            .await
+            .optional()
+            .map_err(error::ErrorInternalServerError)?
+            .ok_or_else(|| error::ErrorConflict("Request with this key is in progress"))?;
+        return replay(stored, hash);
+    }
+
+    let response = match handler(con).await {
+        Ok(response) => response,
+        Err(err) => err.error_response(),
+    };
+    if response.status().is_server_error() {
+        // Rolled back along with the claim
+        return Ok(response);
+    }
+
+    // Store the result for replaying
+    let status = response.status();
+    let (response, handler_body) = response.into_parts();
+    let bytes = body::to_bytes(handler_body)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let stored_content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    diesel::update(idempotency_keys)
+        .filter(user_id.eq(uid))
+        .filter(key.eq(request_key))
+        .set((
+            response_status.eq(status.as_u16() as i32),
+            response_content_type.eq(stored_content_type),
+            response_body.eq(bytes.to_vec()),
+        ))
+        .execute(con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(response.set_body(BoxBody::new(bytes)))
+}
+
+/// Builds a response out of a stored result
+fn replay(stored: IdempotencyKey, hash: &str) -> Result<HttpResponse, Error> {
+    if stored.request_hash != hash {
+        return Err(error::ErrorUnprocessableEntity(
+            "Idempotency-Key was already used for a different request",
+        ));
+    }
+    let (Some(status), Some(stored_body)) = (stored.response_status, stored.response_body) else {
+        return Err(error::ErrorConflict("Request with this key is in progress"));
+    };
+    let status = StatusCode::from_u16(status as u16).map_err(error::ErrorInternalServerError)?;
+    let mut response = HttpResponse::build(status);
+    response.insert_header((REPLAYED_HEADER, "true"));
+    if let Some(stored_content_type) = stored.response_content_type {
+        response.insert_header((header::CONTENT_TYPE, stored_content_type));
+    }
+    Ok(response.body(stored_body))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Result;
+    use std::sync::Arc;
+
+    use crate::api::admin::AdminGiveQuery;
+    use crate::api::transactions::{AdjustmentKind, TransferQuery};
+    use crate::api::user::UserQuery;
+    use crate::models::User;
+
+    use super::*;
+    const URL: &str = "http://localhost:3030";
+
+    // Test that repeated requests are carried out only once
+    #[test]
+    fn idempotent_requests() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users and log them in to their clients
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+
+        // A retried deposit is only made once
+        for _ in 0..2 {
+            let result = client
+                .post(format!("{URL}/api/admin/give"))
+                .header(IDEMPOTENCY_KEY_HEADER, "give-1")
+                .json(&AdminGiveQuery {
+                    amount_cents: 100,
+                    user_id: None,
+                    kind: AdjustmentKind::CashDeposit,
+                    reason: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not give currency");
+        }
+
+        // A retried transfer is only made once, and the repeat is replayed
+        let transfer = |amount_cents, key| {
+            client
+                .post(format!("{URL}/api/transfer"))
+                .header(IDEMPOTENCY_KEY_HEADER, key)
+                .json(&TransferQuery {
+                    amount_cents,
+                    recipient: "test2".to_string(),
+                    memo: None,
+                })
+                .send()
+        };
+        let result = transfer(30, "transfer-1")?;
+        assert_eq!(result.status(), 200, "Could not transfer currency");
+        assert!(result.headers().get(REPLAYED_HEADER).is_none());
+        let result = transfer(30, "transfer-1")?;
+        assert_eq!(result.status(), 200, "Repeated transfer was not replayed");
+        assert_eq!(result.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        assert_eq!(result.text()?, "OK");
+
+        // Reusing the key for a different request is refused
+        let result = transfer(40, "transfer-1")?;
+        assert_eq!(result.status(), 422, "Key was reused for a different request");
+
+        // Failures are replayed as well
+        let result = transfer(1000, "transfer-2")?;
+        assert_eq!(result.status(), 400, "Transfer didn't fail on insufficient funds");
+        let result = transfer(1000, "transfer-2")?;
+        assert_eq!(result.status(), 400, "Failed transfer was not replayed");
+        assert_eq!(result.headers().get(REPLAYED_HEADER).unwrap(), "true");
+
+        // Repeats sent at the same time wait for the first one and replay it
+        let statuses: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| transfer(10, "transfer-3").map(|result| result.status())))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        for status in statuses {
+            assert_eq!(status?, 200, "Simultaneous repeat wasn't replayed");
+        }
+
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 100 - 30 - 10, "Repeated requests moved money twice");
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let user2: User = result.json()?;
+        assert_eq!(user2.balance_cents, 30 + 10, "Repeated requests moved money twice");
+
+        // Keys are per user
+        let result = client2
+            .post(format!("{URL}/api/transfer"))
+            .header(IDEMPOTENCY_KEY_HEADER, "transfer-1")
+            .json(&TransferQuery {
+                amount_cents: 30,
+                recipient: "test".to_string(),
+                memo: None,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Key of another user was replayed");
+        assert!(result.headers().get(REPLAYED_HEADER).is_none());
+
+        Ok(())
+    }
+}
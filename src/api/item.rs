@@ -1,85 +1,336 @@
 use actix_session::Session;
-use actix_web::post;
-use actix_web::{error, web, Error, HttpResponse};
+use actix_web::http::header::{self, EntityTag, Header, IfNoneMatch};
+use actix_web::{error, web, Error, HttpRequest, HttpResponse};
+use actix_web::{get, post};
+use diesel::dsl::sql;
+use diesel::expression::{is_aggregate, TypedExpressionType};
+use diesel::pg::Pg;
 use diesel::prelude::*;
+use diesel::sql_types::{Bool, Float, Nullable, SqlType, Text};
 use diesel::ExpressionMethods;
-use diesel_async::AsyncConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_async::RunQueryDsl;
-use futures::try_join;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
 use itertools::Itertools;
-use serde::Deserialize;
 use serde::Serialize;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 
-use crate::api::user::get_login_uid;
-use crate::models::{Attachment, Item, User};
+use crate::api::cart::reserved_amounts;
+use crate::api::idempotency;
+use crate::api::roles::{Authorized, KioskManager, Moderator, Permission};
+use crate::api::transactions::TransactionKind;
+use crate::api::user::{get_login_uid, lock_users};
+use crate::models::{Attachment, Category, Item, ItemTag, Transaction};
 use crate::BB8Pool;
 
-#[derive(Serialize, Deserialize)]
+// Limits of item listings
+pub const MAX_TITLE_LENGTH: usize = 50;
+pub const MAX_DESCRIPTION_LENGTH: usize = 500;
+const MAX_ITEM_AMOUNT: usize = 50;
+const MAX_PRICE_CENTS: u32 = 15_00;
+const MIN_PRICE_CENTS: u32 = 1;
+const MAX_ATTACHMENTS: usize = 5;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_LENGTH: usize = 30;
+
+#[derive(Serialize, Deserialize, Default)]
 struct ItemQuery {
     search_term: Option<String>,
-    offset: Option<i64>,
+    /// Only list items after this position. Pass `next_cursor` of the
+    /// previous page to get the next one.
+    cursor: Option<String>,
     limit: Option<i64>,
     get_items_without_stock: Option<bool>,
+    include_archived: Option<bool>,
+    category_id: Option<i32>,
+    /// Only list items having all of these tags
+    tags: Option<Vec<String>>,
+    /// Defaults to relevance when searching and to newest otherwise
+    sort: Option<ItemSort>,
+    min_price_cents: Option<i32>,
+    max_price_cents: Option<i32>,
+    seller_id: Option<i32>,
+    /// Username of the seller
+    seller: Option<String>,
+}
+
+/// Orders items can be listed in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ItemSort {
+    /// Most relevant to the search term first
+    Relevance,
+    Newest,
+    Oldest,
+    PriceAsc,
+    PriceDesc,
+    /// Most units sold first
+    Popular,
+}
+
+/// Position of an item in a listing, from which the next page continues.
+/// Items with equal sort values are ordered by id.
+#[derive(Debug, PartialEq)]
+enum Cursor {
+    Count(i32, i32),
+    Time(chrono::DateTime<chrono::Utc>, i32),
+    Rank(f32, i32),
+}
+
+impl Cursor {
+    /// Returns the cursor pointing at an item in given sort order
+    fn at(item: &Item, rank: f32, sort: ItemSort) -> Cursor {
+        match sort {
+            ItemSort::Relevance => Cursor::Rank(rank, item.id),
+            ItemSort::Newest | ItemSort::Oldest => Cursor::Time(item.created_at.to_utc(), item.id),
+            ItemSort::PriceAsc | ItemSort::PriceDesc => Cursor::Count(item.price_cents, item.id),
+            ItemSort::Popular => Cursor::Count(item.units_sold, item.id),
+        }
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            Cursor::Count(value, id) => format!("{value}:{id}"),
+            Cursor::Time(value, id) => format!(
+                "{}:{id}",
+                value.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+            ),
+            Cursor::Rank(value, id) => format!("{value}:{id}"),
+        }
+    }
+
+    /// Parses a cursor made for given sort order
+    fn decode(cursor: &str, sort: ItemSort) -> Option<Cursor> {
+        let (value, id) = cursor.rsplit_once(':')?;
+        let id = id.parse().ok()?;
+        Some(match sort {
+            ItemSort::Relevance => Cursor::Rank(value.parse().ok()?, id),
+            ItemSort::Newest | ItemSort::Oldest => Cursor::Time(
+                chrono::DateTime::parse_from_rfc3339(value).ok()?.to_utc(),
+                id,
+            ),
+            ItemSort::PriceAsc | ItemSort::PriceDesc | ItemSort::Popular => {
+                Cursor::Count(value.parse().ok()?, id)
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ItemResult {
+    #[serde(flatten)]
+    pub item: Item,
+    pub attachments: Vec<Attachment>,
+    pub tags: Vec<String>,
+    /// Part of the description matching the search term, with matches
+    /// wrapped in `<mark>` tags and the rest of the text HTML escaped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
 }
 
+/// Category along with the amount of listed items in it
 #[derive(Serialize, Deserialize)]
-struct ItemResult {
+struct CategoryFacet {
     #[serde(flatten)]
-    item: Item,
-    attachments: Vec<Attachment>,
+    category: Category,
+    item_count: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ItemListResult {
+    items: Vec<ItemResult>,
+    /// Amount of items matching the query on every page
+    total: i64,
+    /// Cursor for the next page, if there might be one
+    next_cursor: Option<String>,
+    /// Every category, with item counts matching the query apart from its category
+    categories: Vec<CategoryFacet>,
+}
+
+/// Loads attachments and tags of items
+pub async fn load_item_results(
+    con: &mut AsyncPgConnection,
+    item_result: Vec<Item>,
+) -> QueryResult<Vec<ItemResult>> {
+    let attachments_result = Attachment::belonging_to(&item_result)
+        .select(Attachment::as_select())
+        .load(con)
+        .await?;
+    let tags_result = ItemTag::belonging_to(&item_result)
+        .select(ItemTag::as_select())
+        .order(crate::schema::item_tags::columns::tag)
+        .load(con)
+        .await?;
+    Ok(attachments_result
+        .grouped_by(&item_result)
+        .into_iter()
+        .zip(tags_result.grouped_by(&item_result))
+        .zip(item_result)
+        .map(|((attachments, tags), item)| ItemResult {
+            item,
+            attachments,
+            tags: tags.into_iter().map(|t| t.tag).collect(),
+            snippet: None,
+        })
+        .collect())
 }
 
-/// Lists items for sale. The endpoint can filter results by a search
-/// term and get results regardless of if they are in stock. It can also
-/// limit the amount of returned items and skip an amount of items from
-/// being returned, which can be used to implement pages in the frontend.
+/// Trims, lowercases, deduplicates, sorts and validates tags
+fn normalize_tags(tags: &[String]) -> Result<Vec<String>, Error> {
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .unique()
+        .sorted()
+        .collect();
+    if tags.len() > MAX_TAGS {
+        return Err(error::ErrorBadRequest(format!(
+            "Amount of tags can be at most {MAX_TAGS}"
+        )));
+    }
+    if tags
+        .iter()
+        .any(|tag| tag.is_empty() || tag.len() > MAX_TAG_LENGTH)
+    {
+        return Err(error::ErrorBadRequest(format!(
+            "Tags must be at least 1 and at most {MAX_TAG_LENGTH} characters long"
+        )));
+    }
+    Ok(tags)
+}
+
+/// Checks that a category exists
+async fn validate_category(con: &mut AsyncPgConnection, category: i32) -> Result<(), Error> {
+    use crate::schema::categories;
+
+    let found: i64 = categories::table
+        .filter(categories::columns::id.eq(category))
+        .count()
+        .get_result(con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if found == 0 {
+        return Err(error::ErrorBadRequest("Category not found"));
+    }
+    Ok(())
+}
+
+/// How a search term is matched against items
+#[derive(Clone, Copy)]
+enum SearchMode {
+    /// Matches words of titles and descriptions, in Finnish and English
+    FullText,
+    /// Matches titles resembling the search term, to tolerate typos
+    Fuzzy,
+}
+
+/// Minimum word similarity of a title to the search term in fuzzy search
+const FUZZY_SEARCH_THRESHOLD: f32 = 0.4;
+
+/// SQL expression on items, built at runtime
+type ItemExpression<ST> = Box<
+    dyn BoxableExpression<crate::schema::items::table, Pg, (), is_aggregate::Never, SqlType = ST>,
+>;
+
+/// Wraps the full text query of a search term between two SQL fragments.
+/// The query matches in every language items are indexed in.
+fn with_ts_query<ST>(prefix: &str, term: &str, suffix: &str) -> ItemExpression<ST>
+where
+    ST: SqlType + TypedExpressionType + Send + 'static,
+{
+    Box::new(
+        sql::<ST>(&format!("{prefix}(websearch_to_tsquery('finnish', "))
+            .bind::<Text, _>(term.to_string())
+            .sql(") || websearch_to_tsquery('english', ")
+            .bind::<Text, _>(term.to_string())
+            .sql(&format!(")){suffix}")),
+    )
+}
+
+/// Filters items matching a search term
+fn search_filter(term: &str, mode: SearchMode) -> ItemExpression<Bool> {
+    match mode {
+        SearchMode::FullText => with_ts_query("items.search_vector @@ ", term, ""),
+        SearchMode::Fuzzy => Box::new(
+            sql::<Bool>("")
+                .bind::<Text, _>(term.to_string())
+                .sql(" <% items.title"),
+        ),
+    }
+}
+
+/// Relevance of items to a search term, higher being more relevant
+fn search_rank(term: &str, mode: SearchMode) -> ItemExpression<Float> {
+    match mode {
+        SearchMode::FullText => with_ts_query("ts_rank(items.search_vector, ", term, ")"),
+        SearchMode::Fuzzy => Box::new(
+            sql::<Float>("word_similarity(")
+                .bind::<Text, _>(term.to_string())
+                .sql(", items.title)"),
+        ),
+    }
+}
+
+/// Highlighted snippet of item descriptions matching a full text search.
+/// The description is escaped first, so that the snippet is safe to show
+/// as HTML.
+fn search_snippet(term: Option<&str>, mode: Option<SearchMode>) -> ItemExpression<Nullable<Text>> {
+    match (term, mode) {
+        (Some(term), Some(SearchMode::FullText)) => with_ts_query(
+            "ts_headline('finnish', \
+             replace(replace(replace(items.description, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), ",
+            term,
+            ", 'StartSel=<mark>, StopSel=</mark>, MaxWords=20, MinWords=5')",
+        ),
+        _ => Box::new(sql::<Nullable<Text>>("NULL")),
+    }
+}
+
+/// Lists items for sale. The endpoint can search titles and descriptions,
+/// ranking results by relevance and falling back to fuzzy matching of
+/// titles if nothing matches exactly. It can filter by category, tags,
+/// price and seller, and get results regardless of if they are in stock or
+/// archived. Deleted items are never listed. Results are returned in
+/// pages, which are walked through with a cursor so that pages stay intact
+/// while items are added or sold. Along with items the total amount of
+/// matches is returned, and every category with the amount of matching
+/// items in it, which can be used to show how narrowing down by category
+/// would affect the results.
 #[post("/item/list")]
 pub async fn get_items(
     pool: web::Data<BB8Pool>,
     query: Option<web::Json<ItemQuery>>,
 ) -> Result<HttpResponse, Error> {
+    use crate::schema::categories;
+    use crate::schema::item_tags;
     use crate::schema::items::dsl::*;
-
-    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    use crate::schema::users;
 
     // Default values
-    let mut offset = 0;
     let mut limit = 20;
     let mut minimum_stock = 1;
+    let mut include_archived = false;
+    let mut search_term = None;
+    let mut category = None;
+    let mut required_tags = Vec::new();
+    let mut sort = None;
+    let mut cursor = None;
+    let mut price_range = (None, None);
+    let mut seller = None;
+    let mut seller_name = None;
 
     // Limits
     const SEARCH_MAX_LENGTH: usize = 50;
-    const OFFSET_MIN: i64 = 0;
     const LIMIT_CONSTRAINTS: (i64, i64) = (1, 100);
 
-    let mut db_query = items.into_boxed();
-
     // Overwrite default values with ones provided in item query
     if let Some(query) = &query {
-        if let Some(search_term) = &query.search_term {
-            if search_term.len() > SEARCH_MAX_LENGTH {
+        if let Some(val) = &query.search_term {
+            if val.len() > SEARCH_MAX_LENGTH {
                 return Err(error::ErrorBadRequest("Search term too long"));
             }
-
-            // Surround search term with wildmarks and escape accidental (or not) wildmarks provided by user
-            let escaped: String = search_term
-                .chars()
-                .flat_map(|c| match c {
-                    '%' => vec!['\\', '%'],
-                    '\\' => vec!['\\', '\\'],
-                    c => vec![c],
-                })
-                .collect();
-            let wildmarked = format!("%{escaped}%");
-            db_query = db_query.filter(title.ilike(wildmarked));
-        }
-        if let Some(val) = &query.offset {
-            if *val < OFFSET_MIN {
-                return Err(error::ErrorBadRequest(format!(
-                    "Offset must be at least {OFFSET_MIN}"
-                )));
-            }
-            offset = val.to_owned();
+            search_term = Some(val.trim().to_string()).filter(|term| !term.is_empty());
         }
         if let Some(val) = &query.limit {
             if *val < LIMIT_CONSTRAINTS.0 || *val > LIMIT_CONSTRAINTS.1 {
@@ -93,30 +344,291 @@ pub async fn get_items(
         if let Some(true) = &query.get_items_without_stock {
             minimum_stock = 0;
         }
+        if let Some(true) = &query.include_archived {
+            include_archived = true;
+        }
+        category = query.category_id;
+        if let Some(val) = &query.tags {
+            required_tags = normalize_tags(val)?;
+        }
+        sort = query.sort;
+        cursor = query.cursor.clone();
+        price_range = (query.min_price_cents, query.max_price_cents);
+        seller = query.seller_id;
+        seller_name = query.seller.clone();
     }
 
-    // Query db and return results
-    let item_result = db_query
-        .offset(offset)
-        .limit(limit)
-        .select(Item::as_select())
-        .filter(amount.ge(minimum_stock))
-        .load(&mut con)
+    // Relevance only makes sense when searching
+    let sort = match (sort, &search_term) {
+        (None | Some(ItemSort::Relevance), Some(_)) => ItemSort::Relevance,
+        (None | Some(ItemSort::Relevance), None) => ItemSort::Newest,
+        (Some(sort), _) => sort,
+    };
+    let cursor = cursor
+        .map(|c| Cursor::decode(&c, sort).ok_or_else(|| error::ErrorBadRequest("Invalid cursor")))
+        .transpose()?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    // Run in a transaction to scope the fuzzy search threshold to this query,
+    // and to count matches from the same snapshot as the listing
+    let (item_results, total, next_cursor, categories) = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                diesel::sql_query(format!(
+                    "SET LOCAL pg_trgm.word_similarity_threshold = {FUZZY_SEARCH_THRESHOLD}"
+                ))
+                .execute(con)
+                .await?;
+
+                // Items matching every filter apart from category, shared by
+                // the listing and category counts
+                let filtered_items = |mode: Option<SearchMode>| {
+                    let mut db_query = items
+                        .filter(deleted_at.is_null())
+                        .filter(amount.ge(minimum_stock))
+                        .into_boxed();
+                    if !include_archived {
+                        db_query = db_query.filter(archived_at.is_null());
+                    }
+                    if let (Some(term), Some(mode)) = (&search_term, mode) {
+                        db_query = db_query.filter(search_filter(term, mode));
+                    }
+                    for required_tag in &required_tags {
+                        db_query = db_query.filter(
+                            id.eq_any(
+                                item_tags::table
+                                    .filter(item_tags::columns::tag.eq(required_tag.clone()))
+                                    .select(item_tags::columns::item_id),
+                            ),
+                        );
+                    }
+                    if let Some(min_price) = price_range.0 {
+                        db_query = db_query.filter(price_cents.ge(min_price));
+                    }
+                    if let Some(max_price) = price_range.1 {
+                        db_query = db_query.filter(price_cents.le(max_price));
+                    }
+                    if let Some(seller) = seller {
+                        db_query = db_query.filter(seller_id.eq(seller));
+                    }
+                    if let Some(seller_name) = &seller_name {
+                        db_query = db_query.filter(
+                            seller_id.eq_any(
+                                users::table
+                                    .filter(users::columns::username.eq(seller_name.clone()))
+                                    .select(users::columns::id),
+                            ),
+                        );
+                    }
+                    db_query
+                };
+                let matching_items = |mode: Option<SearchMode>| {
+                    let mut db_query = filtered_items(mode);
+                    if let Some(category) = category {
+                        db_query = db_query.filter(category_id.eq(category));
+                    }
+                    db_query
+                };
+
+                // Fall back to fuzzy search if there are no exact matches
+                let mode = match &search_term {
+                    Some(_) => {
+                        let matches: i64 = filtered_items(Some(SearchMode::FullText))
+                            .count()
+                            .get_result(con)
+                            .await?;
+                        Some(if matches > 0 {
+                            SearchMode::FullText
+                        } else {
+                            SearchMode::Fuzzy
+                        })
+                    }
+                    None => None,
+                };
+                let rank = || match (&search_term, mode) {
+                    (Some(term), Some(mode)) => search_rank(term, mode),
+                    _ => Box::new(sql::<Float>("0")),
+                };
+
+                let total: i64 = matching_items(mode).count().get_result(con).await?;
+
+                // Continue from the cursor and sort, breaking ties by id
+                let mut db_query = matching_items(mode);
+                match cursor {
+                    Some(Cursor::Rank(value, after)) => {
+                        db_query =
+                            db_query.filter(rank().lt(value).or(rank().eq(value).and(id.gt(after))))
+                    }
+                    Some(Cursor::Time(value, after)) if sort == ItemSort::Newest => {
+                        db_query = db_query.filter(
+                            created_at
+                                .lt(value)
+                                .or(created_at.eq(value).and(id.gt(after))),
+                        )
+                    }
+                    Some(Cursor::Time(value, after)) => {
+                        db_query = db_query.filter(
+                            created_at
+                                .gt(value)
+                                .or(created_at.eq(value).and(id.gt(after))),
+                        )
+                    }
+                    Some(Cursor::Count(value, after)) if sort == ItemSort::PriceAsc => {
+                        db_query = db_query.filter(
+                            price_cents
+                                .gt(value)
+                                .or(price_cents.eq(value).and(id.gt(after))),
+                        )
+                    }
+                    Some(Cursor::Count(value, after)) if sort == ItemSort::PriceDesc => {
+                        db_query = db_query.filter(
+                            price_cents
+                                .lt(value)
+                                .or(price_cents.eq(value).and(id.gt(after))),
+                        )
+                    }
+                    Some(Cursor::Count(value, after)) => {
+                        db_query = db_query.filter(
+                            units_sold
+                                .lt(value)
+                                .or(units_sold.eq(value).and(id.gt(after))),
+                        )
+                    }
+                    None => {}
+                }
+                db_query = match sort {
+                    ItemSort::Relevance => db_query.order((rank().desc(), id.asc())),
+                    ItemSort::Newest => db_query.order((created_at.desc(), id.asc())),
+                    ItemSort::Oldest => db_query.order((created_at.asc(), id.asc())),
+                    ItemSort::PriceAsc => db_query.order((price_cents.asc(), id.asc())),
+                    ItemSort::PriceDesc => db_query.order((price_cents.desc(), id.asc())),
+                    ItemSort::Popular => db_query.order((units_sold.desc(), id.asc())),
+                };
+
+                let page: Vec<(Item, Option<String>, f32)> = db_query
+                    .limit(limit)
+                    .select((
+                        Item::as_select(),
+                        search_snippet(search_term.as_deref(), mode),
+                        rank(),
+                    ))
+                    .load(con)
+                    .await?;
+                let next_cursor = match page.as_slice().last() {
+                    Some((item, _, item_rank)) if page.len() as i64 == limit => {
+                        Some(Cursor::at(item, *item_rank, sort).encode())
+                    }
+                    _ => None,
+                };
+                let (item_result, snippets): (Vec<Item>, Vec<Option<String>>) = page
+                    .into_iter()
+                    .map(|(item, snippet, _)| (item, snippet))
+                    .unzip();
+                let mut item_results = load_item_results(con, item_result).await?;
+                for (item_result, snippet) in item_results.iter_mut().zip(snippets) {
+                    item_result.snippet = snippet;
+                }
+
+                // Count items per category
+                let counts: HashMap<Option<i32>, i64> = items
+                    .filter(id.eq_any(filtered_items(mode).select(id)))
+                    .group_by(category_id)
+                    .select((category_id, diesel::dsl::count_star()))
+                    .load::<(Option<i32>, i64)>(con)
+                    .await?
+                    .into_iter()
+                    .collect();
+                let categories: Vec<CategoryFacet> = categories::table
+                    .order(categories::columns::name)
+                    .select(Category::as_select())
+                    .load(con)
+                    .await?
+                    .into_iter()
+                    .map(|category| CategoryFacet {
+                        item_count: counts.get(&Some(category.id)).copied().unwrap_or(0),
+                        category,
+                    })
+                    .collect();
+
+                Ok::<_, diesel::result::Error>((item_results, total, next_cursor, categories))
+            })
+        })
         .await
         .map_err(error::ErrorInternalServerError)?;
-    let attachments_result = Attachment::belonging_to(&item_result)
-        .select(Attachment::as_select())
-        .load(&mut con)
+
+    Ok(HttpResponse::Ok().json(ItemListResult {
+        items: item_results,
+        total,
+        next_cursor,
+        categories,
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+struct ItemDetail {
+    #[serde(flatten)]
+    item: ItemResult,
+    seller_username: String,
+}
+
+/// Gets a single item along with its seller and sales. Items out of stock
+/// and archived items can be fetched too, but deleted items can't. The
+/// response carries an ETag, and requests with a matching If-None-Match
+/// header are answered with 304 Not Modified.
+#[get("/item/{item_id}")]
+pub async fn get_item(
+    pool: web::Data<BB8Pool>,
+    item_id: web::Path<i32>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{items, users};
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let (item, seller_username) = items::table
+        .inner_join(users::table)
+        .filter(items::columns::id.eq(*item_id))
+        .filter(items::columns::deleted_at.is_null())
+        .select((Item::as_select(), users::columns::username))
+        .get_result::<(Item, String)>(&mut con)
         .await
-        .map_err(error::ErrorInternalServerError)?;
-    let attachments_per_item = attachments_result
-        .grouped_by(&item_result)
-        .into_iter()
-        .zip(item_result)
-        .map(|(attachments, item)| ItemResult { item, attachments })
-        .collect::<Vec<ItemResult>>();
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Item not found"))?;
+    let item = load_item_results(&mut con, vec![item])
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .pop()
+        .ok_or_else(|| error::ErrorInternalServerError("Item disappeared"))?;
+
+    // Tag the response by its contents, so that any change to the item,
+    // its attachments or its seller changes the tag
+    let body = serde_json::to_vec(&ItemDetail {
+        item,
+        seller_username,
+    })
+    .map_err(error::ErrorInternalServerError)?;
+    let etag = EntityTag::new_strong(blake3::hash(&body).to_string());
+    let cached = match IfNoneMatch::parse(&req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        Err(_) => false,
+    };
 
-    Ok(HttpResponse::Ok().json(attachments_per_item))
+    // Let clients cache the item, but have them check it is still current
+    let mut response = if cached {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, "no-cache"));
+    if cached {
+        return Ok(response.finish());
+    }
+    Ok(response.content_type("application/json").body(body))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -126,6 +638,13 @@ pub struct NewItemQuery {
     pub amount: usize,
     pub price: String,
     pub attachments: Vec<i32>,
+    #[serde(default)]
+    pub category_id: Option<i32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// EAN-13 or UPC-A barcode of the good
+    #[serde(default)]
+    pub barcode: Option<String>,
 }
 
 /// Parses string of form 1.23 to number like 123, see tests
@@ -146,6 +665,102 @@ fn parse_decimal_to_cents(string: String) -> Result<u32, ()> {
     Ok(cents)
 }
 
+/// Trims and validates an item title
+fn validate_title(item_title: &str) -> Result<String, Error> {
+    let item_title = item_title.trim().to_string();
+    if item_title.len() > MAX_TITLE_LENGTH {
+        return Err(error::ErrorBadRequest(format!(
+            "Title can be at most {MAX_TITLE_LENGTH} characters long"
+        )));
+    }
+    Ok(item_title)
+}
+
+/// Trims and validates an item description
+fn validate_description(item_description: &str) -> Result<String, Error> {
+    let item_description = item_description.trim().to_string();
+    if item_description.len() > MAX_DESCRIPTION_LENGTH {
+        return Err(error::ErrorBadRequest(format!(
+            "Description can be at most {MAX_DESCRIPTION_LENGTH} characters long"
+        )));
+    }
+    Ok(item_description)
+}
+
+/// Parses and validates an item price given in decimal format
+fn validate_price(price: &str) -> Result<i32, Error> {
+    let item_price_cents = parse_decimal_to_cents(price.to_string()).map_err(|_| {
+        error::ErrorBadRequest("Price must be in decimal format with cents, i.e 9.95")
+    })?;
+    if !(MIN_PRICE_CENTS..=MAX_PRICE_CENTS).contains(&item_price_cents) {
+        return Err(error::ErrorBadRequest(format!(
+            "Price must be at least {MIN_PRICE_CENTS} cents and at most {MAX_PRICE_CENTS} cents"
+        )));
+    }
+    Ok(item_price_cents as i32)
+}
+
+/// Lists items on sale with the given EAN-13 or UPC-A barcode, cheapest
+/// first. Items out of stock are listed too.
+#[get("/item/barcode/{barcode}")]
+pub async fn get_items_by_barcode(
+    pool: web::Data<BB8Pool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::items;
+
+    let item_barcode = validate_barcode(&path)?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let item_result = items::table
+        .filter(items::columns::barcode.eq(item_barcode))
+        .filter(items::columns::archived_at.is_null())
+        .order((items::columns::price_cents, items::columns::id))
+        .select(Item::as_select())
+        .load(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let item_results = load_item_results(&mut con, item_result)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(item_results))
+}
+
+/// Validates an EAN-13 or UPC-A barcode and normalizes it to EAN-13, of
+/// which UPC-A codes are the ones starting with zero
+pub fn validate_barcode(barcode: &str) -> Result<String, Error> {
+    let barcode = barcode.trim();
+    let barcode = match barcode.len() {
+        12 => format!("0{barcode}"),
+        13 => barcode.to_string(),
+        _ => {
+            return Err(error::ErrorBadRequest(
+                "Barcode must be a 13 digit EAN-13 or 12 digit UPC-A code",
+            ))
+        }
+    };
+    let digits: Vec<u32> = barcode
+        .chars()
+        .map(|c| c.to_digit(10))
+        .collect::<Option<_>>()
+        .ok_or_else(|| error::ErrorBadRequest("Barcode must only contain digits"))?;
+
+    // Digits are weighted alternately by 1 and 3, and the check digit pads
+    // their sum to a multiple of 10
+    let sum: u32 = digits[..12]
+        .iter()
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { *digit } else { digit * 3 })
+        .sum();
+    if (10 - sum % 10) % 10 != digits[12] {
+        return Err(error::ErrorBadRequest("Barcode has an invalid check digit"));
+    }
+    Ok(barcode)
+}
+
 /// Enlists a new item for sale.
 #[post("/item/new")]
 pub async fn new_item(
@@ -153,34 +768,15 @@ pub async fn new_item(
     query: web::Json<NewItemQuery>,
     session: Session,
 ) -> Result<HttpResponse, Error> {
-    use crate::schema::{attachments, items};
-
-    // Limits
-    const MAX_TITLE_LENGTH: usize = 50;
-    const MAX_DESCRIPTION_LENGTH: usize = 500;
-    const MAX_ITEM_AMOUNT: usize = 50;
-    const MAX_PRICE_CENTS: u32 = 15_00;
-    const MIN_PRICE_CENTS: u32 = 1;
-    const MAX_ATTACHMENTS: usize = 5;
+    use crate::schema::{attachments, item_tags, items};
 
     // Gather and validate input
 
     let user_id =
         get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
 
-    let item_title = query.title.trim().to_string();
-    if item_title.len() > MAX_TITLE_LENGTH {
-        return Err(error::ErrorBadRequest(format!(
-            "Title can be at most {MAX_TITLE_LENGTH} characters long"
-        )));
-    }
-
-    let item_description = query.description.trim().to_string();
-    if item_description.len() > MAX_DESCRIPTION_LENGTH {
-        return Err(error::ErrorBadRequest(format!(
-            "Description can be at most {MAX_DESCRIPTION_LENGTH} characters long"
-        )));
-    }
+    let item_title = validate_title(&query.title)?;
+    let item_description = validate_description(&query.description)?;
 
     let item_amount = query.amount;
     if !(1..MAX_ITEM_AMOUNT).contains(&item_amount) {
@@ -190,15 +786,9 @@ pub async fn new_item(
     }
     let item_amount = item_amount as i32;
 
-    let item_price_cents = parse_decimal_to_cents(query.price.clone()).map_err(|_| {
-        error::ErrorBadRequest("Price must be in decimal format with cents, i.e 9.95")
-    })?;
-    if !(MIN_PRICE_CENTS..=MAX_PRICE_CENTS).contains(&item_price_cents) {
-        return Err(error::ErrorBadRequest(format!(
-            "Price must be at least {MIN_PRICE_CENTS} cents and at most {MAX_PRICE_CENTS} cents"
-        )));
-    }
-    let item_price_cents = item_price_cents as i32;
+    let item_price_cents = validate_price(&query.price)?;
+    let item_tags = normalize_tags(&query.tags)?;
+    let item_barcode = query.barcode.as_deref().map(validate_barcode).transpose()?;
 
     // Deduplicate attachments
     let item_attachments: Vec<i32> = query.attachments.iter().unique().cloned().collect();
@@ -212,6 +802,10 @@ pub async fn new_item(
     // Aquire db connection hande only when needed, to avoid aquiring it for no use on bad user input
     let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
 
+    if let Some(category) = query.category_id {
+        validate_category(&mut con, category).await?;
+    }
+
     // Validate referenced attachments
     let referenced_attachments = attachments::table
         .select(Attachment::as_select())
@@ -230,149 +824,622 @@ pub async fn new_item(
         return Err(error::ErrorBadRequest(format!("Following attachments could not be used: {missing_attachments}. Try uploading them again.")));
     }
 
-    // Insert item into db
-    let item = diesel::insert_into(items::table)
-        .values((
-            items::columns::title.eq(item_title),
-            items::columns::description.eq(item_description),
-            items::columns::amount.eq(item_amount),
-            items::columns::price_cents.eq(item_price_cents),
-            items::columns::seller_id.eq(user_id),
-            items::columns::created_at.eq(chrono::offset::Utc::now()),
-        ))
-        .returning(Item::as_returning())
-        .get_result(&mut con)
+    // Insert item into db along with its attachments and tags, so that a
+    // failure can't leave a half made item behind
+    let item_category = query.category_id;
+    let (item, attachments, tags) = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                let item = diesel::insert_into(items::table)
+                    .values((
+                        items::columns::title.eq(item_title),
+                        items::columns::description.eq(item_description),
+                        items::columns::amount.eq(item_amount),
+                        items::columns::price_cents.eq(item_price_cents),
+                        items::columns::seller_id.eq(user_id),
+                        items::columns::created_at.eq(chrono::offset::Utc::now()),
+                        items::columns::category_id.eq(item_category),
+                        items::columns::barcode.eq(item_barcode),
+                    ))
+                    .returning(Item::as_returning())
+                    .get_result(con)
+                    .await?;
+
+                // Reference attachments to item
+                let attachments: Vec<Attachment> = diesel::update(attachments::table)
+                    .filter(attachments::columns::id.eq_any(item_attachments))
+                    .set(attachments::columns::item_id.eq(item.id))
+                    .get_results(con)
+                    .await?;
+
+                // Tag item
+                let tags: Vec<String> = diesel::insert_into(item_tags::table)
+                    .values(
+                        item_tags
+                            .iter()
+                            .map(|tag| {
+                                (
+                                    item_tags::columns::item_id.eq(item.id),
+                                    item_tags::columns::tag.eq(tag),
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .returning(item_tags::columns::tag)
+                    .get_results(con)
+                    .await?;
+
+                Ok::<_, diesel::result::Error>((item, attachments, tags))
+            })
+        })
         .await
         .map_err(error::ErrorInternalServerError)?;
 
-    // Reference attachments to item
-    let attachments = diesel::update(attachments::table)
-        .filter(attachments::columns::id.eq_any(item_attachments))
-        .set(attachments::columns::item_id.eq(item.id))
-        .get_results(&mut con)
+    Ok(HttpResponse::Ok().json(ItemResult {
+        attachments,
+        item,
+        tags,
+        snippet: None,
+    }))
+}
+
+/// Fetches an item the user is allowed to manage. Sellers can manage their
+/// own items, and users with the role `P` every item. Deleted items can't be
+/// managed.
+async fn get_managed_item<P: Permission>(
+    con: &mut AsyncPgConnection,
+    uid: i32,
+    item_id: i32,
+    manager: &Option<Authorized<P>>,
+) -> Result<Item, Error> {
+    use crate::schema::items;
+
+    let item = items::table
+        .filter(items::columns::id.eq(item_id))
+        .filter(items::columns::deleted_at.is_null())
+        .select(Item::as_select())
+        .get_result(con)
         .await
-        .map_err(error::ErrorInternalServerError)?;
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("Item not found"))?;
+    if item.seller_id != uid && manager.is_none() {
+        return Err(error::ErrorForbidden("You can only manage your own items"));
+    }
+    Ok(item)
+}
+
+/// Fields of an item changed by an edit. Fields left out are not changed.
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::items)]
+struct ItemChanges {
+    title: Option<String>,
+    description: Option<String>,
+    price_cents: Option<i32>,
+    category_id: Option<Option<i32>>,
+    barcode: Option<Option<String>>,
+}
 
-    Ok(HttpResponse::Ok().json(ItemResult { attachments, item }))
+/// Deserializes a field which can be left out, so that `null` is told apart
+/// from the field missing
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Serialize, Deserialize)]
-struct BuyQuery {
-    item_id: i32,
-    amount: Option<i32>,
+pub struct EditItemQuery {
+    pub item_id: i32,
+    /// Version of the item the edit was based on
+    pub version: i32,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub price: Option<String>,
+    /// Null removes the item from its category
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub category_id: Option<Option<i32>>,
+    /// Replaces every tag of the item when given
+    pub tags: Option<Vec<String>>,
+    /// Empty barcode removes the barcode of the item
+    #[serde(default)]
+    pub barcode: Option<String>,
 }
 
-/// Buys an item.
-#[post("/item/buy")]
-pub async fn buy_item(
+/// Edits the title, description, price, category, tags or barcode of an item. The edit is refused
+/// with 409 Conflict if the item has been edited since the given version,
+/// in which case the client should reload the item and try again.
+#[post("/item/edit")]
+pub async fn edit_item(
     pool: web::Data<BB8Pool>,
-    query: web::Json<BuyQuery>,
+    query: web::Json<EditItemQuery>,
     session: Session,
+    moderator: Option<Authorized<Moderator>>,
 ) -> Result<HttpResponse, Error> {
-    use crate::schema::{items, transactions, users};
+    use crate::schema::{item_tags, items};
 
     // Gather and validate input
-    let buyer_id =
+    let user_id =
         get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    let changes = ItemChanges {
+        title: query.title.as_deref().map(validate_title).transpose()?,
+        description: query
+            .description
+            .as_deref()
+            .map(validate_description)
+            .transpose()?,
+        price_cents: query.price.as_deref().map(validate_price).transpose()?,
+        category_id: query.category_id,
+        barcode: match query.barcode.as_deref().map(str::trim) {
+            Some("") => Some(None),
+            Some(barcode) => Some(Some(validate_barcode(barcode)?)),
+            None => None,
+        },
+    };
+    let new_tags = query.tags.as_deref().map(normalize_tags).transpose()?;
     let item_id = query.item_id;
-    let item_amount = query.amount.unwrap_or(1);
+    let item_version = query.version;
 
-    // Aquire db connection handle
     let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    let item = get_managed_item(&mut con, user_id, item_id, &moderator).await?;
+    if item.archived_at.is_some() {
+        return Err(error::ErrorBadRequest("Item is archived"));
+    }
+    if let Some(Some(category)) = changes.category_id {
+        validate_category(&mut con, category).await?;
+    }
 
-    // Run the whole buy operation inside a transaction to prevent double spending
-    let result: Result<Result<(), &str>, diesel::result::Error> = con
+    // Only update if nobody else has edited the item meanwhile
+    let item: Option<Item> = con
         .transaction(move |con| {
             Box::pin(async move {
-                // Fetch item from db and perform checks
-                let items = items::table
+                let item = diesel::update(items::table)
                     .filter(items::columns::id.eq(item_id))
-                    .select(Item::as_select())
-                    .load(con)
-                    .await?;
-                let item = match &items[..] {
-                    [item] => item,
-                    _ => return Ok(Err("Item not found")),
-                };
-                if item.amount < item_amount {
-                    return Ok(Err("Not enough item in stock"));
-                }
-                let total_price = item_amount * item.price_cents;
-
-                // Same for both parties of the transaction
-                let users = users::table
-                    .filter(users::columns::id.eq(buyer_id))
-                    .select(User::as_select())
-                    .load(con)
-                    .await?;
-                let user = match &users[..] {
-                    [user] => user,
-                    _ => return Ok(Err("Your user does not exist")), // Weird but possible using 2 sessions and deleting users account from one
-                };
-                if user.balance_cents < total_price {
-                    return Ok(Err("You don't have enough balance on your account"));
-                }
-                let seller_id = item.seller_id; // Relation guarantees that the seller exists if the item referring to it does
-
-                // All checks ok, make the transaction
-                try_join!(
-                    // Remove items from stock
-                    diesel::update(items::table)
-                        .filter(items::columns::id.eq(item_id))
-                        .set(items::columns::amount.eq(items::columns::amount - item_amount))
-                        .execute(con),
-                    // Remove balance from the buyers account
-                    diesel::update(users::table)
-                        .filter(users::columns::id.eq(buyer_id))
-                        .set(
-                            users::columns::balance_cents
-                                .eq(users::columns::balance_cents - total_price)
+                    .filter(items::columns::version.eq(item_version))
+                    .filter(items::columns::archived_at.is_null())
+                    .set((
+                        changes,
+                        items::columns::version.eq(items::columns::version + 1),
+                    ))
+                    .returning(Item::as_returning())
+                    .get_result(con)
+                    .await
+                    .optional()?;
+                if let (Some(item), Some(new_tags)) = (&item, new_tags) {
+                    diesel::delete(item_tags::table)
+                        .filter(item_tags::columns::item_id.eq(item.id))
+                        .execute(con)
+                        .await?;
+                    diesel::insert_into(item_tags::table)
+                        .values(
+                            new_tags
+                                .iter()
+                                .map(|tag| {
+                                    (
+                                        item_tags::columns::item_id.eq(item.id),
+                                        item_tags::columns::tag.eq(tag),
+                                    )
+                                })
+                                .collect::<Vec<_>>(),
                         )
-                        .execute(con),
-                    // Append balance to the sellers account
-                    diesel::update(users::table)
-                        .filter(users::columns::id.eq(seller_id))
-                        .set(
-                            users::columns::balance_cents
-                                .eq(users::columns::balance_cents + total_price)
-                        )
-                        .execute(con),
-                    // Log transaction
-                    diesel::insert_into(transactions::table)
-                        .values((
-                            transactions::columns::item_id.eq(item_id),
-                            transactions::columns::receiver_id.eq(seller_id), 
-                            transactions::columns::payer_id.eq(buyer_id),
-                            transactions::columns::item_amount.eq(item_amount),
-                            transactions::columns::amount_cents.eq(total_price),
-                            transactions::columns::transacted_at.eq(chrono::offset::Utc::now()),
-                        ))
-                        .execute(con),
-                )?;
-
-                Ok(Ok(()))
+                        .execute(con)
+                        .await?;
+                }
+                Ok::<_, diesel::result::Error>(item)
             })
         })
-        .await;
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    let item = item.ok_or_else(|| {
+        error::ErrorConflict("Item has been changed meanwhile, reload it and try again")
+    })?;
+    let item_result = load_item_results(&mut con, vec![item])
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .pop();
 
-    // Propagate errors from transaction
-    result
-        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
-        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+    Ok(HttpResponse::Ok().json(item_result))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StockQuery {
+    pub item_id: i32,
+    /// Amount added to stock, negative to remove stock
+    pub change: i32,
+}
+
+/// Adds or removes stock of an item. Stock is changed relative to its
+/// current amount, so that it can't overwrite purchases made meanwhile.
+/// Kiosk managers can restock every item, not just their own.
+#[post("/item/stock")]
+pub async fn change_stock(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<StockQuery>,
+    session: Session,
+    kiosk_manager: Option<Authorized<KioskManager>>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::items;
+
+    let user_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    if query.change == 0 || query.change.unsigned_abs() as usize > MAX_ITEM_AMOUNT {
+        return Err(error::ErrorBadRequest(format!(
+            "Change must be nonzero and at most {MAX_ITEM_AMOUNT} in either direction"
+        )));
+    }
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    let item = get_managed_item(&mut con, user_id, query.item_id, &kiosk_manager).await?;
+    if item.archived_at.is_some() {
+        return Err(error::ErrorBadRequest("Item is archived"));
+    }
+
+    // Check the resulting stock within the update, so that parallel changes
+    // and purchases can't push it out of bounds
+    let new_amount = items::columns::amount + query.change;
+    let item = diesel::update(items::table)
+        .filter(items::columns::id.eq(query.item_id))
+        .filter(items::columns::archived_at.is_null())
+        .filter(new_amount.ge(0))
+        .filter(new_amount.le(MAX_ITEM_AMOUNT as i32))
+        .set(items::columns::amount.eq(new_amount))
+        .returning(Item::as_returning())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| {
+            error::ErrorBadRequest(format!(
+                "Stock must stay at least 0 and at most {MAX_ITEM_AMOUNT}"
+            ))
+        })?;
+    let item_result = load_item_results(&mut con, vec![item])
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .pop();
+
+    Ok(HttpResponse::Ok().json(item_result))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveItemQuery {
+    pub item_id: i32,
+}
+
+/// Unlists an item. Archived items are hidden from listings and can't be
+/// bought, but remain in the transaction history.
+#[post("/item/archive")]
+pub async fn archive_item(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<ArchiveItemQuery>,
+    session: Session,
+    moderator: Option<Authorized<Moderator>>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::items;
+
+    let user_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    get_managed_item(&mut con, user_id, query.item_id, &moderator).await?;
+
+    diesel::update(items::table)
+        .filter(items::columns::id.eq(query.item_id))
+        .filter(items::columns::archived_at.is_null())
+        .set(items::columns::archived_at.eq(chrono::offset::Utc::now()))
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+/// Deletes an item for good. The item is kept in the database so that
+/// transactions referring to it can still be resolved, but it can't be
+/// listed, bought or managed anymore. Attachments of deleted items are
+/// removed by cron like those of archived items.
+#[post("/item/delete")]
+pub async fn delete_item(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<ArchiveItemQuery>,
+    session: Session,
+    moderator: Option<Authorized<Moderator>>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::items;
+
+    let user_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    let item = get_managed_item(&mut con, user_id, query.item_id, &moderator).await?;
+
+    // Deleting also archives, keeping the original archival time if there is one
+    let now = chrono::offset::Utc::now();
+    diesel::update(items::table)
+        .filter(items::columns::id.eq(query.item_id))
+        .filter(items::columns::deleted_at.is_null())
+        .set((
+            items::columns::deleted_at.eq(now),
+            items::columns::archived_at.eq(item.archived_at.map_or(now, |at| at.to_utc())),
+        ))
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BuyQuery {
+    pub item_id: i32,
+    pub amount: Option<i32>,
+}
+
+/// Buys an item. Repeats of a request sent with the same `Idempotency-Key`
+/// header are only carried out once.
+#[post("/item/buy")]
+pub async fn buy_item(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<BuyQuery>,
+    session: Session,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let buyer_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    idempotency::run(&pool, &req, buyer_id, &*query, |con| {
+        buy(con, &query, buyer_id).scope_boxed_local()
+    })
+    .await
+}
+
+async fn buy(
+    con: &mut AsyncPgConnection,
+    query: &BuyQuery,
+    buyer_id: i32,
+) -> Result<HttpResponse, Error> {
+    // Gather and validate input
+    let item_id = query.item_id;
+    let item_amount = query.amount.unwrap_or(1);
+    if item_amount <= 0 {
+        return Err(error::ErrorBadRequest("Amount must be positive"));
+    }
+
+    // Run the whole buy operation inside a transaction to prevent double spending
+    let result = con
+        .transaction(move |con| {
+            Box::pin(async move { purchase(con, buyer_id, &[(item_id, item_amount)]).await })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
 
     // If we got here the transaction was a success
     Ok(HttpResponse::Ok().body("OK"))
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ScanBuyQuery {
+    pub barcode: String,
+    pub amount: Option<i32>,
+}
+
+/// Buys the cheapest item on sale with the scanned barcode which has enough
+/// stock available, and returns the logged purchase. Repeats of a request sent
+/// with the same `Idempotency-Key` header are only carried out once.
+#[post("/item/barcode/buy")]
+pub async fn scan_to_buy(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<ScanBuyQuery>,
+    session: Session,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let buyer_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    idempotency::run(&pool, &req, buyer_id, &*query, |con| {
+        buy_scanned(con, &query, buyer_id).scope_boxed_local()
+    })
+    .await
+}
+
+async fn buy_scanned(
+    con: &mut AsyncPgConnection,
+    query: &ScanBuyQuery,
+    buyer_id: i32,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::items;
+
+    // Gather and validate input
+    let item_barcode = validate_barcode(&query.barcode)?;
+    let item_amount = query.amount.unwrap_or(1);
+    if item_amount <= 0 {
+        return Err(error::ErrorBadRequest("Amount must be positive"));
+    }
+
+    let result = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                // Lock every listing of the good, so that the cheapest one
+                // can't sell out before buying it
+                let listings = items::table
+                    .filter(items::columns::barcode.eq(item_barcode))
+                    .filter(items::columns::archived_at.is_null())
+                    .order(items::columns::id)
+                    .select(Item::as_select())
+                    .for_update()
+                    .load(con)
+                    .await?;
+                let listing_ids: Vec<i32> = listings.iter().map(|item| item.id).collect();
+                let now = chrono::offset::Utc::now();
+                let reserved = reserved_amounts(con, &listing_ids, buyer_id, now).await?;
+                let cheapest = listings
+                    .iter()
+                    .filter(|item| {
+                        let reserved_amount = reserved.get(&item.id).copied().unwrap_or(0);
+                        i64::from(item.amount) - reserved_amount >= i64::from(item_amount)
+                    })
+                    .min_by_key(|item| (item.price_cents, item.id));
+                let Some(cheapest) = cheapest else {
+                    return Ok(Err(if listings.is_empty() {
+                        "Item not found"
+                    } else {
+                        "Not enough item in stock"
+                    }));
+                };
+
+                purchase(con, buyer_id, &[(cheapest.id, item_amount)]).await
+            })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    let purchases = result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    Ok(HttpResponse::Ok().json(purchases.as_slice().first()))
+}
+
+/// Buys given `(item_id, amount)` lines for the buyer, logging a purchase
+/// transaction for each of them. Has to be run inside of a transaction.
+/// Stock reserved in other users carts can't be bought. Either every line is
+/// bought or, if any of the checks fails, nothing is changed and the reason
+/// is returned.
+pub async fn purchase(
+    con: &mut AsyncPgConnection,
+    buyer_id: i32,
+    lines: &[(i32, i32)],
+) -> QueryResult<Result<Vec<Transaction>, &'static str>> {
+    use crate::schema::{items, transactions, users};
+
+    // Merge lines of the same item
+    let mut lines = lines.to_vec();
+    lines.sort_unstable();
+    lines.dedup_by(|line, kept| {
+        let same_item = line.0 == kept.0;
+        if same_item {
+            kept.1 += line.1;
+        }
+        same_item
+    });
+    let item_ids: Vec<i32> = lines.iter().map(|(item_id, _)| *item_id).collect();
+
+    // Fetch items from db and perform checks. The items are locked in id
+    // order until the end of the transaction, so that parallel buys can't
+    // both pass the stock check nor deadlock each other.
+    let locked_items = items::table
+        .filter(items::columns::id.eq_any(&item_ids))
+        .filter(items::columns::archived_at.is_null())
+        .order(items::columns::id)
+        .select(Item::as_select())
+        .for_update()
+        .load(con)
+        .await?;
+    if locked_items.len() != lines.len() {
+        return Ok(Err("Item not found"));
+    }
+    let now = chrono::offset::Utc::now();
+    let reserved = reserved_amounts(con, &item_ids, buyer_id, now).await?;
+
+    let mut total_price = 0;
+    let mut seller_incomes: HashMap<i32, i32> = HashMap::new();
+    for (item, (_, item_amount)) in locked_items.iter().zip(&lines) {
+        if *item_amount <= 0 {
+            return Ok(Err("Amount must be positive"));
+        }
+        let reserved_amount = reserved.get(&item.id).copied().unwrap_or(0);
+        if i64::from(item.amount) - reserved_amount < i64::from(*item_amount) {
+            return Ok(Err("Not enough item in stock"));
+        }
+        let price = item_amount * item.price_cents;
+        total_price += price;
+        // Relation guarantees that the seller exists if the item referring to it does
+        *seller_incomes.entry(item.seller_id).or_default() += price;
+    }
+
+    // Same for all parties of the transaction
+    let mut user_ids: Vec<i32> = seller_incomes.keys().copied().collect();
+    user_ids.push(buyer_id);
+    let users = lock_users(con, &user_ids).await?;
+    let user = match users.iter().find(|user| user.id == buyer_id) {
+        Some(user) => user,
+        None => return Ok(Err("Your user does not exist")), // Weird but possible using 2 sessions and deleting users account from one
+    };
+    if user.spendable_cents() < total_price as i64 {
+        return Ok(Err("You don't have enough balance on your account"));
+    }
+
+    // All checks ok, make the transaction
+    for (item_id, item_amount) in &lines {
+        // Remove items from stock and count them as sold
+        diesel::update(items::table)
+            .filter(items::columns::id.eq(item_id))
+            .set((
+                items::columns::amount.eq(items::columns::amount - item_amount),
+                items::columns::units_sold.eq(items::columns::units_sold + item_amount),
+                items::columns::last_sold_at.eq(now),
+            ))
+            .execute(con)
+            .await?;
+    }
+    // Remove balance from the buyers account
+    diesel::update(users::table)
+        .filter(users::columns::id.eq(buyer_id))
+        .set(users::columns::balance_cents.eq(users::columns::balance_cents - total_price))
+        .execute(con)
+        .await?;
+    // Append balance to the sellers accounts
+    for (seller_id, income) in &seller_incomes {
+        diesel::update(users::table)
+            .filter(users::columns::id.eq(seller_id))
+            .set(users::columns::balance_cents.eq(users::columns::balance_cents + income))
+            .execute(con)
+            .await?;
+    }
+    // Log transactions
+    let rows: Vec<_> = locked_items
+        .iter()
+        .zip(&lines)
+        .map(|(item, (_, item_amount))| {
+            (
+                transactions::columns::item_id.eq(item.id),
+                transactions::columns::receiver_id.eq(item.seller_id),
+                transactions::columns::payer_id.eq(buyer_id),
+                transactions::columns::item_amount.eq(*item_amount),
+                transactions::columns::amount_cents.eq(item_amount * item.price_cents),
+                transactions::columns::transacted_at.eq(now),
+                transactions::columns::kind.eq(TransactionKind::Purchase.name()),
+            )
+        })
+        .collect();
+    let logged = diesel::insert_into(transactions::table)
+        .values(rows)
+        .returning(Transaction::as_returning())
+        .get_results(con)
+        .await?;
+
+    Ok(Ok(logged))
+}
+
 #[cfg(test)]
 mod tests {
     use reqwest::Result;
     use std::sync::Arc;
 
-    use crate::api::{admin::AdminGiveQuery, user::UserQuery};
+    use crate::api::admin::{AdminGiveQuery, DeleteCategoryQuery, NewCategoryQuery};
+    use crate::api::transactions::{AdjustmentKind, HistoryResult};
+    use crate::api::user::UserQuery;
 
     use super::*;
-    const URL: &str = "http://backend:3030";
+    const URL: &str = "http://localhost:3030";
 
     // Currency parsing tests
     #[test]
@@ -389,6 +1456,28 @@ mod tests {
         assert_eq!(parse_decimal_to_cents("-5.14,".to_string()), Err(()));
     }
 
+    #[test]
+    fn barcode_validation_works() {
+        assert_eq!(
+            validate_barcode("4006381333931").ok(),
+            Some("4006381333931".to_string())
+        );
+        assert_eq!(
+            validate_barcode(" 6414893400016 ").ok(),
+            Some("6414893400016".to_string())
+        );
+        assert_eq!(
+            validate_barcode("036000291452").ok(),
+            Some("0036000291452".to_string())
+        );
+
+        assert!(validate_barcode("4006381333932").is_err());
+        assert!(validate_barcode("036000291453").is_err());
+        assert!(validate_barcode("40063813339").is_err());
+        assert!(validate_barcode("400638133393a").is_err());
+        assert!(validate_barcode("").is_err());
+    }
+
     // Test selling and buying
     #[test]
     fn item_operations() -> Result<()> {
@@ -407,7 +1496,7 @@ mod tests {
         assert_eq!(
             result.status(),
             200,
-            "Could not clear db. Make sure the server is compiled in debug mode."
+            "Could not clear db. Make sure the server is running in development mode."
         );
 
         // Register test users and log them in to their clients
@@ -438,6 +1527,9 @@ mod tests {
                 amount: 3,
                 price: "1.11".to_string(),
                 attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
             })
             .send()?;
         let item: Item = result.json()?;
@@ -451,6 +1543,9 @@ mod tests {
                 amount: 1,
                 price: "2,5".to_string(),
                 attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
             })
             .send()?;
         let item2: Item = result.json()?;
@@ -471,6 +1566,8 @@ mod tests {
             .json(&AdminGiveQuery {
                 user_id: Some(item.seller_id),
                 amount_cents: 333,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
             })
             .send()?;
         assert_eq!(result.status(), 200, "Could not add balance to user");
@@ -480,6 +1577,8 @@ mod tests {
             .json(&AdminGiveQuery {
                 user_id: Some(item2.seller_id),
                 amount_cents: 250,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
             })
             .send()?;
         assert_eq!(result.status(), 200, "Could not add balance to user");
@@ -494,7 +1593,7 @@ mod tests {
 
         // Item listing
         let result = client.post(format!("{URL}/api/item/list")).send()?;
-        let items: Vec<Item> = result.json()?;
+        let items = result.json::<ItemListResult>()?.items;
         assert_eq!(
             items.len(),
             2,
@@ -506,13 +1605,11 @@ mod tests {
             .post(format!("{URL}/api/item/list"))
             .json(&ItemQuery {
                 search_term: Some("best".to_string()),
-                limit: None,
-                offset: None,
-                get_items_without_stock: None,
+                ..Default::default()
             })
             .send()?;
-        let items: Vec<Item> = result.json()?;
-        let got_item = &items[0];
+        let items = result.json::<ItemListResult>()?.items;
+        let got_item = &items[0].item;
         assert_eq!(*got_item, item2);
 
         // Item buying
@@ -553,4 +1650,1121 @@ mod tests {
 
         Ok(())
     }
+
+    // Test editing, restocking and archiving items
+    #[test]
+    fn item_management() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register a test user and sell an item
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+
+        let result = client
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "test item".to_string(),
+                description: "test description".to_string(),
+                amount: 3,
+                price: "1.11".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+        assert_eq!(item.version, 1, "New item has unexpected version");
+
+        // Edit price and title, leaving description as is
+        let edit = |version, title: &str, price: &str| {
+            client
+                .post(format!("{URL}/api/item/edit"))
+                .json(&EditItemQuery {
+                    item_id: item.id,
+                    version,
+                    title: Some(title.to_string()),
+                    description: None,
+                    price: Some(price.to_string()),
+                    category_id: None,
+                    tags: None,
+                    barcode: None,
+                })
+                .send()
+        };
+        let result = edit(1, "edited item", "0.99")?;
+        assert_eq!(result.status(), 200, "Could not edit item");
+        let edited: Item = result.json()?;
+        assert_eq!(edited.title, "edited item");
+        assert_eq!(edited.description, "test description");
+        assert_eq!(edited.price_cents, 99);
+        assert_eq!(edited.version, 2, "Edit didn't bump version");
+
+        // Edits based on a stale version are refused
+        let result = edit(1, "stale edit", "0.50")?;
+        assert_eq!(result.status(), 409, "Stale edit was not refused");
+
+        // Limits of new items apply to edits
+        let result = edit(2, "edited item", "100")?;
+        assert_eq!(
+            result.status(),
+            400,
+            "Edit with too high price was accepted"
+        );
+        let result = edit(2, &"a".repeat(MAX_TITLE_LENGTH + 1), "1")?;
+        assert_eq!(
+            result.status(),
+            400,
+            "Edit with too long title was accepted"
+        );
+
+        // Restock and remove stock
+        let stock = |change| {
+            client
+                .post(format!("{URL}/api/item/stock"))
+                .json(&StockQuery {
+                    item_id: item.id,
+                    change,
+                })
+                .send()
+        };
+        let result = stock(5)?;
+        assert_eq!(result.status(), 200, "Could not add stock");
+        let restocked: Item = result.json()?;
+        assert_eq!(restocked.amount, 8);
+        assert_eq!(restocked.version, 2, "Stock change bumped version");
+        let result = stock(-2)?;
+        let restocked: Item = result.json()?;
+        assert_eq!(restocked.amount, 6, "Could not remove stock");
+        let result = stock(-7)?;
+        assert_eq!(result.status(), 400, "Stock went below zero");
+
+        // Other users can restock and edit the item through their roles,
+        // which development mode grants to everyone
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+        let result = client2
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test2".to_string(),
+                password: "test2".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+        let result = client2
+            .post(format!("{URL}/api/item/stock"))
+            .json(&StockQuery {
+                item_id: item.id,
+                change: 1,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Kiosk manager could not add stock");
+        let restocked: Item = result.json()?;
+        assert_eq!(restocked.amount, 7);
+        let result = client2
+            .post(format!("{URL}/api/item/edit"))
+            .json(&EditItemQuery {
+                item_id: item.id,
+                version: 2,
+                title: None,
+                description: None,
+                price: Some("1".to_string()),
+                category_id: None,
+                tags: None,
+                barcode: None,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Moderator could not edit item");
+
+        // Archived items are hidden from listings and can't be bought or changed
+        let result = client
+            .post(format!("{URL}/api/item/archive"))
+            .json(&ArchiveItemQuery { item_id: item.id })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not archive item");
+
+        let result = client
+            .post(format!("{URL}/api/item/list"))
+            .json(&ItemQuery {
+                get_items_without_stock: Some(true),
+                ..Default::default()
+            })
+            .send()?;
+        let items = result.json::<ItemListResult>()?.items;
+        assert!(items.is_empty(), "Archived item was listed");
+
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                user_id: None,
+                amount_cents: 500,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add balance to user");
+        let result = client
+            .post(format!("{URL}/api/item/buy"))
+            .json(&BuyQuery {
+                item_id: item.id,
+                amount: Some(1),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Archived item was bought");
+
+        let result = edit(2, "archived item", "1")?;
+        assert_eq!(result.status(), 400, "Archived item was edited");
+        let result = stock(1)?;
+        assert_eq!(result.status(), 400, "Archived item was restocked");
+
+        Ok(())
+    }
+
+    // Test that archived and deleted items stay resolvable from history
+    #[test]
+    fn item_deletion() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users, sell an item and buy one of it
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        let result = client
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "test item".to_string(),
+                description: "test description".to_string(),
+                amount: 3,
+                price: "1".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+        let result = client2
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                user_id: None,
+                amount_cents: 100,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add balance to user");
+        let result = client2
+            .post(format!("{URL}/api/item/buy"))
+            .json(&BuyQuery {
+                item_id: item.id,
+                amount: Some(1),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not buy an item");
+
+        // Archived items can be listed on request
+        let result = client
+            .post(format!("{URL}/api/item/archive"))
+            .json(&ArchiveItemQuery { item_id: item.id })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not archive item");
+        let list = |include_archived| {
+            client
+                .post(format!("{URL}/api/item/list"))
+                .json(&ItemQuery {
+                    include_archived: Some(include_archived),
+                    ..Default::default()
+                })
+                .send()?
+                .json::<ItemListResult>()
+                .map(|list| list.items)
+        };
+        assert!(list(false)?.is_empty(), "Archived item was listed");
+        let items = list(true)?;
+        assert_eq!(items.len(), 1, "Archived item was not listed on request");
+        assert!(items[0].item.archived_at.is_some());
+
+        // Deleted items are not listed at all and can't be managed
+        let result = client
+            .post(format!("{URL}/api/item/delete"))
+            .json(&ArchiveItemQuery { item_id: item.id })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not delete item");
+        assert!(list(true)?.is_empty(), "Deleted item was listed");
+        let result = client
+            .post(format!("{URL}/api/item/delete"))
+            .json(&ArchiveItemQuery { item_id: item.id })
+            .send()?;
+        assert_eq!(result.status(), 400, "Item was deleted twice");
+
+        // The purchase still refers to the item
+        let result = client2.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        assert_eq!(history.entries[0].transaction.item_id, Some(item.id));
+        assert_eq!(
+            history.entries[0].item_title.as_deref(),
+            Some("test item"),
+            "Deleted item was not resolved in history"
+        );
+
+        Ok(())
+    }
+
+    // Test categories, tags and filtering by them
+    #[test]
+    fn item_categories() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register a test user
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+
+        // Create categories
+        let new_category = |name: &str| {
+            client
+                .post(format!("{URL}/api/admin/category/new"))
+                .json(&NewCategoryQuery {
+                    name: name.to_string(),
+                })
+                .send()
+        };
+        let drinks: Category = new_category("drinks")?.json()?;
+        let snacks: Category = new_category("snacks")?.json()?;
+        let result = new_category("drinks")?;
+        assert_eq!(result.status(), 400, "Duplicate category was created");
+
+        // Sell items with categories and tags
+        let sell = |title: &str, category_id, tags: &[&str]| {
+            client
+                .post(format!("{URL}/api/item/new"))
+                .json(&NewItemQuery {
+                    title: title.to_string(),
+                    description: "test description".to_string(),
+                    amount: 3,
+                    price: "1".to_string(),
+                    attachments: Vec::new(),
+                    category_id,
+                    tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                    barcode: None,
+                })
+                .send()
+        };
+        let result = sell("cola", Some(drinks.id), &["Sugar", " fizzy ", "sugar"])?;
+        let cola: ItemResult = result.json()?;
+        assert_eq!(
+            cola.tags,
+            vec!["fizzy", "sugar"],
+            "Tags were not normalized"
+        );
+        let result = sell("water", Some(drinks.id), &["fizzy"])?;
+        let water: ItemResult = result.json()?;
+        sell("chips", Some(snacks.id), &["salty"])?;
+        sell("mug", None, &[])?;
+
+        let result = sell("cola", Some(-1), &[])?;
+        assert_eq!(result.status(), 400, "Item was sold in a missing category");
+        let too_many_tags: Vec<String> = (0..=MAX_TAGS).map(|i| i.to_string()).collect();
+        let too_many_tags: Vec<&str> = too_many_tags.iter().map(String::as_str).collect();
+        let result = sell("cola", None, &too_many_tags)?;
+        assert_eq!(result.status(), 400, "Item was sold with too many tags");
+
+        // Filter by category and tags
+        let list = |category_id, tags: &[&str]| {
+            client
+                .post(format!("{URL}/api/item/list"))
+                .json(&ItemQuery {
+                    category_id,
+                    tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+                    ..Default::default()
+                })
+                .send()?
+                .json::<ItemListResult>()
+        };
+        let listed = list(None, &[])?;
+        assert_eq!(listed.items.len(), 4);
+        let listed = list(Some(drinks.id), &[])?;
+        assert_eq!(listed.items.len(), 2, "Category filter didn't work");
+        let listed = list(Some(drinks.id), &["FIZZY", "sugar"])?;
+        assert_eq!(listed.items.len(), 1, "Tag filter didn't work");
+        assert_eq!(listed.items[0].item.id, cola.item.id);
+
+        // Counts ignore the category filter, but not others
+        let listed = list(Some(snacks.id), &["fizzy"])?;
+        assert!(listed.items.is_empty());
+        let counts: Vec<(String, i64)> = listed
+            .categories
+            .into_iter()
+            .map(|facet| (facet.category.name, facet.item_count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("drinks".to_string(), 2), ("snacks".to_string(), 0)],
+            "Category counts are off"
+        );
+
+        // Replace tags by editing
+        let result = client
+            .post(format!("{URL}/api/item/edit"))
+            .json(&EditItemQuery {
+                item_id: water.item.id,
+                version: water.item.version,
+                title: None,
+                description: None,
+                price: None,
+                category_id: None,
+                tags: Some(vec!["still".to_string()]),
+                barcode: None,
+            })
+            .send()?;
+        let edited: ItemResult = result.json()?;
+        assert_eq!(edited.tags, vec!["still"], "Could not replace tags");
+        assert_eq!(list(None, &["fizzy"])?.items.len(), 1);
+
+        // Edits can take an item out of its category, leaving out fields
+        // leaves them as they are
+        let result = client
+            .post(format!("{URL}/api/item/edit"))
+            .json(&EditItemQuery {
+                item_id: water.item.id,
+                version: edited.item.version,
+                title: None,
+                description: None,
+                price: None,
+                category_id: Some(None),
+                tags: None,
+                barcode: None,
+            })
+            .send()?;
+        let edited: ItemResult = result.json()?;
+        assert_eq!(edited.item.category_id, None, "Could not clear category");
+        assert_eq!(edited.tags, vec!["still"], "Clearing category changed tags");
+        assert_eq!(list(Some(drinks.id), &[])?.items.len(), 1);
+
+        // Deleting a category leaves its items without one
+        let result = client
+            .post(format!("{URL}/api/admin/category/delete"))
+            .json(&DeleteCategoryQuery {
+                category_id: drinks.id,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not delete category");
+        let listed = list(None, &["still"])?;
+        assert_eq!(listed.items[0].item.category_id, None);
+        assert_eq!(listed.categories.len(), 1);
+
+        Ok(())
+    }
+
+    // Test full text and fuzzy searching
+    #[test]
+    fn item_search() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register a test user and sell items
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+        for (title, description) in [
+            ("Kahvi", "Tuoretta kahvia pannusta"),
+            ("Cola", "Fizzy drink"),
+            ("Chips", "Goes well with a cola"),
+            ("Green tea", "Straight from Japan & more <3"),
+        ] {
+            let result = client
+                .post(format!("{URL}/api/item/new"))
+                .json(&NewItemQuery {
+                    title: title.to_string(),
+                    description: description.to_string(),
+                    amount: 3,
+                    price: "1".to_string(),
+                    attachments: Vec::new(),
+                    category_id: None,
+                    tags: Vec::new(),
+                    barcode: None,
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create new item for sale");
+        }
+        let search = |term: &str| {
+            client
+                .post(format!("{URL}/api/item/list"))
+                .json(&ItemQuery {
+                    search_term: Some(term.to_string()),
+                    ..Default::default()
+                })
+                .send()?
+                .json::<ItemListResult>()
+                .map(|list| list.items)
+        };
+
+        // Inflected Finnish words match, with highlighted snippets
+        let found = search("kahvit")?;
+        assert_eq!(found.len(), 1, "Finnish full text search didn't match");
+        assert_eq!(found[0].item.title, "Kahvi");
+        assert_eq!(
+            found[0].snippet.as_deref(),
+            Some("Tuoretta <mark>kahvia</mark> pannusta")
+        );
+
+        // Matches in titles rank above matches in descriptions
+        let found = search("cola")?;
+        let titles: Vec<&str> = found.iter().map(|i| i.item.title.as_str()).collect();
+        assert_eq!(titles, vec!["Cola", "Chips"], "Results were not ranked");
+
+        // Paging keeps the ranking
+        let mut cursor = None;
+        for expected in ["Cola", "Chips"] {
+            let listed = client
+                .post(format!("{URL}/api/item/list"))
+                .json(&ItemQuery {
+                    search_term: Some("cola".to_string()),
+                    limit: Some(1),
+                    cursor,
+                    ..Default::default()
+                })
+                .send()?
+                .json::<ItemListResult>()?;
+            assert_eq!(listed.items[0].item.title, expected, "Ranked page is off");
+            cursor = listed.next_cursor;
+        }
+
+        // Snippets are escaped
+        let found = search("japan")?;
+        assert_eq!(
+            found[0].snippet.as_deref(),
+            Some("<mark>Japan</mark> &amp; more &lt;3")
+        );
+
+        // Typos are tolerated when nothing matches exactly
+        let found = search("kahwi")?;
+        assert_eq!(found.len(), 1, "Fuzzy search didn't match");
+        assert_eq!(found[0].item.title, "Kahvi");
+        assert_eq!(found[0].snippet, None);
+        assert!(search("xyzzy")?.is_empty(), "Unrelated search matched");
+
+        Ok(())
+    }
+
+    // Test sorting, filtering by price and seller, and paging with a cursor
+    #[test]
+    fn item_sorting_and_paging() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users and sell items with both
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        let mut sold = Vec::new();
+        for (client, title, price) in [
+            (&client, "a", "1.50"),
+            (&client, "b", "0.50"),
+            (&client, "c", "1.50"),
+            (&client2, "d", "2"),
+            (&client2, "e", "1"),
+        ] {
+            let result = client
+                .post(format!("{URL}/api/item/new"))
+                .json(&NewItemQuery {
+                    title: title.to_string(),
+                    description: "test description".to_string(),
+                    amount: 5,
+                    price: price.to_string(),
+                    attachments: Vec::new(),
+                    category_id: None,
+                    tags: Vec::new(),
+                    barcode: None,
+                })
+                .send()?;
+            let item: Item = result.json()?;
+            sold.push(item);
+        }
+
+        // Make e the most popular and b the second most
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                user_id: None,
+                amount_cents: 1000,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add balance to user");
+        for (item, amount) in [(&sold[4], 3), (&sold[1], 2)] {
+            let result = client
+                .post(format!("{URL}/api/item/buy"))
+                .json(&BuyQuery {
+                    item_id: item.id,
+                    amount: Some(amount),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not buy an item");
+        }
+
+        let list = |query: &ItemQuery| {
+            client
+                .post(format!("{URL}/api/item/list"))
+                .json(query)
+                .send()?
+                .json::<ItemListResult>()
+        };
+        let titles = |list: &ItemListResult| {
+            list.items
+                .iter()
+                .map(|i| i.item.title.clone())
+                .collect::<Vec<String>>()
+        };
+
+        // Sort orders, with ties broken by id
+        for (sort, expected) in [
+            (ItemSort::Newest, ["e", "d", "c", "b", "a"]),
+            (ItemSort::Oldest, ["a", "b", "c", "d", "e"]),
+            (ItemSort::PriceAsc, ["b", "e", "a", "c", "d"]),
+            (ItemSort::PriceDesc, ["d", "a", "c", "e", "b"]),
+            (ItemSort::Popular, ["e", "b", "a", "c", "d"]),
+        ] {
+            let listed = list(&ItemQuery {
+                sort: Some(sort),
+                ..Default::default()
+            })?;
+            assert_eq!(titles(&listed), expected, "Wrong order for {sort:?}");
+            assert_eq!(listed.total, 5);
+        }
+        let listed = list(&ItemQuery::default())?;
+        assert_eq!(
+            listed.items[0].item.units_sold, 3,
+            "Units sold were not counted"
+        );
+
+        // Price range and seller filters
+        let listed = list(&ItemQuery {
+            min_price_cents: Some(100),
+            max_price_cents: Some(150),
+            sort: Some(ItemSort::PriceAsc),
+            ..Default::default()
+        })?;
+        assert_eq!(
+            titles(&listed),
+            ["e", "a", "c"],
+            "Price range filter didn't work"
+        );
+        let listed = list(&ItemQuery {
+            seller: Some("test2".to_string()),
+            ..Default::default()
+        })?;
+        assert_eq!(
+            titles(&listed),
+            ["e", "d"],
+            "Seller username filter didn't work"
+        );
+        let listed = list(&ItemQuery {
+            seller_id: Some(sold[0].seller_id),
+            ..Default::default()
+        })?;
+        assert_eq!(listed.total, 3, "Seller id filter didn't work");
+
+        // Page through items, across equal prices
+        let mut paged = Vec::new();
+        let mut cursor = None;
+        loop {
+            let listed = list(&ItemQuery {
+                sort: Some(ItemSort::PriceDesc),
+                limit: Some(2),
+                cursor: cursor.clone(),
+                ..Default::default()
+            })?;
+            assert_eq!(listed.total, 5, "Total should not depend on the page");
+            paged.extend(titles(&listed));
+            cursor = listed.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(
+            paged,
+            ["d", "a", "c", "e", "b"],
+            "Paging skipped or repeated items"
+        );
+
+        // Pages stay intact when new items are added meanwhile
+        let first = list(&ItemQuery {
+            limit: Some(2),
+            ..Default::default()
+        })?;
+        let result = client
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "f".to_string(),
+                description: "test description".to_string(),
+                amount: 5,
+                price: "1".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create new item for sale");
+        let second = list(&ItemQuery {
+            limit: Some(2),
+            cursor: first.next_cursor,
+            ..Default::default()
+        })?;
+        assert_eq!(titles(&second), ["c", "b"], "Page shifted after an insert");
+
+        let result = client
+            .post(format!("{URL}/api/item/list"))
+            .json(&ItemQuery {
+                cursor: Some("garbage".to_string()),
+                ..Default::default()
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Invalid cursor was accepted");
+
+        Ok(())
+    }
+
+    // Test fetching single items and caching them
+    #[test]
+    fn item_detail() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register a test user and sell an item
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+        let result = client
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "test item".to_string(),
+                description: "test description".to_string(),
+                amount: 1,
+                price: "1".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+
+        // Fetch the item
+        let result = client.get(format!("{URL}/api/item/{}", item.id)).send()?;
+        assert_eq!(result.status(), 200, "Could not get item");
+        let etag = result.headers().get("ETag").unwrap().clone();
+        let detail: ItemDetail = result.json()?;
+        assert_eq!(detail.item.item, item);
+        assert_eq!(detail.seller_username, "test");
+
+        // Unchanged items are not sent again
+        let result = client
+            .get(format!("{URL}/api/item/{}", item.id))
+            .header("If-None-Match", etag.clone())
+            .send()?;
+        assert_eq!(result.status(), 304, "Unchanged item was sent again");
+
+        // Selling out changes the item, which can still be fetched
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                user_id: None,
+                amount_cents: 100,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add balance to user");
+        let result = client
+            .post(format!("{URL}/api/item/buy"))
+            .json(&BuyQuery {
+                item_id: item.id,
+                amount: Some(1),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not buy an item");
+        let result = client
+            .get(format!("{URL}/api/item/{}", item.id))
+            .header("If-None-Match", etag)
+            .send()?;
+        assert_eq!(result.status(), 200, "Changed item was not sent");
+        let detail: ItemDetail = result.json()?;
+        assert_eq!(detail.item.item.amount, 0);
+        assert_eq!(detail.item.item.units_sold, 1);
+        assert!(detail.item.item.last_sold_at.is_some());
+
+        // Archived items can be fetched, deleted ones can't
+        let result = client
+            .post(format!("{URL}/api/item/archive"))
+            .json(&ArchiveItemQuery { item_id: item.id })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not archive item");
+        let result = client.get(format!("{URL}/api/item/{}", item.id)).send()?;
+        assert_eq!(result.status(), 200, "Could not get archived item");
+        let result = client
+            .post(format!("{URL}/api/item/delete"))
+            .json(&ArchiveItemQuery { item_id: item.id })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not delete item");
+        let result = client.get(format!("{URL}/api/item/{}", item.id)).send()?;
+        assert_eq!(result.status(), 404, "Deleted item was found");
+
+        Ok(())
+    }
+
+    // Test listing, looking up and buying items by their barcodes
+    #[test]
+    fn item_barcodes() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register a test user with some balance
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                user_id: None,
+                amount_cents: 1000,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add balance to user");
+
+        // Barcodes with invalid check digits are refused
+        let sell = |price: &str, amount: usize, barcode: &str| {
+            client
+                .post(format!("{URL}/api/item/new"))
+                .json(&NewItemQuery {
+                    title: "Cola".to_string(),
+                    description: "test description".to_string(),
+                    amount,
+                    price: price.to_string(),
+                    attachments: Vec::new(),
+                    category_id: None,
+                    tags: Vec::new(),
+                    barcode: Some(barcode.to_string()),
+                })
+                .send()
+        };
+        let result = sell("1", 1, "036000291453")?;
+        assert_eq!(result.status(), 400, "Invalid barcode was accepted");
+
+        // UPC-A codes are stored as EAN-13
+        let result = sell("1.5", 2, "036000291452")?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not create item with a UPC-A barcode"
+        );
+        let pricier: ItemResult = result.json()?;
+        assert_eq!(pricier.item.barcode.as_deref(), Some("0036000291452"));
+        let result = sell("1.2", 1, "0036000291452")?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not create item with an EAN-13 barcode"
+        );
+        let cheaper: ItemResult = result.json()?;
+
+        // Look items up by either form of the code, cheapest first
+        let result = client
+            .get(format!("{URL}/api/item/barcode/036000291452"))
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not look items up by barcode");
+        let found: Vec<ItemResult> = result.json()?;
+        let found_ids: Vec<i32> = found.iter().map(|item| item.item.id).collect();
+        assert_eq!(found_ids, vec![cheaper.item.id, pricier.item.id]);
+
+        // Scanning buys the cheapest item in stock
+        let scan = |amount: Option<i32>| {
+            client
+                .post(format!("{URL}/api/item/barcode/buy"))
+                .json(&ScanBuyQuery {
+                    barcode: "0036000291452".to_string(),
+                    amount,
+                })
+                .send()
+        };
+        let result = scan(None)?;
+        assert_eq!(result.status(), 200, "Could not buy by scanning");
+        let bought: Transaction = result.json()?;
+        assert_eq!(bought.item_id, Some(cheaper.item.id));
+        assert_eq!(bought.amount_cents, 120);
+        let result = scan(Some(3))?;
+        assert_eq!(result.status(), 400, "Bought more than there is in stock");
+        let result = scan(Some(2))?;
+        assert_eq!(result.status(), 200, "Could not buy by scanning");
+        let bought: Transaction = result.json()?;
+        assert_eq!(bought.item_id, Some(pricier.item.id));
+        assert_eq!(bought.amount_cents, 300);
+
+        // Empty barcode removes the barcode in edits
+        let result = client
+            .post(format!("{URL}/api/item/edit"))
+            .json(&EditItemQuery {
+                item_id: pricier.item.id,
+                version: pricier.item.version,
+                title: None,
+                description: None,
+                price: None,
+                category_id: None,
+                tags: None,
+                barcode: Some("".to_string()),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not remove barcode");
+        let edited: ItemResult = result.json()?;
+        assert_eq!(edited.item.barcode, None);
+        let result = client
+            .get(format!("{URL}/api/item/barcode/036000291452"))
+            .send()?;
+        let found: Vec<ItemResult> = result.json()?;
+        assert_eq!(found.len(), 1, "Removed barcode was still found");
+
+        Ok(())
+    }
+
+    // Test that parallel buys can't oversell the last item in stock
+    #[test]
+    fn parallel_buying() -> Result<()> {
+        const BUYERS: usize = 8;
+
+        // Set things up for testing
+        let clients = (0..=BUYERS)
+            .map(|_| {
+                reqwest::blocking::ClientBuilder::new()
+                    .cookie_provider(Arc::new(reqwest::cookie::Jar::default()))
+                    .build()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (seller, buyers) = clients.split_first().unwrap();
+
+        // Clear database for testing
+        let result = seller.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register users and give buyers enough money
+        for (i, client) in clients.iter().enumerate() {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: format!("test{i}"),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        for buyer in buyers {
+            let result = buyer
+                .post(format!("{URL}/api/admin/give"))
+                .json(&AdminGiveQuery {
+                    user_id: None,
+                    amount_cents: 100,
+                    kind: AdjustmentKind::CashDeposit,
+                    reason: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not add balance to user");
+        }
+
+        // Sell the last one
+        let result = seller
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "last item".to_string(),
+                description: "test description".to_string(),
+                amount: 1,
+                price: "1".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+
+        // Everyone rushes to buy it at once
+        let barrier = std::sync::Barrier::new(BUYERS);
+        let statuses = std::thread::scope(|scope| {
+            let handles: Vec<_> = buyers
+                .iter()
+                .map(|buyer| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        buyer
+                            .post(format!("{URL}/api/item/buy"))
+                            .json(&BuyQuery {
+                                item_id: item.id,
+                                amount: Some(1),
+                            })
+                            .send()
+                            .map(|result| result.status())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let bought = statuses.iter().filter(|status| status.is_success()).count();
+        assert_eq!(bought, 1, "Item was sold {bought} times");
+        assert!(
+            statuses
+                .iter()
+                .all(|status| status.is_success() || *status == 400),
+            "Failed buys didn't fail cleanly: {statuses:?}"
+        );
+
+        // Money moved exactly once
+        #[derive(Deserialize)]
+        struct TestUserQuery {
+            balance_cents: u32,
+        }
+        let result = seller.post(format!("{URL}/api/user")).send()?;
+        let user: TestUserQuery = result.json()?;
+        assert_eq!(user.balance_cents, 100, "Seller has unexpected balance");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,200 @@
+use actix_web::http::header;
+use actix_web::{error, Error, HttpResponse};
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use std::time::Duration;
+
+use crate::models::LoginThrottle;
+
+/// Describes how failed logins are punished for one kind of subject
+struct Policy {
+    kind: &'static str,
+    /// Failures allowed before back-off kicks in
+    free_attempts: i32,
+    /// Failures after which the subject gets locked for `LOCK_DURATION`
+    lock_threshold: i32,
+}
+
+/// Failed logins per username. Strict, as these target a single account.
+const USERNAME_POLICY: Policy = Policy {
+    kind: "username",
+    free_attempts: 3,
+    lock_threshold: 10,
+};
+/// Failed logins per ip address. Lenient, as the kiosk is used from a
+/// shared network.
+const IP_POLICY: Policy = Policy {
+    kind: "ip",
+    free_attempts: 20,
+    lock_threshold: 100,
+};
+
+/// Back-off after the first failure past free attempts, doubled on each failure after
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 5);
+const LOCK_DURATION: Duration = Duration::from_secs(60 * 15);
+/// Failures are forgotten after a quiet period this long
+pub const FAILURE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+/// Returns how long a subject has to wait after its nth failure
+fn backoff(policy: &Policy, failures: i32) -> Duration {
+    if failures >= policy.lock_threshold {
+        LOCK_DURATION
+    } else if failures >= policy.free_attempts {
+        let exponent = (failures - policy.free_attempts).min(16) as u32;
+        (BASE_BACKOFF * 2_u32.pow(exponent)).min(MAX_BACKOFF)
+    } else {
+        Duration::ZERO
+    }
+}
+
+/// Returns an error with status 429 and a Retry-After header if either
+/// the username or the ip address is blocked from logging in.
+pub async fn check(
+    con: &mut AsyncPgConnection,
+    username: &str,
+    ip_address: Option<&str>,
+) -> Result<(), Error> {
+    use crate::schema::login_throttles::dsl::*;
+
+    let now = chrono::offset::Utc::now();
+    let throttles = login_throttles
+        .filter(
+            kind.eq(USERNAME_POLICY.kind)
+                .and(subject.eq(username))
+                .or(kind.eq(IP_POLICY.kind).and(subject.nullable().eq(ip_address))),
+        )
+        .filter(blocked_until.gt(now))
+        .select(LoginThrottle::as_select())
+        .load(con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let Some(until) = throttles.iter().filter_map(|t| t.blocked_until).max() else {
+        return Ok(());
+    };
+    let retry_after = (until.to_utc() - now).num_seconds() + 1;
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .body(format!(
+            "Too many failed login attempts, try again in {retry_after} seconds"
+        ));
+    Err(error::InternalError::from_response("Too many failed login attempts", response).into())
+}
+
+/// Counts a failed login against both the username and the ip address.
+pub async fn record_failure(
+    con: &mut AsyncPgConnection,
+    username: &str,
+    ip_address: Option<&str>,
+) -> Result<(), Error> {
+    let subjects = [
+        Some((&USERNAME_POLICY, username)),
+        ip_address.map(|ip| (&IP_POLICY, ip)),
+    ];
+    for (policy, subject) in subjects.into_iter().flatten() {
+        record_subject_failure(con, policy, subject)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+    }
+    Ok(())
+}
+
+async fn record_subject_failure(
+    con: &mut AsyncPgConnection,
+    policy: &Policy,
+    failed_subject: &str,
+) -> QueryResult<()> {
+    use crate::schema::login_throttles::dsl::*;
+
+    let now = chrono::offset::Utc::now();
+
+    // Start counting from scratch if the subject has been quiet for long enough
+    diesel::delete(login_throttles)
+        .filter(kind.eq(policy.kind))
+        .filter(subject.eq(failed_subject))
+        .filter(last_failure_at.lt(now - FAILURE_MEMORY))
+        .filter(blocked_until.is_null().or(blocked_until.lt(now)))
+        .execute(con)
+        .await?;
+
+    // Increment atomically, so that parallel attempts can't dodge counting
+    let throttle = diesel::insert_into(login_throttles)
+        .values((
+            kind.eq(policy.kind),
+            subject.eq(failed_subject),
+            failures.eq(1),
+            last_failure_at.eq(now),
+        ))
+        .on_conflict((kind, subject))
+        .do_update()
+        .set((failures.eq(failures + 1), last_failure_at.eq(now)))
+        .returning(LoginThrottle::as_returning())
+        .get_result(con)
+        .await?;
+
+    let wait = backoff(policy, throttle.failures);
+    if !wait.is_zero() {
+        diesel::update(login_throttles)
+            .filter(id.eq(throttle.id))
+            .set(blocked_until.eq(now + wait))
+            .execute(con)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Forgets failed logins of a username, done after a successful login.
+pub async fn reset(con: &mut AsyncPgConnection, username: &str) -> Result<(), Error> {
+    use crate::schema::login_throttles::dsl::*;
+
+    diesel::delete(login_throttles)
+        .filter(kind.eq(USERNAME_POLICY.kind))
+        .filter(subject.eq(username))
+        .execute(con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(())
+}
+
+/// Lifts blocks and forgets failed logins of a username and/or an ip address.
+/// Returns the amount of cleared subjects.
+pub async fn unlock(
+    con: &mut AsyncPgConnection,
+    username: Option<&str>,
+    ip_address: Option<&str>,
+) -> QueryResult<usize> {
+    use crate::schema::login_throttles::dsl::*;
+
+    let subjects = [
+        username.map(|u| (USERNAME_POLICY.kind, u)),
+        ip_address.map(|ip| (IP_POLICY.kind, ip)),
+    ];
+    let mut cleared = 0;
+    for (subject_kind, unlocked_subject) in subjects.into_iter().flatten() {
+        cleared += diesel::delete(login_throttles)
+            .filter(kind.eq(subject_kind))
+            .filter(subject.eq(unlocked_subject))
+            .execute(con)
+            .await?;
+    }
+    Ok(cleared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially_until_lock() {
+        assert_eq!(backoff(&USERNAME_POLICY, 1), Duration::ZERO);
+        assert_eq!(backoff(&USERNAME_POLICY, 3), BASE_BACKOFF);
+        assert_eq!(backoff(&USERNAME_POLICY, 4), BASE_BACKOFF * 2);
+        assert_eq!(backoff(&USERNAME_POLICY, 6), BASE_BACKOFF * 8);
+        assert_eq!(backoff(&USERNAME_POLICY, 10), LOCK_DURATION);
+        assert_eq!(backoff(&IP_POLICY, 10), Duration::ZERO);
+        assert_eq!(backoff(&IP_POLICY, 99), MAX_BACKOFF);
+        assert_eq!(backoff(&IP_POLICY, 1000), LOCK_DURATION);
+    }
+}
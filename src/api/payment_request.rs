@@ -0,0 +1,483 @@
+use actix_session::Session;
+use actix_web::{error, web, Error, HttpRequest, HttpResponse};
+use actix_web::{get, post};
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::api::idempotency;
+use crate::api::transactions::{transfer_funds, validate_memo};
+use crate::api::user::get_login_uid;
+use crate::models::{PaymentRequest, Transaction};
+use crate::BB8Pool;
+
+/// State of a payment request. Stored in the `status` column of payment requests.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentRequestStatus {
+    /// Waiting for the payer to answer
+    Pending,
+    /// Paid by the payer
+    Accepted,
+    /// Refused by the payer
+    Declined,
+    /// Withdrawn by the requester
+    Cancelled,
+}
+
+impl PaymentRequestStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaymentRequestStatus::Pending => "pending",
+            PaymentRequestStatus::Accepted => "accepted",
+            PaymentRequestStatus::Declined => "declined",
+            PaymentRequestStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewPaymentRequestQuery {
+    pub amount_cents: i32,
+    /// Username of the user asked to pay
+    pub payer: String,
+    /// What the money is asked for
+    #[serde(default)]
+    pub memo: Option<String>,
+}
+
+/// Asks another user to pay the logged in user the given amount
+#[post("/request/new")]
+pub async fn new_payment_request(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<NewPaymentRequestQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{payment_requests, users};
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    if query.amount_cents <= 0 {
+        return Err(error::ErrorBadRequest("Requested amount must be positive"));
+    }
+    let memo = validate_memo(query.memo.as_deref())?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let payer_id = users::table
+        .filter(users::columns::username.eq(&query.payer))
+        .filter(users::columns::deleted_at.is_null())
+        .select(users::columns::id)
+        .get_result::<i32>(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("Payer does not exist"))?;
+    if payer_id == uid {
+        return Err(error::ErrorBadRequest("Can't request money from yourself"));
+    }
+
+    let request = diesel::insert_into(payment_requests::table)
+        .values((
+            payment_requests::columns::requester_id.eq(uid),
+            payment_requests::columns::payer_id.eq(payer_id),
+            payment_requests::columns::amount_cents.eq(query.amount_cents),
+            payment_requests::columns::memo.eq(memo),
+            payment_requests::columns::status.eq(PaymentRequestStatus::Pending.name()),
+            payment_requests::columns::created_at.eq(chrono::offset::Utc::now()),
+        ))
+        .returning(PaymentRequest::as_returning())
+        .get_result(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(request))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PaymentRequestEntry {
+    #[serde(flatten)]
+    pub request: PaymentRequest,
+    /// Username of the other party
+    pub counterparty: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PaymentRequestList {
+    /// Requests the logged in user has been asked to pay
+    pub incoming: Vec<PaymentRequestEntry>,
+    /// Requests the logged in user has made
+    pub outgoing: Vec<PaymentRequestEntry>,
+}
+
+/// Lists pending payment requests of the logged in user, newest first
+#[get("/request/list")]
+pub async fn list_payment_requests(
+    pool: web::Data<BB8Pool>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{payment_requests, users};
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let requests = payment_requests::table
+        .filter(
+            payment_requests::columns::payer_id
+                .eq(uid)
+                .or(payment_requests::columns::requester_id.eq(uid)),
+        )
+        .filter(payment_requests::columns::status.eq(PaymentRequestStatus::Pending.name()))
+        .order(payment_requests::columns::id.desc())
+        .select(PaymentRequest::as_select())
+        .load(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let counterparty_ids: Vec<i32> = requests
+        .iter()
+        .map(|request| {
+            if request.payer_id == uid {
+                request.requester_id
+            } else {
+                request.payer_id
+            }
+        })
+        .collect();
+    let usernames: HashMap<i32, String> = users::table
+        .filter(users::columns::id.eq_any(&counterparty_ids))
+        .select((users::columns::id, users::columns::username))
+        .load::<(i32, String)>(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .collect();
+
+    let mut list = PaymentRequestList {
+        incoming: Vec::new(),
+        outgoing: Vec::new(),
+    };
+    for (request, counterparty_id) in requests.into_iter().zip(counterparty_ids) {
+        let incoming = request.payer_id == uid;
+        let entry = PaymentRequestEntry {
+            request,
+            counterparty: usernames.get(&counterparty_id).cloned().unwrap_or_default(),
+        };
+        if incoming {
+            list.incoming.push(entry);
+        } else {
+            list.outgoing.push(entry);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(list))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PaymentRequestQuery {
+    pub request_id: i32,
+}
+
+/// Pays a pending payment request made to the logged in user, as a normal
+/// transfer carrying the memo of the request. Repeats of a request sent with
+/// the same `Idempotency-Key` header are only carried out once.
+#[post("/request/accept")]
+pub async fn accept_payment_request(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<PaymentRequestQuery>,
+    session: Session,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    idempotency::run(&pool, &req, uid, &*query, |con| {
+        pay_request(con, query.request_id, uid).scope_boxed_local()
+    })
+    .await
+}
+
+async fn pay_request(
+    con: &mut AsyncPgConnection,
+    request_id: i32,
+    payer_id: i32,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::payment_requests;
+
+    let result: Result<Result<Transaction, &str>, diesel::result::Error> = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                // Lock the request, so that it can't be paid twice
+                let request = payment_requests::table
+                    .filter(payment_requests::columns::id.eq(request_id))
+                    .filter(payment_requests::columns::payer_id.eq(payer_id))
+                    .select(PaymentRequest::as_select())
+                    .for_update()
+                    .get_result(con)
+                    .await
+                    .optional()?;
+                let Some(request) = request else {
+                    return Ok(Err("Payment request not found"));
+                };
+                if request.status != PaymentRequestStatus::Pending.name() {
+                    return Ok(Err("Payment request is no longer pending"));
+                }
+
+                let transaction = match transfer_funds(
+                    con,
+                    payer_id,
+                    request.requester_id,
+                    request.amount_cents,
+                    request.memo,
+                )
+                .await?
+                {
+                    Ok(transaction) => transaction,
+                    Err(reason) => return Ok(Err(reason)),
+                };
+
+                diesel::update(payment_requests::table)
+                    .filter(payment_requests::columns::id.eq(request_id))
+                    .set((
+                        payment_requests::columns::status.eq(PaymentRequestStatus::Accepted.name()),
+                        payment_requests::columns::resolved_at.eq(chrono::offset::Utc::now()),
+                        payment_requests::columns::transaction_id.eq(transaction.id),
+                    ))
+                    .execute(con)
+                    .await?;
+
+                Ok(Ok(transaction))
+            })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    let transaction = result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    Ok(HttpResponse::Ok().json(transaction))
+}
+
+/// Refuses to pay a pending payment request made to the logged in user
+#[post("/request/decline")]
+pub async fn decline_payment_request(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<PaymentRequestQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    resolve_request(&pool, query.request_id, uid, PaymentRequestStatus::Declined).await
+}
+
+/// Withdraws a pending payment request made by the logged in user
+#[post("/request/cancel")]
+pub async fn cancel_payment_request(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<PaymentRequestQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    resolve_request(
+        &pool,
+        query.request_id,
+        uid,
+        PaymentRequestStatus::Cancelled,
+    )
+    .await
+}
+
+/// Closes a pending request without paying it. Declining is up to the payer
+/// and cancelling up to the requester.
+async fn resolve_request(
+    pool: &BB8Pool,
+    request_id: i32,
+    uid: i32,
+    status: PaymentRequestStatus,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::payment_requests;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let request = payment_requests::table
+        .filter(payment_requests::columns::id.eq(request_id))
+        .select(PaymentRequest::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?;
+    let party = request.map(|request| match status {
+        PaymentRequestStatus::Declined => request.payer_id,
+        _ => request.requester_id,
+    });
+    if party != Some(uid) {
+        return Err(error::ErrorBadRequest("Payment request not found"));
+    }
+
+    // Only pending requests are touched, in case the request got paid meanwhile
+    let resolved = diesel::update(payment_requests::table)
+        .filter(payment_requests::columns::id.eq(request_id))
+        .filter(payment_requests::columns::status.eq(PaymentRequestStatus::Pending.name()))
+        .set((
+            payment_requests::columns::status.eq(status.name()),
+            payment_requests::columns::resolved_at.eq(chrono::offset::Utc::now()),
+        ))
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if resolved == 0 {
+        return Err(error::ErrorBadRequest(
+            "Payment request is no longer pending",
+        ));
+    }
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Result;
+    use std::sync::Arc;
+
+    use crate::api::admin::AdminGiveQuery;
+    use crate::api::transactions::AdjustmentKind;
+    use crate::api::user::UserQuery;
+    use crate::models::User;
+
+    use super::*;
+    const URL: &str = "http://localhost:3030";
+
+    // Test requesting money and answering the requests
+    #[test]
+    fn payment_requests() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users, the second of which has some balance
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        let result = client2
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                user_id: None,
+                amount_cents: 100,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not add balance to user");
+
+        // Invalid requests are rejected
+        let request_money = |payer: &str, amount_cents: i32| {
+            client
+                .post(format!("{URL}/api/request/new"))
+                .json(&NewPaymentRequestQuery {
+                    amount_cents,
+                    payer: payer.to_string(),
+                    memo: Some("pizza".to_string()),
+                })
+                .send()
+        };
+        for (payer, amount_cents) in [("test", 60), ("test2", 0), ("nobody", 60)] {
+            let result = request_money(payer, amount_cents)?;
+            assert_eq!(result.status(), 400, "Invalid payment request was accepted");
+        }
+
+        // Both parties see a pending request
+        let result = request_money("test2", 60)?;
+        assert_eq!(result.status(), 200, "Could not request money");
+        let request: PaymentRequest = result.json()?;
+        assert_eq!(request.status, "pending");
+        let result = client2.get(format!("{URL}/api/request/list")).send()?;
+        let list: PaymentRequestList = result.json()?;
+        assert_eq!(list.incoming.len(), 1);
+        assert!(list.outgoing.is_empty());
+        assert_eq!(list.incoming[0].counterparty, "test");
+        let result = client.get(format!("{URL}/api/request/list")).send()?;
+        let list: PaymentRequestList = result.json()?;
+        assert_eq!(list.outgoing.len(), 1);
+        assert_eq!(list.outgoing[0].counterparty, "test2");
+
+        // Only the payer can accept, and only once
+        let answer = |client: &reqwest::blocking::Client, action: &str, request_id: i32| {
+            client
+                .post(format!("{URL}/api/request/{action}"))
+                .json(&PaymentRequestQuery { request_id })
+                .send()
+        };
+        let result = answer(&client, "accept", request.id)?;
+        assert_eq!(result.status(), 400, "Requester paid their own request");
+        let result = answer(&client2, "accept", request.id)?;
+        assert_eq!(result.status(), 200, "Could not accept payment request");
+        let transaction: Transaction = result.json()?;
+        assert_eq!(transaction.amount_cents, 60);
+        assert_eq!(transaction.memo.as_deref(), Some("pizza"));
+        let result = answer(&client2, "accept", request.id)?;
+        assert_eq!(result.status(), 400, "Payment request was paid twice");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 60, "Requester didn't get paid");
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 40, "Payer wasn't charged");
+
+        // Requests the payer can't afford stay pending until declined
+        let result = request_money("test2", 50)?;
+        let request: PaymentRequest = result.json()?;
+        let result = answer(&client2, "accept", request.id)?;
+        assert_eq!(result.status(), 400, "Accepted a request without funds");
+        let result = answer(&client, "decline", request.id)?;
+        assert_eq!(result.status(), 400, "Requester declined their own request");
+        let result = answer(&client2, "decline", request.id)?;
+        assert_eq!(result.status(), 200, "Could not decline payment request");
+        let result = answer(&client2, "decline", request.id)?;
+        assert_eq!(result.status(), 400, "Declined request was declined again");
+
+        // Cancelled requests can't be paid
+        let result = request_money("test2", 10)?;
+        let request: PaymentRequest = result.json()?;
+        let result = answer(&client2, "cancel", request.id)?;
+        assert_eq!(result.status(), 400, "Payer cancelled a request");
+        let result = answer(&client, "cancel", request.id)?;
+        assert_eq!(result.status(), 200, "Could not cancel payment request");
+        let result = answer(&client2, "accept", request.id)?;
+        assert_eq!(result.status(), 400, "Cancelled request was paid");
+        let result = client2.get(format!("{URL}/api/request/list")).send()?;
+        let list: PaymentRequestList = result.json()?;
+        assert!(
+            list.incoming.is_empty(),
+            "Answered requests are still listed"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,491 @@
+use actix_web::{error, get, web, Error, HttpResponse};
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel::ExpressionMethods;
+use diesel_async::AsyncPgConnection;
+use diesel_async::RunQueryDsl;
+use futures::io::{AsyncBufReadExt, BufReader};
+use log::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::api::item::{validate_barcode, MAX_DESCRIPTION_LENGTH, MAX_TITLE_LENGTH};
+use crate::models::Product;
+use crate::settings::catalogue_dir;
+use crate::BB8Pool;
+
+/// Products are written to db in batches of this many
+const IMPORT_BATCH_SIZE: usize = 1000;
+/// Give up reading a catalogue after this many read errors in a row
+const MAX_READ_ERRORS: usize = 100;
+
+/// Product as described in a catalogue file. Field names follow the export
+/// format of Open Food Facts.
+#[derive(Deserialize, Default)]
+struct CatalogueRow {
+    code: Option<String>,
+    product_name: Option<String>,
+    generic_name: Option<String>,
+    brands: Option<String>,
+    quantity: Option<String>,
+}
+
+impl CatalogueRow {
+    /// Turns the row into a product, unless it lacks a valid barcode or a name
+    fn into_product(self, now: chrono::DateTime<chrono::Utc>) -> Option<Product> {
+        let barcode = validate_barcode(self.code.as_deref()?).ok()?;
+        let title = truncate(self.product_name.as_deref()?.trim(), MAX_TITLE_LENGTH);
+        if title.is_empty() {
+            return None;
+        }
+        let description = [self.generic_name, self.brands, self.quantity]
+            .into_iter()
+            .flatten()
+            .map(|part| part.trim().to_string())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(Product {
+            barcode,
+            title,
+            description: truncate(&description, MAX_DESCRIPTION_LENGTH),
+            imported_at: now.into(),
+        })
+    }
+}
+
+/// Cuts text to at most `max_len` bytes without splitting characters
+fn truncate(text: &str, max_len: usize) -> String {
+    let mut end = max_len.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].trim_end().to_string()
+}
+
+/// Splits a line of a CSV file into its fields. Fields can be quoted with
+/// double quotes, but can't span multiple lines.
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Reads a catalogue row from CSV fields, given the names of the columns
+fn csv_row(columns: &[String], fields: Vec<String>) -> CatalogueRow {
+    let mut row = CatalogueRow::default();
+    for (column, field) in columns.iter().zip(fields) {
+        let value = Some(field);
+        match column.as_str() {
+            "code" => row.code = value,
+            "product_name" => row.product_name = value,
+            "generic_name" => row.generic_name = value,
+            "brands" => row.brands = value,
+            "quantity" => row.quantity = value,
+            _ => (),
+        }
+    }
+    row
+}
+
+/// Catalogue file opened for importing
+pub struct CatalogueFile {
+    file: async_fs::File,
+    /// JSON lines rather than comma or tab separated values
+    is_jsonl: bool,
+}
+
+/// Opens a catalogue file in the catalogue directory. Files ending in
+/// `.jsonl` are read as JSON lines, and `.csv` and `.tsv` files as comma or
+/// tab separated values with a header row.
+pub async fn open_catalogue(file_name: &str) -> Result<CatalogueFile, Error> {
+    // Only allow reading files directly inside of the catalogue directory
+    let file_path = Path::new(file_name);
+    if file_path.file_name() != Some(file_path.as_os_str()) {
+        return Err(error::ErrorBadRequest(
+            "Catalogue must be given as a file name",
+        ));
+    }
+    let is_jsonl = match file_path.extension().and_then(|ext| ext.to_str()) {
+        Some("jsonl") => true,
+        Some("csv" | "tsv") => false,
+        _ => {
+            return Err(error::ErrorBadRequest(
+                "Catalogue must be a .jsonl, .csv or .tsv file",
+            ))
+        }
+    };
+    let file = async_fs::File::open(catalogue_dir().join(file_path))
+        .await
+        .map_err(|_| error::ErrorBadRequest("Catalogue file not found"))?;
+    Ok(CatalogueFile { file, is_jsonl })
+}
+
+/// Imports products from an opened catalogue, replacing earlier imports of
+/// the same barcodes, and records the outcome on given import. Catalogues
+/// can hold millions of products, so this is meant to run in the background.
+pub async fn import_catalogue(pool: BB8Pool, import_id: i32, catalogue: CatalogueFile) {
+    use crate::schema::{product_imports, products};
+
+    let mut con = match pool.get().await {
+        Ok(con) => con,
+        Err(e) => {
+            error!("Could not start catalogue import {import_id}: {e}");
+            return;
+        }
+    };
+
+    let now = chrono::offset::Utc::now();
+    let mut skipped = 0;
+    let import_error = read_catalogue(&mut con, catalogue, now, &mut skipped)
+        .await
+        .err();
+
+    // Every product written by this import carries its timestamp, which
+    // counts barcodes repeated across batches only once
+    let result = async {
+        let imported: i64 = products::table
+            .filter(products::columns::imported_at.eq(now))
+            .count()
+            .get_result(&mut con)
+            .await?;
+        diesel::update(product_imports::table)
+            .filter(product_imports::columns::id.eq(import_id))
+            .set((
+                product_imports::columns::finished_at.eq(chrono::offset::Utc::now()),
+                product_imports::columns::imported.eq(i32::try_from(imported).unwrap_or(i32::MAX)),
+                product_imports::columns::skipped.eq(i32::try_from(skipped).unwrap_or(i32::MAX)),
+                product_imports::columns::failure.eq(&import_error),
+            ))
+            .execute(&mut con)
+            .await?;
+        Ok::<_, diesel::result::Error>(imported)
+    }
+    .await;
+    match (result, import_error) {
+        (Ok(imported), None) => info!(
+            "Imported {imported} products in catalogue import {import_id}, skipped {skipped} rows"
+        ),
+        (Ok(imported), Some(e)) => {
+            error!("Catalogue import {import_id} stopped after {imported} products: {e}")
+        }
+        (Err(e), _) => error!("Could not record outcome of catalogue import {import_id}: {e}"),
+    }
+}
+
+/// Reads products from a catalogue into db, counting lines which can't be
+/// read as products into `skipped`
+async fn read_catalogue(
+    con: &mut AsyncPgConnection,
+    catalogue: CatalogueFile,
+    now: chrono::DateTime<chrono::Utc>,
+    skipped: &mut usize,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(catalogue.file);
+
+    // Column names of CSV files and the separator they use
+    let mut csv_header: Option<(Vec<String>, char)> = None;
+
+    let mut batch: HashMap<String, Product> = HashMap::new();
+    let mut line = Vec::new();
+    let mut read_errors = 0;
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) => break,
+            Ok(_) => read_errors = 0,
+            Err(e) => {
+                read_errors += 1;
+                if read_errors >= MAX_READ_ERRORS {
+                    return Err(format!("Could not read catalogue: {e}"));
+                }
+                *skipped += 1;
+                continue;
+            }
+        }
+        let Ok(line) = std::str::from_utf8(&line) else {
+            *skipped += 1;
+            continue;
+        };
+        // Files saved on Windows often start with a byte order mark
+        let line = line.strip_prefix('\u{feff}').unwrap_or(line);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row = if catalogue.is_jsonl {
+            serde_json::from_str::<CatalogueRow>(line).ok()
+        } else if let Some((columns, delimiter)) = &csv_header {
+            Some(csv_row(columns, split_csv_line(line, *delimiter)))
+        } else {
+            let delimiter = if line.contains('\t') { '\t' } else { ',' };
+            csv_header = Some((split_csv_line(line, delimiter), delimiter));
+            continue;
+        };
+
+        match row.and_then(|row| row.into_product(now)) {
+            Some(product) => {
+                batch.insert(product.barcode.clone(), product);
+            }
+            None => *skipped += 1,
+        }
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            save_products(con, batch.drain().map(|(_, p)| p).collect())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    save_products(con, batch.into_values().collect())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Inserts or updates given products, returning how many were written
+async fn save_products(con: &mut AsyncPgConnection, batch: Vec<Product>) -> QueryResult<usize> {
+    use crate::schema::products::dsl::*;
+
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    diesel::insert_into(products)
+        .values(
+            batch
+                .iter()
+                .map(|product| {
+                    (
+                        barcode.eq(&product.barcode),
+                        title.eq(&product.title),
+                        description.eq(&product.description),
+                        imported_at.eq(product.imported_at),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .on_conflict(barcode)
+        .do_update()
+        .set((
+            title.eq(excluded(title)),
+            description.eq(excluded(description)),
+            imported_at.eq(excluded(imported_at)),
+        ))
+        .execute(con)
+        .await
+}
+
+/// Gets a product from the imported catalogue by its EAN-13 or UPC-A barcode,
+/// for prefilling the title and description of a new item. Catalogue images
+/// aren't imported, since new items only take uploaded attachments.
+#[get("/product/{barcode}")]
+pub async fn get_product(
+    pool: web::Data<BB8Pool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::products;
+
+    let product_barcode = validate_barcode(&path)?;
+
+    // Aquire db connection handle
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let product = products::table
+        .find(product_barcode)
+        .select(Product::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Product not found"))?;
+
+    Ok(HttpResponse::Ok().json(product))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Result;
+    use std::sync::Arc;
+
+    use crate::api::admin::{ImportProductsQuery, ProductImportQuery};
+    use crate::api::user::UserQuery;
+    use crate::models::ProductImport;
+
+    use super::*;
+    const URL: &str = "http://localhost:3030";
+
+    #[test]
+    fn csv_splitting_works() {
+        assert_eq!(split_csv_line("a,b,,c", ','), vec!["a", "b", "", "c"]);
+        assert_eq!(
+            split_csv_line("\"a, b\",\"say \"\"hi\"\"\",c", ','),
+            vec!["a, b", "say \"hi\"", "c"]
+        );
+        assert_eq!(
+            split_csv_line("a\t5\" disk\tc", '\t'),
+            vec!["a", "5\" disk", "c"]
+        );
+    }
+
+    // Test importing catalogues and looking products up from them
+    #[test]
+    fn product_import() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register a test user
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test".to_string(),
+                password: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+
+        // Write catalogues to the directory the server reads them from
+        let dir = catalogue_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut jsonl = [
+            r#"{"code":"4006381333931","product_name":"Pen","image_url":"https://images.example/pen.jpg"}"#,
+            r#"{"code":"036000291452","product_name":"  Cola  ","generic_name":null,"brands":"Fizz","quantity":"330 ml"}"#,
+            r#"{"code":"123","product_name":"Invalid barcode"}"#,
+            r#"not json"#,
+            r#"{"code":"4006381333931","product_name":"Highlighter","image_front_url":"https://images.example/highlighter.jpg"}"#,
+        ]
+        .join("\n")
+        .into_bytes();
+        jsonl.extend_from_slice(b"\n{\"code\":\"5901234123457\",\"product_name\":\"\xff\"}\r\n");
+        std::fs::write(dir.join("test_catalogue.jsonl"), jsonl).unwrap();
+        std::fs::write(
+            dir.join("test_catalogue.csv"),
+            "\u{feff}code,product_name,generic_name,image_url\n6414893400016,\"Kahvi, tumma\",Suodatinkahvi,ftp://images.example/kahvi.jpg\n",
+        )
+        .unwrap();
+
+        // A catalogue spanning several batches, which repeats its first
+        // product at the end
+        let barcode = |number: u32| {
+            let digits = format!("2{number:011}");
+            let sum: u32 = digits
+                .chars()
+                .enumerate()
+                .map(|(i, c)| c.to_digit(10).unwrap() * if i % 2 == 0 { 1 } else { 3 })
+                .sum();
+            format!("{digits}{}", (10 - sum % 10) % 10)
+        };
+        let mut csv = "code\tproduct_name\n".to_string();
+        for number in (0..IMPORT_BATCH_SIZE as u32).chain([0]) {
+            csv.push_str(&format!("{}\tProduct {number}\n", barcode(number)));
+        }
+        std::fs::write(dir.join("test_catalogue.tsv"), csv).unwrap();
+
+        // Only files inside of the catalogue directory can be imported
+        let import = |file: &str| {
+            client
+                .post(format!("{URL}/api/admin/products/import"))
+                .json(&ImportProductsQuery {
+                    file: file.to_string(),
+                })
+                .send()
+        };
+        let result = import("../Cargo.toml")?;
+        assert_eq!(
+            result.status(),
+            400,
+            "Read a file outside of catalogue directory"
+        );
+        let result = import("missing.jsonl")?;
+        assert_eq!(result.status(), 400, "Imported a missing catalogue");
+
+        // Imports run in the background, so wait for them to finish
+        let wait_for_import = |import: ProductImport| -> Result<ProductImport> {
+            for _ in 0..100 {
+                let import: ProductImport = client
+                    .post(format!("{URL}/api/admin/products/import/status"))
+                    .json(&ProductImportQuery {
+                        import_id: import.id,
+                    })
+                    .send()?
+                    .json()?;
+                if import.finished_at.is_some() {
+                    return Ok(import);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            panic!("Catalogue import didn't finish");
+        };
+
+        // Rows without a valid barcode or a name and lines which aren't
+        // valid text are skipped, and later rows replace earlier ones
+        let result = import("test_catalogue.jsonl")?;
+        assert_eq!(result.status(), 202, "Could not import catalogue");
+        let imported = wait_for_import(result.json()?)?;
+        assert_eq!(imported.imported, 2);
+        assert_eq!(imported.skipped, 3);
+        assert_eq!(imported.failure, None);
+        let result = import("test_catalogue.csv")?;
+        assert_eq!(result.status(), 202, "Could not import CSV catalogue");
+        let imported = wait_for_import(result.json()?)?;
+        assert_eq!(imported.imported, 1, "Byte order mark broke the header");
+        assert_eq!(imported.skipped, 0);
+
+        // Products repeated in different batches are counted once
+        let result = import("test_catalogue.tsv")?;
+        assert_eq!(result.status(), 202, "Could not import TSV catalogue");
+        let imported = wait_for_import(result.json()?)?;
+        assert_eq!(imported.imported, IMPORT_BATCH_SIZE as i32);
+        assert_eq!(imported.skipped, 0);
+
+        std::fs::remove_file(dir.join("test_catalogue.jsonl")).unwrap();
+        std::fs::remove_file(dir.join("test_catalogue.csv")).unwrap();
+        std::fs::remove_file(dir.join("test_catalogue.tsv")).unwrap();
+
+        // Look products up for prefilling items
+        let lookup = |barcode: &str| client.get(format!("{URL}/api/product/{barcode}")).send();
+        let result = lookup("4006381333931")?;
+        assert_eq!(result.status(), 200, "Could not look up product");
+        let product: Product = result.json()?;
+        assert_eq!(product.title, "Highlighter");
+        let product: Product = lookup("036000291452")?.json()?;
+        assert_eq!(product.barcode, "0036000291452");
+        assert_eq!(product.title, "Cola");
+        assert_eq!(product.description, "Fizz, 330 ml");
+        let product: Product = lookup("6414893400016")?.json()?;
+        assert_eq!(product.title, "Kahvi, tumma");
+        assert_eq!(product.description, "Suodatinkahvi");
+        let result = lookup("5901234123457")?;
+        assert_eq!(
+            result.status(),
+            404,
+            "Found a product which wasn't imported"
+        );
+
+        Ok(())
+    }
+}
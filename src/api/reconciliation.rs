@@ -0,0 +1,222 @@
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::models::{ReconciliationDiscrepancy, ReconciliationRun};
+
+#[derive(Serialize, Deserialize)]
+pub struct ReconciliationResult {
+    #[serde(flatten)]
+    pub run: ReconciliationRun,
+    pub discrepancies: Vec<ReconciliationDiscrepancy>,
+}
+
+/// Balance of a user next to the balance their ledger adds up to
+#[derive(Debug, PartialEq)]
+struct Account {
+    user_id: i32,
+    balance_cents: i64,
+    ledger_cents: i64,
+}
+
+/// Discrepancy of a user, not yet tied to a run
+#[derive(Debug, PartialEq)]
+struct Finding {
+    user_id: i32,
+    balance_cents: i64,
+    ledger_cents: i64,
+    discrepancy_cents: i64,
+    drift_cents: i64,
+}
+
+/// Compares balances to ledgers. `previous` holds discrepancies found by the
+/// previous run, by user id. Users whose discrepancy was resolved since are
+/// reported too, with a zero discrepancy.
+fn compare(accounts: &[Account], previous: &HashMap<i32, i64>) -> Vec<Finding> {
+    accounts
+        .iter()
+        .filter_map(|account| {
+            let discrepancy_cents = account.balance_cents - account.ledger_cents;
+            let previous_cents = previous.get(&account.user_id).copied().unwrap_or(0);
+            if discrepancy_cents == 0 && previous_cents == 0 {
+                return None;
+            }
+            Some(Finding {
+                user_id: account.user_id,
+                balance_cents: account.balance_cents,
+                ledger_cents: account.ledger_cents,
+                discrepancy_cents,
+                drift_cents: discrepancy_cents - previous_cents,
+            })
+        })
+        .collect()
+}
+
+/// Checks that the balance of every user equals the sum of their
+/// transactions, and stores the results. `triggered_by` is the user who
+/// asked for the run, or None if it was run by cron.
+pub async fn reconcile(
+    con: &mut AsyncPgConnection,
+    triggered_by: Option<i32>,
+) -> QueryResult<ReconciliationResult> {
+    use crate::schema::{reconciliation_discrepancies, reconciliation_runs, transactions, users};
+
+    // Read balances and the ledger from the same snapshot, so that
+    // transactions made meanwhile don't show up as discrepancies
+    con.build_transaction()
+        .repeatable_read()
+        .run(move |con| {
+            Box::pin(async move {
+                let balances: Vec<(i32, i32)> = users::table
+                    .select((users::columns::id, users::columns::balance_cents))
+                    .order(users::columns::id)
+                    .load(con)
+                    .await?;
+                let incoming: Vec<(Option<i32>, Option<i64>)> = transactions::table
+                    .filter(transactions::columns::receiver_id.is_not_null())
+                    .group_by(transactions::columns::receiver_id)
+                    .select((
+                        transactions::columns::receiver_id,
+                        diesel::dsl::sum(transactions::columns::amount_cents),
+                    ))
+                    .load(con)
+                    .await?;
+                let outgoing: Vec<(Option<i32>, Option<i64>)> = transactions::table
+                    .filter(transactions::columns::payer_id.is_not_null())
+                    .group_by(transactions::columns::payer_id)
+                    .select((
+                        transactions::columns::payer_id,
+                        diesel::dsl::sum(transactions::columns::amount_cents),
+                    ))
+                    .load(con)
+                    .await?;
+
+                let mut ledgers: HashMap<i32, i64> = HashMap::new();
+                for (user_id, amount) in incoming {
+                    if let (Some(user_id), Some(amount)) = (user_id, amount) {
+                        *ledgers.entry(user_id).or_default() += amount;
+                    }
+                }
+                for (user_id, amount) in outgoing {
+                    if let (Some(user_id), Some(amount)) = (user_id, amount) {
+                        *ledgers.entry(user_id).or_default() -= amount;
+                    }
+                }
+                let accounts: Vec<Account> = balances
+                    .into_iter()
+                    .map(|(user_id, balance_cents)| Account {
+                        user_id,
+                        balance_cents: balance_cents as i64,
+                        ledger_cents: ledgers.get(&user_id).copied().unwrap_or(0),
+                    })
+                    .collect();
+
+                // Compare against the previous run to tell how things have drifted
+                let previous_run = reconciliation_runs::table
+                    .order(reconciliation_runs::columns::id.desc())
+                    .select(ReconciliationRun::as_select())
+                    .first(con)
+                    .await
+                    .optional()?;
+                let previous: HashMap<i32, i64> = match &previous_run {
+                    Some(run) => reconciliation_discrepancies::table
+                        .filter(reconciliation_discrepancies::columns::run_id.eq(run.id))
+                        .select((
+                            reconciliation_discrepancies::columns::user_id,
+                            reconciliation_discrepancies::columns::discrepancy_cents,
+                        ))
+                        .load::<(i32, i64)>(con)
+                        .await?
+                        .into_iter()
+                        .collect(),
+                    None => HashMap::new(),
+                };
+                let findings = compare(&accounts, &previous);
+
+                let money_supply_cents: i64 = accounts.iter().map(|a| a.balance_cents).sum();
+                let ledger_supply_cents: i64 = accounts.iter().map(|a| a.ledger_cents).sum();
+                let discrepant_users = findings.iter().filter(|f| f.discrepancy_cents != 0).count();
+                let run = diesel::insert_into(reconciliation_runs::table)
+                    .values((
+                        reconciliation_runs::columns::ran_at.eq(chrono::offset::Utc::now()),
+                        reconciliation_runs::columns::triggered_by.eq(triggered_by),
+                        reconciliation_runs::columns::money_supply_cents.eq(money_supply_cents),
+                        reconciliation_runs::columns::ledger_supply_cents.eq(ledger_supply_cents),
+                        reconciliation_runs::columns::discrepant_users.eq(discrepant_users as i32),
+                        reconciliation_runs::columns::money_supply_drift_cents.eq(previous_run
+                            .map(|previous| money_supply_cents - previous.money_supply_cents)),
+                    ))
+                    .returning(ReconciliationRun::as_returning())
+                    .get_result(con)
+                    .await?;
+
+                let rows: Vec<_> = findings
+                    .iter()
+                    .map(|f| {
+                        (
+                            reconciliation_discrepancies::columns::run_id.eq(run.id),
+                            reconciliation_discrepancies::columns::user_id.eq(f.user_id),
+                            reconciliation_discrepancies::columns::balance_cents
+                                .eq(f.balance_cents),
+                            reconciliation_discrepancies::columns::ledger_cents.eq(f.ledger_cents),
+                            reconciliation_discrepancies::columns::discrepancy_cents
+                                .eq(f.discrepancy_cents),
+                            reconciliation_discrepancies::columns::drift_cents.eq(f.drift_cents),
+                        )
+                    })
+                    .collect();
+                let discrepancies = diesel::insert_into(reconciliation_discrepancies::table)
+                    .values(rows)
+                    .returning(ReconciliationDiscrepancy::as_returning())
+                    .get_results(con)
+                    .await?;
+
+                Ok(ReconciliationResult { run, discrepancies })
+            })
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_reports_discrepancies_and_drift() {
+        let accounts = [
+            Account { user_id: 1, balance_cents: 100, ledger_cents: 100 },
+            Account { user_id: 2, balance_cents: 150, ledger_cents: 100 },
+            Account { user_id: 3, balance_cents: 100, ledger_cents: 100 },
+            Account { user_id: 4, balance_cents: 0, ledger_cents: 20 },
+        ];
+        let previous = HashMap::from([(2, 30), (3, -10)]);
+        assert_eq!(
+            compare(&accounts, &previous),
+            vec![
+                Finding {
+                    user_id: 2,
+                    balance_cents: 150,
+                    ledger_cents: 100,
+                    discrepancy_cents: 50,
+                    drift_cents: 20,
+                },
+                Finding {
+                    user_id: 3,
+                    balance_cents: 100,
+                    ledger_cents: 100,
+                    discrepancy_cents: 0,
+                    drift_cents: 10,
+                },
+                Finding {
+                    user_id: 4,
+                    balance_cents: 0,
+                    ledger_cents: 20,
+                    discrepancy_cents: -20,
+                    drift_cents: -20,
+                },
+            ]
+        );
+    }
+}
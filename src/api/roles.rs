@@ -0,0 +1,151 @@
+use actix_session::{Session, SessionExt};
+use actix_web::dev::Payload;
+use actix_web::{error, web, Error, FromRequest, HttpRequest};
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+use crate::api::user::get_login_uid;
+use crate::settings::dev_mode;
+use crate::BB8Pool;
+
+/// Roles which can be granted to users. Names match rows of the `roles` table.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Manages roles of other users, and implicitly has every other role
+    Admin,
+    /// Adjusts balances and reads reports
+    Treasurer,
+    /// Takes down items
+    Moderator,
+    /// Manages stock
+    KioskManager,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Treasurer => "treasurer",
+            Role::Moderator => "moderator",
+            Role::KioskManager => "kiosk_manager",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Role> {
+        [
+            Role::Admin,
+            Role::Treasurer,
+            Role::Moderator,
+            Role::KioskManager,
+        ]
+        .into_iter()
+        .find(|role| role.name() == name)
+    }
+}
+
+/// Returns roles granted to a user
+pub async fn get_roles(con: &mut AsyncPgConnection, uid: i32) -> QueryResult<Vec<Role>> {
+    use crate::schema::{roles, user_roles};
+
+    let names: Vec<String> = user_roles::table
+        .inner_join(roles::table)
+        .filter(user_roles::columns::user_id.eq(uid))
+        .select(roles::columns::name)
+        .load(con)
+        .await?;
+    Ok(names.iter().filter_map(|name| Role::from_name(name)).collect())
+}
+
+/// Marks a role required by `Authorized`
+pub trait Permission {
+    const ROLE: Role;
+
+    /// Returns true if given roles grant the permission, either directly or
+    /// through being admin
+    fn granted_by(roles: &[Role]) -> bool {
+        roles.contains(&Self::ROLE) || roles.contains(&Role::Admin)
+    }
+}
+
+pub struct Admin;
+impl Permission for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+pub struct Treasurer;
+impl Permission for Treasurer {
+    const ROLE: Role = Role::Treasurer;
+}
+
+pub struct Moderator;
+impl Permission for Moderator {
+    const ROLE: Role = Role::Moderator;
+}
+
+pub struct KioskManager;
+impl Permission for KioskManager {
+    const ROLE: Role = Role::KioskManager;
+}
+
+/// Extractor which lets the request through only if the session user has
+/// the role required by `P`. Use it as a handler argument, for example
+/// `auth: Authorized<Treasurer>`. Endpoints also open to users without the
+/// role, such as sellers managing their own items, can take an
+/// `Option<Authorized<P>>` instead.
+pub struct Authorized<P: Permission> {
+    pub user_id: i32,
+    permission: PhantomData<P>,
+}
+
+impl<P: Permission> FromRequest for Authorized<P> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session: Session = req.get_session();
+        let pool = req.app_data::<web::Data<BB8Pool>>().cloned();
+        Box::pin(async move {
+            let uid = get_login_uid(&session)?
+                .ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+            let pool = pool.ok_or_else(|| error::ErrorInternalServerError("No db pool"))?;
+            let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+            let roles = get_roles(&mut con, uid)
+                .await
+                .map_err(error::ErrorInternalServerError)?;
+            // Allow any user to act in any role in development mode
+            if dev_mode() || P::granted_by(&roles) {
+                Ok(Authorized {
+                    user_id: uid,
+                    permission: PhantomData,
+                })
+            } else {
+                Err(error::ErrorForbidden("Insufficent privileges"))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions_work() {
+        assert!(KioskManager::granted_by(&[Role::KioskManager]));
+        assert!(!KioskManager::granted_by(&[Role::Moderator]));
+        assert!(Moderator::granted_by(&[Role::Moderator]));
+        assert!(!Moderator::granted_by(&[Role::KioskManager]));
+        assert!(!Treasurer::granted_by(&[Role::Moderator]));
+        assert!(!Treasurer::granted_by(&[]));
+
+        // Admins have every role
+        assert!(KioskManager::granted_by(&[Role::Admin]));
+        assert!(Moderator::granted_by(&[Role::Admin]));
+        assert!(Treasurer::granted_by(&[Role::Admin]));
+    }
+}
@@ -1,166 +1,479 @@
 use actix_session::Session;
 use actix_web::post;
-use actix_web::{error, web, Error, HttpResponse};
+use actix_web::{error, web, Error, HttpRequest, HttpResponse};
+use diesel::pg::Pg;
 use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Bool, Integer, Nullable};
 use diesel::ExpressionMethods;
-use diesel_async::AsyncConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
 use futures::try_join;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
 
-use crate::api::user::get_login_uid;
-use crate::models::{Transaction, User};
+use crate::api::idempotency;
+use crate::api::roles::{Admin, Authorized};
+use crate::api::user::{get_login_uid, lock_users};
+use crate::models::Transaction;
+use crate::settings::cancellation_window;
+use crate::schema::transactions;
 use crate::BB8Pool;
 
+/// What a transaction was made for. Stored in the `kind` column of transactions.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    /// Buyer paying seller for an item
+    Purchase,
+    /// User sending money to another user
+    Transfer,
+    /// Admin crediting or debiting a balance
+    Adjustment,
+    /// Seller returning money of a purchase to the buyer
+    Refund,
+    /// Buyer undoing their purchase soon after making it
+    Cancellation,
+}
+
+impl TransactionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionKind::Purchase => "purchase",
+            TransactionKind::Transfer => "transfer",
+            TransactionKind::Adjustment => "adjustment",
+            TransactionKind::Refund => "refund",
+            TransactionKind::Cancellation => "cancellation",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TransactionKind> {
+        [
+            TransactionKind::Purchase,
+            TransactionKind::Transfer,
+            TransactionKind::Adjustment,
+            TransactionKind::Refund,
+            TransactionKind::Cancellation,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+}
+
+/// Kinds of admin adjustments. Stored in the `adjustment_kind` column of transactions.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentKind {
+    /// Cash paid in to the kiosk
+    CashDeposit,
+    /// Cash paid out of the kiosk
+    CashWithdrawal,
+    /// Fixing an earlier mistake
+    Correction,
+    /// Returning money for a purchase gone wrong
+    Refund,
+}
+
+impl AdjustmentKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdjustmentKind::CashDeposit => "cash_deposit",
+            AdjustmentKind::CashWithdrawal => "cash_withdrawal",
+            AdjustmentKind::Correction => "correction",
+            AdjustmentKind::Refund => "refund",
+        }
+    }
+}
+
+/// Transaction types as seen by one user. A purchase is a sale for the seller.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryType {
+    Purchase,
+    Sale,
+    Transfer,
+    AdminAdjustment,
+    Refund,
+    Cancellation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+const DEFAULT_HISTORY_LIMIT: i64 = 50;
+const MAX_HISTORY_LIMIT: i64 = 200;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct HistoryQuery {
+    /// Only return transactions older than the one with this id. Pass
+    /// `next_cursor` of the previous page to get the next one.
+    pub cursor: Option<i32>,
+    pub limit: Option<i64>,
+    /// Inclusive start of the date range
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Exclusive end of the date range
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only return transactions of these types, or all if empty
+    pub types: Option<Vec<HistoryType>>,
+}
+
 #[derive(Serialize, Deserialize)]
-enum LogQuery {
-    UserId(i32),
-    ForEveryone,
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    pub entry_type: HistoryType,
+    pub direction: Direction,
+    /// Username of the other party, if there is one
+    pub counterparty: Option<String>,
+    pub item_title: Option<String>,
+    /// Change of the user's balance caused by the transaction
+    pub delta_cents: i64,
+    /// User's balance right after the transaction
+    pub balance_after_cents: i64,
+    /// Whether the purchase has been cancelled by the buyer
+    pub cancelled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HistoryResult {
+    pub entries: Vec<HistoryEntry>,
+    /// Cursor for the next page, if there might be one
+    pub next_cursor: Option<i32>,
+}
+
+type HistoryFilter =
+    Box<dyn BoxableExpression<transactions::table, Pg, SqlType = Nullable<Bool>>>;
+
+/// Returns a filter matching transactions of given type from the point of view of `uid`
+fn history_type_filter(history_type: HistoryType, uid: i32) -> HistoryFilter {
+    use crate::schema::transactions::dsl::*;
+
+    match history_type {
+        HistoryType::Purchase => Box::new(
+            kind.eq(TransactionKind::Purchase.name())
+                .nullable()
+                .and(payer_id.eq(uid)),
+        ),
+        HistoryType::Sale => Box::new(
+            kind.eq(TransactionKind::Purchase.name())
+                .nullable()
+                .and(receiver_id.eq(uid)),
+        ),
+        HistoryType::Transfer => Box::new(kind.eq(TransactionKind::Transfer.name()).nullable()),
+        HistoryType::AdminAdjustment => {
+            Box::new(kind.eq(TransactionKind::Adjustment.name()).nullable())
+        }
+        HistoryType::Refund => Box::new(kind.eq(TransactionKind::Refund.name()).nullable()),
+        HistoryType::Cancellation => {
+            Box::new(kind.eq(TransactionKind::Cancellation.name()).nullable())
+        }
+    }
+}
+
+/// Returns the change a transaction causes to the balance of `uid`
+fn balance_delta(transaction: &Transaction, uid: i32) -> i64 {
+    let mut delta = 0;
+    if transaction.receiver_id == Some(uid) {
+        delta += transaction.amount_cents as i64;
+    }
+    if transaction.payer_id == Some(uid) {
+        delta -= transaction.amount_cents as i64;
+    }
+    delta
+}
+
+/// Sum of balance changes from a history entry up to the next one
+#[derive(QueryableByName)]
+struct BalanceChange {
+    /// Position of the entry on the page, counting from the oldest one and
+    /// starting from 1
+    #[diesel(sql_type = Integer)]
+    entry: i32,
+    #[diesel(sql_type = BigInt)]
+    change_cents: i64,
+}
+
+/// Loads a page of transaction history for a user, newest first.
+pub async fn load_history(
+    con: &mut AsyncPgConnection,
+    uid: i32,
+    query: HistoryQuery,
+) -> Result<HistoryResult, Error> {
+    // Read the balance and the ledger from the same snapshot, so that
+    // transactions made meanwhile can't throw off running balances
+    con.build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run(move |con| Box::pin(async move { read_history(con, uid, query).await }))
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("User not found"))
+}
+
+/// Reads a page of transaction history for `load_history`, or nothing if
+/// the user doesn't exist
+async fn read_history(
+    con: &mut AsyncPgConnection,
+    uid: i32,
+    query: HistoryQuery,
+) -> QueryResult<Option<HistoryResult>> {
+    use crate::schema::{items, users};
+    use crate::schema::transactions::dsl::*;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .clamp(1, MAX_HISTORY_LIMIT);
+
+    // Validate user id
+    let Some(balance): Option<i32> = users::table
+        .filter(users::columns::id.eq(uid))
+        .select(users::columns::balance_cents)
+        .get_result(con)
+        .await
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    // Gather page of transactions matching the query
+    let mut db_query = transactions
+        .filter(payer_id.eq(uid).or(receiver_id.eq(uid)))
+        .into_boxed();
+    if let Some(cursor) = query.cursor {
+        db_query = db_query.filter(id.lt(cursor));
+    }
+    if let Some(from) = query.from {
+        db_query = db_query.filter(transacted_at.ge(from));
+    }
+    if let Some(to) = query.to {
+        db_query = db_query.filter(transacted_at.lt(to));
+    }
+    let type_filter = query
+        .types
+        .unwrap_or_default()
+        .into_iter()
+        .map(|history_type| history_type_filter(history_type, uid))
+        .reduce(|a, b| Box::new(a.or(b)));
+    if let Some(type_filter) = type_filter {
+        db_query = db_query.filter(type_filter);
+    }
+    let page = db_query
+        .order(id.desc())
+        .limit(limit)
+        .select(Transaction::as_select())
+        .load(con)
+        .await?;
+    let Some(oldest) = page.last() else {
+        return Ok(Some(HistoryResult {
+            entries: Vec::new(),
+            next_cursor: None,
+        }));
+    };
+
+    // Running balance is the current balance minus everything that happened
+    // after. Filters may have skipped rows, so changes to the balance are
+    // summed up in the database for each page entry along with the rows
+    // after it, up to the next entry. The newest entry also gets every row
+    // newer than the page.
+    let page_ids: Vec<i32> = page.iter().rev().map(|t| t.id).collect();
+    let changes: HashMap<i32, i64> = diesel::sql_query(
+        "SELECT width_bucket(id, $2) AS entry, \
+         SUM(CASE WHEN receiver_id = $1 THEN amount_cents ELSE 0 END \
+         - CASE WHEN payer_id = $1 THEN amount_cents ELSE 0 END) AS change_cents \
+         FROM transactions \
+         WHERE (payer_id = $1 OR receiver_id = $1) AND id >= $3 \
+         GROUP BY entry",
+    )
+    .bind::<Integer, _>(uid)
+    .bind::<Array<Integer>, _>(&page_ids)
+    .bind::<Integer, _>(oldest.id)
+    .load::<BalanceChange>(con)
+    .await?
+    .into_iter()
+    .map(|change| (page_ids[change.entry as usize - 1], change.change_cents))
+    .collect();
+    let mut running_balance = balance as i64;
+    let mut balances_after = HashMap::new();
+    for transaction in &page {
+        let change = changes.get(&transaction.id).copied().unwrap_or(0);
+        balances_after.insert(
+            transaction.id,
+            running_balance - change + balance_delta(transaction, uid),
+        );
+        running_balance -= change;
+    }
+
+    // Batch load names of counterparties and items
+    let counterparty_ids: Vec<i32> = page
+        .iter()
+        .flat_map(|t| [t.payer_id, t.receiver_id])
+        .flatten()
+        .filter(|counterparty_id| *counterparty_id != uid)
+        .collect();
+    let item_ids: Vec<i32> = page.iter().filter_map(|t| t.item_id).collect();
+    let usernames: HashMap<i32, String> = users::table
+        .filter(users::columns::id.eq_any(counterparty_ids))
+        .select((users::columns::id, users::columns::username))
+        .load::<(i32, String)>(con)
+        .await?
+        .into_iter()
+        .collect();
+    let item_titles: HashMap<i32, String> = items::table
+        .filter(items::columns::id.eq_any(item_ids))
+        .select((items::columns::id, items::columns::title))
+        .load::<(i32, String)>(con)
+        .await?
+        .into_iter()
+        .collect();
+
+    // Purchases on the page which have been cancelled
+    let cancelled_ids: Vec<Option<i32>> = transactions
+        .filter(kind.eq(TransactionKind::Cancellation.name()))
+        .filter(reverses_id.eq_any(page_ids))
+        .select(reverses_id)
+        .load(con)
+        .await?;
+
+    let next_cursor = (page.len() as i64 == limit).then_some(oldest.id);
+    let mut entries = Vec::with_capacity(page.len());
+    for transaction in page {
+        let outgoing = transaction.payer_id == Some(uid);
+        let counterparty_id = if outgoing {
+            transaction.receiver_id
+        } else {
+            transaction.payer_id
+        };
+        let entry_type = match TransactionKind::from_name(&transaction.kind) {
+            Some(TransactionKind::Purchase) if outgoing => HistoryType::Purchase,
+            Some(TransactionKind::Purchase) => HistoryType::Sale,
+            Some(TransactionKind::Transfer) => HistoryType::Transfer,
+            Some(TransactionKind::Adjustment) => HistoryType::AdminAdjustment,
+            Some(TransactionKind::Refund) => HistoryType::Refund,
+            Some(TransactionKind::Cancellation) => HistoryType::Cancellation,
+            None => {
+                return Err(diesel::result::Error::DeserializationError(
+                    "Unknown transaction kind".into(),
+                ))
+            }
+        };
+        entries.push(HistoryEntry {
+            entry_type,
+            direction: if outgoing {
+                Direction::Outgoing
+            } else {
+                Direction::Incoming
+            },
+            counterparty: counterparty_id.and_then(|c| usernames.get(&c).cloned()),
+            item_title: transaction
+                .item_id
+                .and_then(|i| item_titles.get(&i).cloned()),
+            delta_cents: balance_delta(&transaction, uid),
+            balance_after_cents: balances_after[&transaction.id],
+            cancelled: cancelled_ids.contains(&Some(transaction.id)),
+            transaction,
+        });
+    }
+
+    Ok(Some(HistoryResult {
+        entries,
+        next_cursor,
+    }))
 }
 
-/// Returns a transaction log for a given user. If no user is provided,
-/// the endpoint will use the user currently logged in. It's possible
-/// to list logs for everyone, but only in debug builds.
+/// Returns transaction history of the user currently logged in, newest
+/// first. See `HistoryQuery` for filters and pagination.
 #[post("/log")]
 pub async fn get_transactions(
     pool: web::Data<BB8Pool>,
-    query: Option<web::Json<LogQuery>>,
+    query: Option<web::Json<HistoryQuery>>,
     session: Session,
 ) -> Result<HttpResponse, Error> {
-    use crate::schema::transactions;
-    use crate::schema::users;
+    let uid =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
 
     // Aquire db connection handle
     let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
 
-    let mut db_query = transactions::table.into_boxed();
-
-    match query {
-        Some(query) => {
-            // Prevent access when not running a debug build
-            if !cfg!(debug_assertions) {
-                return Err(error::ErrorNotFound(
-                    "Feature only available in debug builds",
-                ));
-            };
-            match query.0 {
-                LogQuery::UserId(uid) => {
-                    // Validate user id exists
-                    if users::table
-                        .filter(users::columns::id.eq(uid))
-                        .select(User::as_select())
-                        .load(&mut con)
-                        .await
-                        .map_err(error::ErrorInternalServerError)?
-                        .is_empty()
-                    {
-                        return Err(error::ErrorBadRequest("User not found"));
-                    }
-                    db_query = db_query.filter(transactions::columns::id.eq(uid));
-                }
-                LogQuery::ForEveryone => {}
-            }
-        }
-        None => {
-            // Get currently logged in users id if no query was provided
-            let uid = get_login_uid(&session)?
-                .ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
-            db_query = db_query.filter(transactions::columns::id.eq(uid));
-        }
-    };
-
-    let transactions_result = db_query
-        .select(Transaction::as_select())
-        .load(&mut con)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
-
-    Ok(HttpResponse::Ok().json(transactions_result))
+    let query = query.map(|q| q.into_inner()).unwrap_or_default();
+    let history = load_history(&mut con, uid, query).await?;
+    Ok(HttpResponse::Ok().json(history))
 }
 
+/// Longest memo allowed on transfers
+pub const MAX_MEMO_LENGTH: usize = 200;
+
 #[derive(Serialize, Deserialize)]
 pub struct TransferQuery {
-    amount_cents: i32,
-    recipient: String,
+    pub amount_cents: i32,
+    pub recipient: String,
+    /// What the transfer is for
+    #[serde(default)]
+    pub memo: Option<String>,
 }
 
-/// Transfers money from the logged in user to another user
+/// Trims and validates a memo. Empty memos are left out.
+pub fn validate_memo(memo: Option<&str>) -> Result<Option<String>, Error> {
+    let memo = memo.map(str::trim).filter(|memo| !memo.is_empty());
+    if memo.is_some_and(|memo| memo.chars().count() > MAX_MEMO_LENGTH) {
+        return Err(error::ErrorBadRequest(format!(
+            "Memo can be at most {MAX_MEMO_LENGTH} characters long"
+        )));
+    }
+    Ok(memo.map(str::to_string))
+}
+
+/// Transfers money from the logged in user to another user. Repeats of a
+/// request sent with the same `Idempotency-Key` header are only carried
+/// out once.
 #[post("/transfer")]
 pub async fn transfer(
     pool: web::Data<BB8Pool>,
     query: web::Json<TransferQuery>,
     session: Session,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
-    use crate::schema::{transactions, users};
-
-    // Gather and validate input
     let transactor_id =
         get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    idempotency::run(&pool, &req, transactor_id, &*query, |con| {
+        make_transfer(con, &query, transactor_id).scope_boxed_local()
+    })
+    .await
+}
+
+async fn make_transfer(
+    con: &mut AsyncPgConnection,
+    query: &TransferQuery,
+    transactor_id: i32,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::users;
+
+    // Gather and validate input
     let transfer_amount = query.amount_cents;
     if transfer_amount <= 0 {
         return Err(error::ErrorBadRequest("Transfer amount must be positive"));
     }
+    let memo = validate_memo(query.memo.as_deref())?;
 
-    // Aquire db connection handle
-    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
-
-    let result: Result<Result<(), &str>, diesel::result::Error> = con
+    let result: Result<Result<Transaction, &str>, diesel::result::Error> = con
         .transaction(move |con| {
             Box::pin(async move {
-                // Fetch both users from db and perform checks
-                let users = users::table
-                    .filter(users::columns::id.eq(transactor_id))
-                    .select(User::as_select())
-                    .load(con)
-                    .await?;
-                let transactor = match &users[..] {
-                    [user] => user,
-                    _ => return Ok(Err("Your user does not exist")),
-                };
-                if transactor.balance_cents < transfer_amount {
-                    return Ok(Err("Insufficient funds"));
-                }
-                let users = users::table
+                let recipient_id = users::table
                     .filter(users::columns::username.eq(&query.recipient))
-                    .select(User::as_select())
-                    .load(con)
-                    .await?;
-                let recipient = match &users[..] {
-                    [user] => user,
-                    _ => return Ok(Err("Recipient does not exist")),
+                    .filter(users::columns::deleted_at.is_null())
+                    .select(users::columns::id)
+                    .get_result::<i32>(con)
+                    .await
+                    .optional()?;
+                let Some(recipient_id) = recipient_id else {
+                    return Ok(Err("Recipient does not exist"));
                 };
-
-                // All checks ok, make the transaction
-                try_join!(
-                    // Take balance from seller
-                    diesel::update(users::table)
-                        .filter(users::columns::id.eq(transactor_id))
-                        .set(
-                            users::columns::balance_cents
-                                .eq(users::columns::balance_cents - transfer_amount)
-                        )
-                        .execute(con),
-                    // Append balance to recipient
-                    diesel::update(users::table)
-                        .filter(users::columns::id.eq(recipient.id))
-                        .set(
-                            users::columns::balance_cents
-                                .eq(users::columns::balance_cents + transfer_amount)
-                        )
-                        .execute(con),
-                    // Log transaction
-                    diesel::insert_into(transactions::table)
-                        .values((
-                            transactions::columns::payer_id.eq(transactor.id),
-                            transactions::columns::receiver_id.eq(recipient.id),
-                            transactions::columns::transacted_at.eq(chrono::offset::Utc::now()),
-                            transactions::columns::amount_cents.eq(transfer_amount)
-                        ))
-                        .execute(con),
-                )?;
-
-                Ok(Ok(()))
+                transfer_funds(con, transactor_id, recipient_id, transfer_amount, memo).await
             })
         })
         .await;
@@ -174,15 +487,372 @@ pub async fn transfer(
     Ok(HttpResponse::Ok().body("OK"))
 }
 
+/// Moves money from a user to another and logs it as a transfer. Has to be
+/// run inside of a transaction. Nothing is changed if the payer doesn't have
+/// enough balance or credit, in which case the reason is returned.
+pub async fn transfer_funds(
+    con: &mut AsyncPgConnection,
+    transactor_id: i32,
+    recipient_id: i32,
+    transfer_amount: i32,
+    memo: Option<String>,
+) -> QueryResult<Result<Transaction, &'static str>> {
+    use crate::schema::users;
+
+    // Both users are locked until the end of the transaction, so that
+    // parallel transfers can't spend the same money twice.
+    let users = lock_users(con, &[transactor_id, recipient_id]).await?;
+    let transactor = match users.iter().find(|user| user.id == transactor_id) {
+        Some(user) => user,
+        None => return Ok(Err("Your user does not exist")),
+    };
+    if transactor.spendable_cents() < transfer_amount as i64 {
+        return Ok(Err("Insufficient funds"));
+    }
+
+    // All checks ok, make the transaction
+    try_join!(
+        // Take balance from seller
+        diesel::update(users::table)
+            .filter(users::columns::id.eq(transactor_id))
+            .set(users::columns::balance_cents.eq(users::columns::balance_cents - transfer_amount))
+            .execute(con),
+        // Append balance to recipient
+        diesel::update(users::table)
+            .filter(users::columns::id.eq(recipient_id))
+            .set(users::columns::balance_cents.eq(users::columns::balance_cents + transfer_amount))
+            .execute(con),
+    )?;
+
+    // Log transaction
+    let logged = diesel::insert_into(transactions::table)
+        .values((
+            transactions::columns::payer_id.eq(transactor.id),
+            transactions::columns::receiver_id.eq(recipient_id),
+            transactions::columns::transacted_at.eq(chrono::offset::Utc::now()),
+            transactions::columns::amount_cents.eq(transfer_amount),
+            transactions::columns::kind.eq(TransactionKind::Transfer.name()),
+            transactions::columns::memo.eq(memo),
+        ))
+        .returning(Transaction::as_returning())
+        .get_result(con)
+        .await?;
+
+    Ok(Ok(logged))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefundQuery {
+    /// Purchase to refund
+    pub transaction_id: i32,
+    /// Defaults to everything not refunded yet
+    pub amount_cents: Option<i32>,
+    /// Units of the item returned by the buyer, none by default
+    pub item_amount: Option<i32>,
+    /// Puts the returned units back in stock
+    #[serde(default)]
+    pub restock: bool,
+    pub reason: String,
+}
+
+/// Refunds a purchase, fully or partially, by moving money back from the
+/// seller to the buyer. The refund is logged as a transaction referencing
+/// the purchase, and the refunds of a purchase can't add up to more money
+/// or units than the purchase had. Returned units can be put back in stock.
+/// Can be made by the seller or an admin. Repeats of a request sent with the
+/// same `Idempotency-Key` header are only carried out once.
+#[post("/refund")]
+pub async fn refund_purchase(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<RefundQuery>,
+    session: Session,
+    admin: Option<Authorized<Admin>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let actor_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    let is_admin = admin.is_some();
+    idempotency::run(&pool, &req, actor_id, &*query, |con| {
+        make_refund(con, &query, actor_id, is_admin).scope_boxed_local()
+    })
+    .await
+}
+
+async fn make_refund(
+    con: &mut AsyncPgConnection,
+    query: &RefundQuery,
+    actor_id: i32,
+    is_admin: bool,
+) -> Result<HttpResponse, Error> {
+    // Gather and validate input
+    let purchase_id = query.transaction_id;
+    if query.amount_cents.is_some_and(|amount| amount <= 0) {
+        return Err(error::ErrorBadRequest("Refund amount must be positive"));
+    }
+    let returned_amount = query.item_amount.unwrap_or(0);
+    if returned_amount < 0 {
+        return Err(error::ErrorBadRequest(
+            "Amount of returned items can't be negative",
+        ));
+    }
+    if query.restock && returned_amount == 0 {
+        return Err(error::ErrorBadRequest("Restocking requires returned items"));
+    }
+    let reason = query.reason.trim().to_string();
+    if reason.is_empty() {
+        return Err(error::ErrorBadRequest("Reason is required"));
+    }
+
+    // Only the seller and admins can refund a purchase
+    let purchase = get_purchase(con, purchase_id).await?;
+    if purchase.receiver_id != Some(actor_id) && !is_admin {
+        return Err(error::ErrorForbidden("You can only refund your own sales"));
+    }
+
+    let reversal = Reversal {
+        kind: TransactionKind::Refund,
+        actor_id,
+        amount_cents: query.amount_cents,
+        item_amount: Some(returned_amount),
+        restock: query.restock,
+        reason: Some(reason),
+    };
+    let result = con
+        .transaction(move |con| {
+            Box::pin(async move { reverse_purchase(con, purchase_id, reversal).await })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    let refund = result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    Ok(HttpResponse::Ok().json(refund))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CancelQuery {
+    /// Purchase to cancel
+    pub transaction_id: i32,
+}
+
+/// Cancels a purchase of the logged in user made within the cancellation
+/// window, returning the money to the buyer and the items to stock. Fails if
+/// the seller no longer has the money. Repeats of a request sent with the
+/// same `Idempotency-Key` header are only carried out once.
+#[post("/cancel")]
+pub async fn cancel_purchase(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<CancelQuery>,
+    session: Session,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let buyer_id =
+        get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    idempotency::run(&pool, &req, buyer_id, &*query, |con| {
+        make_cancellation(con, &query, buyer_id).scope_boxed_local()
+    })
+    .await
+}
+
+async fn make_cancellation(
+    con: &mut AsyncPgConnection,
+    query: &CancelQuery,
+    buyer_id: i32,
+) -> Result<HttpResponse, Error> {
+    let purchase_id = query.transaction_id;
+
+    let purchase = get_purchase(con, purchase_id).await?;
+    if purchase.payer_id != Some(buyer_id) {
+        return Err(error::ErrorForbidden(
+            "You can only cancel your own purchases",
+        ));
+    }
+    // A window too long to represent never closes
+    let window_closes_at = chrono::TimeDelta::from_std(cancellation_window())
+        .ok()
+        .and_then(|window| purchase.transacted_at.to_utc().checked_add_signed(window));
+    if window_closes_at.is_some_and(|closes_at| closes_at < chrono::offset::Utc::now()) {
+        return Err(error::ErrorBadRequest(
+            "Purchase can no longer be cancelled",
+        ));
+    }
+
+    // Reverse everything not refunded yet
+    let reversal = Reversal {
+        kind: TransactionKind::Cancellation,
+        actor_id: buyer_id,
+        amount_cents: None,
+        item_amount: None,
+        restock: true,
+        reason: None,
+    };
+    let result = con
+        .transaction(move |con| {
+            Box::pin(async move { reverse_purchase(con, purchase_id, reversal).await })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    let cancellation = result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    Ok(HttpResponse::Ok().json(cancellation))
+}
+
+/// Fetches a purchase transaction
+async fn get_purchase(con: &mut AsyncPgConnection, purchase_id: i32) -> Result<Transaction, Error> {
+    transactions::table
+        .filter(transactions::columns::id.eq(purchase_id))
+        .filter(transactions::columns::kind.eq(TransactionKind::Purchase.name()))
+        .select(Transaction::as_select())
+        .get_result(con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("Purchase not found"))
+}
+
+/// What to reverse of a purchase, and how
+struct Reversal {
+    /// Kind of the transaction logging the reversal
+    kind: TransactionKind,
+    actor_id: i32,
+    /// Defaults to all the money not reversed yet
+    amount_cents: Option<i32>,
+    /// Units of the item returned, defaulting to every unit not returned yet
+    item_amount: Option<i32>,
+    /// Puts the returned units back in stock
+    restock: bool,
+    reason: Option<String>,
+}
+
+/// Moves money of a purchase back from the seller to the buyer, and logs it
+/// as a transaction referencing the purchase. Has to be run inside of a
+/// transaction. Reversals of a purchase can't add up to more money or units
+/// than the purchase had. Returned units no longer count as sold.
+async fn reverse_purchase(
+    con: &mut AsyncPgConnection,
+    purchase_id: i32,
+    reversal: Reversal,
+) -> QueryResult<Result<Transaction, &'static str>> {
+    use crate::schema::{items, users};
+
+    // Lock the purchase, so that parallel reversals of it can't both pass the
+    // checks
+    let purchase = transactions::table
+        .filter(transactions::columns::id.eq(purchase_id))
+        .select(Transaction::as_select())
+        .for_update()
+        .get_result(con)
+        .await?;
+    let (Some(buyer_id), Some(seller_id)) = (purchase.payer_id, purchase.receiver_id) else {
+        return Ok(Err("Purchase has no buyer or seller to refund"));
+    };
+    let (refunded_cents, returned_items): (Option<i64>, Option<i64>) = transactions::table
+        .filter(transactions::columns::reverses_id.eq(purchase_id))
+        .select((
+            diesel::dsl::sum(transactions::columns::amount_cents),
+            diesel::dsl::sum(transactions::columns::item_amount),
+        ))
+        .get_result(con)
+        .await?;
+    let refundable_cents = i64::from(purchase.amount_cents) - refunded_cents.unwrap_or(0);
+    let returnable_items = i64::from(purchase.item_amount) - returned_items.unwrap_or(0);
+    if refundable_cents <= 0 {
+        return Ok(Err("Purchase has already been refunded"));
+    }
+    let refund_cents = reversal.amount_cents.map_or(refundable_cents, i64::from);
+    if refund_cents > refundable_cents {
+        return Ok(Err("Refunds can't exceed the amount of the purchase"));
+    }
+    let returned_amount = reversal.item_amount.map_or(returnable_items, i64::from);
+    if returned_amount > returnable_items {
+        return Ok(Err("Returned items can't exceed the amount bought"));
+    }
+    // Both are at most the amounts of the purchase
+    let refund_cents = refund_cents as i32;
+    let returned_amount = returned_amount as i32;
+
+    let users = lock_users(con, &[buyer_id, seller_id]).await?;
+    match users.iter().find(|user| user.id == buyer_id) {
+        Some(buyer) if buyer.deleted_at.is_none() => (),
+        _ => return Ok(Err("Buyer's account has been deleted")),
+    }
+    match users.iter().find(|user| user.id == seller_id) {
+        // Refunds come out of earned money, never out of the seller's credit
+        Some(seller) if seller.balance_cents >= refund_cents => (),
+        _ => return Ok(Err("Seller doesn't have enough balance for the refund")),
+    }
+
+    // All checks ok, make the reversal
+    diesel::update(users::table)
+        .filter(users::columns::id.eq(seller_id))
+        .set(users::columns::balance_cents.eq(users::columns::balance_cents - refund_cents))
+        .execute(con)
+        .await?;
+    diesel::update(users::table)
+        .filter(users::columns::id.eq(buyer_id))
+        .set(users::columns::balance_cents.eq(users::columns::balance_cents + refund_cents))
+        .execute(con)
+        .await?;
+    if let (Some(item_id), true) = (purchase.item_id, returned_amount > 0) {
+        let restocked = if reversal.restock { returned_amount } else { 0 };
+        diesel::update(items::table)
+            .filter(items::columns::id.eq(item_id))
+            .set((
+                items::columns::amount.eq(items::columns::amount + restocked),
+                items::columns::units_sold.eq(items::columns::units_sold - returned_amount),
+            ))
+            .execute(con)
+            .await?;
+    }
+    let logged = diesel::insert_into(transactions::table)
+        .values((
+            transactions::columns::item_id.eq(purchase.item_id),
+            transactions::columns::payer_id.eq(seller_id),
+            transactions::columns::receiver_id.eq(buyer_id),
+            transactions::columns::item_amount.eq(returned_amount),
+            transactions::columns::amount_cents.eq(refund_cents),
+            transactions::columns::transacted_at.eq(chrono::offset::Utc::now()),
+            transactions::columns::kind.eq(reversal.kind.name()),
+            transactions::columns::actor_id.eq(reversal.actor_id),
+            transactions::columns::reason.eq(reversal.reason),
+            transactions::columns::reverses_id.eq(purchase_id),
+        ))
+        .returning(Transaction::as_returning())
+        .get_result(con)
+        .await?;
+
+    Ok(Ok(logged))
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
     use reqwest::Result;
 
-    use crate::api::{admin::AdminGiveQuery, user::UserQuery};
+    use crate::api::admin::{AdminGiveQuery, AdminHistoryQuery};
+    use crate::api::item::{BuyQuery, NewItemQuery};
+    use crate::api::user::UserQuery;
+    use crate::models::{Item, User};
 
     use super::*;
-    const URL: &str = "http://backend:3030";
+    const URL: &str = "http://localhost:3030";
+
+    #[test]
+    fn memo_validation_works() {
+        assert_eq!(validate_memo(Some(" lunch ")).ok(), Some(Some("lunch".to_string())));
+        assert_eq!(validate_memo(Some("  ")).ok(), Some(None));
+        assert_eq!(validate_memo(None).ok(), Some(None));
+
+        // Length is counted in characters rather than bytes
+        let memo = "ä".repeat(MAX_MEMO_LENGTH);
+        assert_eq!(validate_memo(Some(&memo)).ok(), Some(Some(memo.clone())));
+        assert!(validate_memo(Some(&format!("{memo}ä"))).is_err());
+    }
 
     // Test currency transferring
     #[test]
@@ -202,7 +872,7 @@ mod tests {
         assert_eq!(
             result.status(),
             200,
-            "Could not clear db. Make sure the server is compiled in debug mode."
+            "Could not clear db. Make sure the server is running in development mode."
         );
 
         // Register test users and log them in to their clients
@@ -227,7 +897,12 @@ mod tests {
         // Give currency to user 1
         let result = client
             .post(format!("{URL}/api/admin/give"))
-            .json(&AdminGiveQuery{amount_cents: 111, user_id: None})
+            .json(&AdminGiveQuery {
+                amount_cents: 111,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
             .send()?;
         assert_eq!(result.status(), 200, "Could not give currency to user via admin give query");
 
@@ -237,6 +912,7 @@ mod tests {
             .json(&TransferQuery {
                 amount_cents: -10,
                 recipient: "test2".to_string(),
+                memo: None,
             })
             .send()?;
         assert_eq!(result.status(), 400, "Allowed negative currency transfer");
@@ -247,6 +923,7 @@ mod tests {
             .json(&TransferQuery {
                 amount_cents: 10,
                 recipient: "test2".to_string(),
+                memo: None,
             })
             .send()?;
         assert_eq!(result.status(), 200, "Could not transfer currency");
@@ -265,6 +942,7 @@ mod tests {
             .json(&TransferQuery {
                 amount_cents: 150,
                 recipient: "test2".to_string(),
+                memo: None,
             })
             .send()?;
         assert!(result.status() == 400, "Transfer didn't fail when user didn't have enough balance");
@@ -272,4 +950,396 @@ mod tests {
         Ok(())
 
     }
+
+    // Test transaction history contents, pagination and filters
+    #[test]
+    fn transaction_history() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users and log them in to their clients
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let user2: User = result.json()?;
+
+        // Buy from the second user and transfer money both ways
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: 1000,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not give currency to user");
+        let result = client2
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "test item".to_string(),
+                description: "test description".to_string(),
+                amount: 5,
+                price: "1".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+        let result = client
+            .post(format!("{URL}/api/item/buy"))
+            .json(&BuyQuery { item_id: item.id, amount: Some(2) })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not buy an item");
+        let result = client
+            .post(format!("{URL}/api/transfer"))
+            .json(&TransferQuery { amount_cents: 50, recipient: "test2".to_string(), memo: Some(" lunch ".to_string()) })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not transfer currency");
+        let result = client2
+            .post(format!("{URL}/api/transfer"))
+            .json(&TransferQuery { amount_cents: 30, recipient: "test".to_string(), memo: None })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not transfer currency");
+
+        // Whole history, newest first
+        let result = client.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        let entries = &history.entries;
+        assert_eq!(entries.len(), 4, "History has wrong amount of entries");
+        assert_eq!(entries[0].direction, Direction::Incoming);
+        assert_eq!(entries[0].counterparty.as_deref(), Some("test2"));
+        assert_eq!(entries[0].balance_after_cents, 1000 - 200 - 50 + 30);
+        assert_eq!(entries[1].entry_type, HistoryType::Transfer);
+        assert_eq!(entries[1].delta_cents, -50);
+        assert_eq!(entries[1].transaction.memo.as_deref(), Some("lunch"), "Memo wasn't stored trimmed");
+        assert_eq!(entries[0].transaction.memo, None);
+        assert_eq!(entries[2].entry_type, HistoryType::Purchase);
+        assert_eq!(entries[2].item_title.as_deref(), Some("test item"));
+        assert_eq!(entries[2].balance_after_cents, 1000 - 200);
+        assert_eq!(entries[3].entry_type, HistoryType::AdminAdjustment);
+        assert_eq!(entries[3].balance_after_cents, 1000);
+        assert_eq!(history.next_cursor, None);
+
+        // Paginate
+        let result = client
+            .post(format!("{URL}/api/log"))
+            .json(&HistoryQuery { limit: Some(3), ..Default::default() })
+            .send()?;
+        let page: HistoryResult = result.json()?;
+        assert_eq!(page.entries.len(), 3, "First page has wrong amount of entries");
+        let result = client
+            .post(format!("{URL}/api/log"))
+            .json(&HistoryQuery { limit: Some(3), cursor: page.next_cursor, ..Default::default() })
+            .send()?;
+        let page: HistoryResult = result.json()?;
+        assert_eq!(page.entries.len(), 1, "Second page has wrong amount of entries");
+        assert_eq!(page.entries[0].balance_after_cents, 1000, "Running balance differs between pages");
+        assert_eq!(page.next_cursor, None);
+
+        // Filter by type and date
+        let result = client2
+            .post(format!("{URL}/api/log"))
+            .json(&HistoryQuery { types: Some(vec![HistoryType::Sale]), ..Default::default() })
+            .send()?;
+        let sales: HistoryResult = result.json()?;
+        assert_eq!(sales.entries.len(), 1, "Type filter returned wrong entries");
+        assert_eq!(sales.entries[0].delta_cents, 200);
+        assert_eq!(sales.entries[0].balance_after_cents, 200, "Running balance ignores filtered rows");
+        let result = client
+            .post(format!("{URL}/api/log"))
+            .json(&HistoryQuery { types: Some(vec![HistoryType::Transfer, HistoryType::AdminAdjustment]), ..Default::default() })
+            .send()?;
+        let filtered: HistoryResult = result.json()?;
+        let balances: Vec<i64> = filtered.entries.iter().map(|entry| entry.balance_after_cents).collect();
+        assert_eq!(balances, vec![1000 - 200 - 50 + 30, 1000 - 200 - 50, 1000], "Running balance ignores rows skipped between entries");
+        let result = client
+            .post(format!("{URL}/api/log"))
+            .json(&HistoryQuery { from: Some(chrono::offset::Utc::now() + chrono::Duration::days(1)), ..Default::default() })
+            .send()?;
+        let future: HistoryResult = result.json()?;
+        assert!(future.entries.is_empty(), "Date filter returned entries");
+
+        // Admin variant for any user
+        let result = client
+            .post(format!("{URL}/api/admin/log"))
+            .json(&AdminHistoryQuery { user_id: user2.id, query: HistoryQuery::default() })
+            .send()?;
+        let history2: HistoryResult = result.json()?;
+        assert_eq!(history2.entries.len(), 3, "Admin history has wrong amount of entries");
+        assert_eq!(history2.entries[0].direction, Direction::Outgoing);
+
+        Ok(())
+    }
+
+    // Test refunding purchases fully and partially
+    #[test]
+    fn refund_operations() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users and log them in to their clients
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+
+        // Buy three items from the second user
+        let result = client
+            .post(format!("{URL}/api/admin/give"))
+            .json(&AdminGiveQuery {
+                amount_cents: 500,
+                user_id: None,
+                kind: AdjustmentKind::CashDeposit,
+                reason: "test".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not give currency to user");
+        let result = client2
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "test item".to_string(),
+                description: "test description".to_string(),
+                amount: 5,
+                price: "1".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+        let result = client
+            .post(format!("{URL}/api/item/buy"))
+            .json(&BuyQuery { item_id: item.id, amount: Some(3) })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not buy an item");
+        let result = client.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        let purchase = &history.entries[0].transaction;
+
+        let refund = |query: &RefundQuery| client2.post(format!("{URL}/api/refund")).json(query).send();
+        let refund_query = |amount_cents: Option<i32>, item_amount: Option<i32>, restock: bool| RefundQuery {
+            transaction_id: purchase.id,
+            amount_cents,
+            item_amount,
+            restock,
+            reason: "wrong item".to_string(),
+        };
+
+        // Refund a part of the purchase and put a returned item back in stock
+        let result = refund(&refund_query(Some(100), Some(1), true))?;
+        assert_eq!(result.status(), 200, "Could not refund a part of a purchase");
+        let refunded: Transaction = result.json()?;
+        assert_eq!(refunded.reverses_id, Some(purchase.id));
+        assert_eq!(refunded.payer_id, purchase.receiver_id);
+        assert_eq!(refunded.receiver_id, purchase.payer_id);
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 500 - 300 + 100, "Buyer didn't get the refund");
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let user2: User = result.json()?;
+        assert_eq!(user2.balance_cents, 300 - 100, "Seller didn't pay the refund");
+        let result = client.get(format!("{URL}/api/item/{}", item.id)).send()?;
+        let restocked: Item = result.json()?;
+        assert_eq!(restocked.amount, 5 - 3 + 1, "Returned item wasn't restocked");
+        assert_eq!(restocked.units_sold, 2, "Returned item still counts as sold");
+
+        // Refunds can't add up to more than the purchase
+        let result = refund(&refund_query(Some(201), None, false))?;
+        assert_eq!(result.status(), 400, "Refunded more money than was left of the purchase");
+        let result = refund(&refund_query(Some(100), Some(3), false))?;
+        assert_eq!(result.status(), 400, "Returned more items than were left of the purchase");
+        let result = refund(&refund_query(None, Some(2), false))?;
+        assert_eq!(result.status(), 200, "Could not refund the rest of a purchase");
+        let refunded: Transaction = result.json()?;
+        assert_eq!(refunded.amount_cents, 200, "Refund didn't default to the rest of the purchase");
+        let result = refund(&refund_query(None, None, false))?;
+        assert_eq!(result.status(), 400, "Refunded a purchase twice");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 500, "Buyer didn't get everything back");
+
+        // Refunds show up in history of the buyer
+        let result = client
+            .post(format!("{URL}/api/log"))
+            .json(&HistoryQuery { types: Some(vec![HistoryType::Refund]), ..Default::default() })
+            .send()?;
+        let refunds: HistoryResult = result.json()?;
+        assert_eq!(refunds.entries.len(), 2);
+        assert!(refunds.entries.iter().all(|entry| entry.direction == Direction::Incoming));
+
+        // Only purchases can be refunded
+        let result = client
+            .post(format!("{URL}/api/transfer"))
+            .json(&TransferQuery { amount_cents: 50, recipient: "test2".to_string(), memo: None })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not transfer currency");
+        let result = client.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        let result = refund(&RefundQuery {
+            transaction_id: history.entries[0].transaction.id,
+            amount_cents: None,
+            item_amount: None,
+            restock: false,
+            reason: "test".to_string(),
+        })?;
+        assert_eq!(result.status(), 400, "Refunded a transfer");
+
+        Ok(())
+    }
+
+    // Test buyers cancelling their purchases
+    #[test]
+    fn purchase_cancellation() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Register test users and log them in to their clients
+        for (client, username) in [(&client, "test"), (&client2, "test2")] {
+            let result = client
+                .post(format!("{URL}/api/user/new"))
+                .json(&UserQuery {
+                    username: username.to_string(),
+                    password: "test".to_string(),
+                })
+                .send()?;
+            assert_eq!(result.status(), 200, "Could not create a new user");
+        }
+
+        // Buy two items from the second user
+        let give = |client: &reqwest::blocking::Client, amount_cents: i32, kind: AdjustmentKind| {
+            client
+                .post(format!("{URL}/api/admin/give"))
+                .json(&AdminGiveQuery { amount_cents, user_id: None, kind, reason: "test".to_string() })
+                .send()
+        };
+        let result = give(&client, 500, AdjustmentKind::CashDeposit)?;
+        assert_eq!(result.status(), 200, "Could not give currency to user");
+        let result = client2
+            .post(format!("{URL}/api/item/new"))
+            .json(&NewItemQuery {
+                title: "test item".to_string(),
+                description: "test description".to_string(),
+                amount: 5,
+                price: "1".to_string(),
+                attachments: Vec::new(),
+                category_id: None,
+                tags: Vec::new(),
+                barcode: None,
+            })
+            .send()?;
+        let item: Item = result.json()?;
+        let result = client
+            .post(format!("{URL}/api/item/buy"))
+            .json(&BuyQuery { item_id: item.id, amount: Some(2) })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not buy an item");
+        let result = client.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        let cancel_query = CancelQuery { transaction_id: history.entries[0].transaction.id };
+
+        // Only the buyer can cancel a purchase
+        let result = client2.post(format!("{URL}/api/cancel")).json(&cancel_query).send()?;
+        assert_eq!(result.status(), 403, "Seller cancelled a purchase");
+
+        // Purchases can't be cancelled after the seller has withdrawn the money
+        let result = give(&client2, -150, AdjustmentKind::CashWithdrawal)?;
+        assert_eq!(result.status(), 200, "Could not withdraw currency");
+        let result = client.post(format!("{URL}/api/cancel")).json(&cancel_query).send()?;
+        assert_eq!(result.status(), 400, "Cancelled a purchase with the money withdrawn");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 300, "Failed cancellation changed balance");
+
+        // Cancelling returns the money and the items
+        let result = give(&client2, 150, AdjustmentKind::CashDeposit)?;
+        assert_eq!(result.status(), 200, "Could not give currency to user");
+        let result = client.post(format!("{URL}/api/cancel")).json(&cancel_query).send()?;
+        assert_eq!(result.status(), 200, "Could not cancel a purchase");
+        let result = client.post(format!("{URL}/api/cancel")).json(&cancel_query).send()?;
+        assert_eq!(result.status(), 400, "Cancelled a purchase twice");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        let user: User = result.json()?;
+        assert_eq!(user.balance_cents, 500, "Buyer didn't get the money back");
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        let user2: User = result.json()?;
+        assert_eq!(user2.balance_cents, 0, "Seller kept the money");
+        let result = client.get(format!("{URL}/api/item/{}", item.id)).send()?;
+        let restocked: Item = result.json()?;
+        assert_eq!(restocked.amount, 5, "Cancelled items weren't restocked");
+        assert_eq!(restocked.units_sold, 0, "Cancelled items still count as sold");
+
+        // History shows the purchase as cancelled
+        let result = client.post(format!("{URL}/api/log")).send()?;
+        let history: HistoryResult = result.json()?;
+        assert_eq!(history.entries[0].entry_type, HistoryType::Cancellation);
+        assert_eq!(history.entries[0].direction, Direction::Incoming);
+        assert!(!history.entries[0].cancelled);
+        assert_eq!(history.entries[1].entry_type, HistoryType::Purchase);
+        assert!(history.entries[1].cancelled, "Purchase wasn't shown as cancelled");
+
+        Ok(())
+    }
 }
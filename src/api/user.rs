@@ -1,20 +1,30 @@
 use actix_session::Session;
+use actix_web::http::header;
 use actix_web::post;
-use actix_web::{error, get, web, Error, HttpResponse};
+use actix_web::{error, get, web, Error, HttpRequest, HttpResponse};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Argon2, Params};
 use diesel::prelude::*;
 use diesel::ExpressionMethods;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
 use diesel_async::RunQueryDsl;
+use log::*;
 use serde::Deserialize;
 use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::LazyLock;
 
+use super::login_throttle;
+use super::payment_request::PaymentRequestStatus;
+use super::roles::{get_roles, Role};
 use super::validation::validators;
 
-use crate::models::User;
+use crate::models::{User, UserSession};
+use crate::session_store::{IP_ADDRESS_KEY, LOGGED_IN_KEY, SESSION_ID_KEY, USER_AGENT_KEY};
+use crate::settings::{dev_mode, trusted_proxies};
 use crate::BB8Pool;
 
-const LOGGED_IN_KEY: &str = "logged_in";
-
 #[derive(Serialize, Deserialize)]
 pub struct UserQuery {
     pub username: String,
@@ -28,30 +38,169 @@ pub fn get_login_uid(session: &Session) -> Result<Option<i32>, Error> {
         .map_err(error::ErrorInternalServerError)
 }
 
-fn set_login_uid(session: &Session, uid: i32) -> Result<(), Error> {
+/// Locks rows of given users until the end of the surrounding transaction
+/// and returns them. Rows are locked in id order, so that transactions
+/// locking the same users can't deadlock each other.
+pub async fn lock_users(con: &mut AsyncPgConnection, ids: &[i32]) -> QueryResult<Vec<User>> {
+    use crate::schema::users::dsl::*;
+
+    users
+        .filter(id.eq_any(ids))
+        .order(id)
+        .select(User::as_select())
+        .for_update()
+        .load(con)
+        .await
+}
+
+/// Retrieves id of the session's row in db. Not available on the request
+/// creating the session.
+fn get_session_id(session: &Session) -> Result<Option<i32>, Error> {
+    session
+        .get::<i32>(SESSION_ID_KEY)
+        .map_err(error::ErrorInternalServerError)
+}
+
+/// Returns the ip address a request came from. Forwarding headers can be set
+/// by anyone, so they are only believed on requests made through a trusted
+/// proxy.
+fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    if !trusted_proxies().contains(&peer) {
+        return Some(peer.to_string());
+    }
+    let forwarded = req
+        .connection_info()
+        .realip_remote_addr()
+        .and_then(|address| {
+            address
+                .parse::<IpAddr>()
+                .or_else(|_| address.parse::<SocketAddr>().map(|address| address.ip()))
+                .ok()
+        })
+        .unwrap_or(peer);
+    Some(forwarded.to_string())
+}
+
+/// Logs user in to a fresh session, recording where the session was created from
+fn set_login_uid(session: &Session, req: &HttpRequest, uid: i32) -> Result<(), Error> {
+    // Renew session key to prevent session fixation
+    session.renew();
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(200).collect::<String>());
+    let ip_address = client_ip(req);
     session
         .insert(LOGGED_IN_KEY, uid)
+        .and_then(|_| session.insert(USER_AGENT_KEY, user_agent))
+        .and_then(|_| session.insert(IP_ADDRESS_KEY, ip_address))
         .map_err(error::ErrorInternalServerError)
 }
 
-// Get salt from environment on first access
-static SALT: LazyLock<String> = LazyLock::new(|| match std::env::var("SALT") {
-    Ok(val) => val,
+// Get legacy salt from environment on first access. The salt is only needed
+// for verifying password hashes created before the switch to Argon2.
+static LEGACY_SALT: LazyLock<Option<String>> = LazyLock::new(|| match std::env::var("SALT") {
+    Ok(val) => Some(val),
     Err(_) => {
-        // Allow use of development values only in debug builds
-        if !cfg!(debug_assertions) {
-            panic!("Environment variable SALT not set! Password salt is required for security.");
+        // Allow use of development values only in development mode, and in
+        // unit tests which don't run against a server
+        if dev_mode() || cfg!(test) {
+            Some("defaultsalt".to_string())
+        } else {
+            None
         }
-        "defaultsalt".to_string()
     }
 });
 
-/// Hash password with salt
-fn hash(pass: &str) -> String {
+/// Hash password with Argon2id and a random per-user salt. The result is
+/// a PHC string, which carries the salt and hashing parameters with it.
+fn hash(pass: &str) -> argon2::password_hash::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(pass.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Runs `hash` on a thread pool, as hashing is expensive enough to stall
+/// the server if done on the async executor.
+async fn hash_blocking(pass: &str) -> Result<String, Error> {
+    let pass = pass.to_string();
+    web::block(move || hash(&pass))
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .map_err(error::ErrorInternalServerError)
+}
+
+/// Hash password the way it was done before Argon2: one round of blake3
+/// with a global salt.
+fn legacy_hash(pass: &str) -> Option<blake3::Hash> {
+    let salt = LEGACY_SALT.as_ref()?;
     let mut hasher = blake3::Hasher::new();
-    hasher.update(SALT.as_bytes());
+    hasher.update(salt.as_bytes());
     hasher.update(pass.as_bytes());
-    hasher.finalize().to_string()
+    Some(hasher.finalize())
+}
+
+#[derive(Debug, PartialEq)]
+enum Verification {
+    Invalid,
+    Valid,
+    /// Password is correct, but the stored hash is legacy or uses outdated
+    /// parameters and should be replaced.
+    ValidNeedsRehash,
+}
+
+/// Verifies password against a stored hash, which can either be an Argon2
+/// PHC string or a legacy blake3 hash.
+fn verify(pass: &str, stored_hash: &str) -> Verification {
+    match PasswordHash::new(stored_hash) {
+        Ok(parsed_hash) => {
+            if Argon2::default()
+                .verify_password(pass.as_bytes(), &parsed_hash)
+                .is_err()
+            {
+                return Verification::Invalid;
+            }
+            let current = Params::default();
+            let up_to_date = parsed_hash.algorithm == argon2::ARGON2ID_IDENT
+                && Params::try_from(&parsed_hash).is_ok_and(|params| {
+                    params.m_cost() == current.m_cost()
+                        && params.t_cost() == current.t_cost()
+                        && params.p_cost() == current.p_cost()
+                });
+            if up_to_date {
+                Verification::Valid
+            } else {
+                Verification::ValidNeedsRehash
+            }
+        }
+        Err(_) => {
+            let Ok(stored_hash) = blake3::Hash::from_hex(stored_hash) else {
+                return Verification::Invalid;
+            };
+            let Some(pass_hash) = legacy_hash(pass) else {
+                warn!("Could not verify a legacy password hash, because environment variable SALT is not set");
+                return Verification::Invalid;
+            };
+            // Comparison of blake3 hashes is constant time
+            if pass_hash == stored_hash {
+                Verification::ValidNeedsRehash
+            } else {
+                Verification::Invalid
+            }
+        }
+    }
+}
+
+/// Runs `verify` on a thread pool, see `hash_blocking`.
+async fn verify_blocking(pass: &str, stored_hash: &str) -> Result<Verification, Error> {
+    let pass = pass.to_string();
+    let stored_hash = stored_hash.to_string();
+    web::block(move || verify(&pass, &stored_hash))
+        .await
+        .map_err(error::ErrorInternalServerError)
 }
 
 /// Registers a new user to the database.
@@ -60,11 +209,12 @@ pub async fn new_user(
     pool: web::Data<BB8Pool>,
     query: web::Json<UserQuery>,
     session: Session,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     use crate::schema::users::dsl::*;
 
-    // Skip validation when running debug builds, so tests don't have to be rewritten
-    if !cfg!(debug_assertions) {
+    // Skip validation in development mode, so tests don't have to be rewritten
+    if !dev_mode() {
         // Validate username
         if let Err(error) = validators::username(&query.username) {
             return Err(error::ErrorBadRequest(error));
@@ -89,7 +239,7 @@ pub async fn new_user(
         return Err(error::ErrorBadRequest("User already registered"));
     }
 
-    let password = hash(&query.password);
+    let password = hash_blocking(&query.password).await?;
 
     // Insert into db
     let user = diesel::insert_into(crate::schema::users::table)
@@ -104,47 +254,362 @@ pub async fn new_user(
         .map_err(error::ErrorInternalServerError)?;
 
     // Log new user in
-    set_login_uid(&session, user.id)?;
+    set_login_uid(&session, &req, user.id)?;
     Ok(HttpResponse::Ok().body("OK"))
 }
 
-/// Logs user in and saves session into a cookie.
+/// Logs user in and saves session key into a cookie. Failed attempts are
+/// throttled per username and per ip address, see `login_throttle`.
 #[post("/user/login")]
 pub async fn login(
     pool: web::Data<BB8Pool>,
     query: web::Json<UserQuery>,
     session: Session,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     use crate::schema::users::dsl::*;
 
+    let ip_address = client_ip(&req);
+
     let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
 
+    login_throttle::check(&mut con, &query.username, ip_address.as_deref()).await?;
+
     let results = users
         .filter(username.eq(&query.username))
+        .filter(deleted_at.is_null())
         .select(User::as_select())
         .load(&mut con)
         .await
         .map_err(error::ErrorInternalServerError)?;
     if let [user] = &results[..] {
-        if user.password_hash == hash(&query.password) {
-            set_login_uid(&session, user.id)?;
+        let verification = verify_blocking(&query.password, &user.password_hash).await?;
+
+        if verification == Verification::ValidNeedsRehash {
+            // Upgrade stored hash while we have the plaintext password at hand
+            let new_hash = hash_blocking(&query.password).await?;
+            diesel::update(users)
+                .filter(id.eq(user.id))
+                .set(password_hash.eq(new_hash))
+                .execute(&mut con)
+                .await
+                .map_err(error::ErrorInternalServerError)?;
+            info!("Upgraded password hash of user {}", user.id);
+        }
+
+        if verification != Verification::Invalid {
+            login_throttle::reset(&mut con, &query.username).await?;
+            set_login_uid(&session, &req, user.id)?;
             return Ok(HttpResponse::Ok().body("OK"));
         }
     }
 
+    login_throttle::record_failure(&mut con, &query.username, ip_address.as_deref()).await?;
     Err(error::ErrorUnauthorized("Incorrect login"))
 }
 
 /// Logs user out.
 #[get("/user/logout")]
 pub async fn logout(session: Session) -> Result<HttpResponse, Error> {
-    if session.remove(LOGGED_IN_KEY).is_some() {
+    if get_login_uid(&session)?.is_some() {
+        session.purge();
         Ok(HttpResponse::Ok().body("OK"))
     } else {
         Err(error::ErrorUnauthorized("Not logged in"))
     }
 }
 
+/// Logs user out from every session, including the current one.
+#[get("/user/logout/all")]
+pub async fn logout_everywhere(
+    pool: web::Data<BB8Pool>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::sessions::dsl::*;
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    diesel::delete(sessions)
+        .filter(user_id.eq(uid))
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    session.purge();
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SessionResult {
+    #[serde(flatten)]
+    pub session: UserSession,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
+/// Lists active sessions of the user logged in.
+#[get("/user/sessions")]
+pub async fn list_sessions(
+    pool: web::Data<BB8Pool>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::sessions::dsl::*;
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    let current_session_id = get_session_id(&session)?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    let results = sessions
+        .filter(user_id.eq(uid))
+        .filter(expires_at.gt(chrono::offset::Utc::now()))
+        .order(last_seen_at.desc())
+        .select(UserSession::as_select())
+        .load(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|s| SessionResult {
+            current: Some(s.id) == current_session_id,
+            session: s,
+        })
+        .collect::<Vec<SessionResult>>();
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RevokeSessionQuery {
+    pub session_id: i32,
+}
+
+/// Revokes one of the sessions of the user logged in, logging that
+/// session out.
+#[post("/user/sessions/revoke")]
+pub async fn revoke_session(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<RevokeSessionQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::sessions::dsl::*;
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+    let removed = diesel::delete(sessions)
+        .filter(id.eq(query.session_id))
+        .filter(user_id.eq(uid)) // Validate ownership
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if removed == 0 {
+        return Err(error::ErrorBadRequest("Session not found"));
+    }
+
+    if get_session_id(&session)? == Some(query.session_id) {
+        session.purge();
+    }
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordQuery {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Changes password of the user logged in. The current password is
+/// required, so that a session left open can't be used to hijack the account.
+#[post("/user/password")]
+pub async fn change_password(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<ChangePasswordQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::sessions;
+    use crate::schema::users::dsl::*;
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+    validators::password(&query.new_password).map_err(error::ErrorBadRequest)?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let user = users
+        .filter(id.eq(uid))
+        .filter(deleted_at.is_null())
+        .select(User::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("User not found"))?;
+    if verify_blocking(&query.current_password, &user.password_hash).await? == Verification::Invalid
+    {
+        return Err(error::ErrorUnauthorized("Incorrect password"));
+    }
+
+    let new_hash = hash_blocking(&query.new_password).await?;
+    diesel::update(users)
+        .filter(id.eq(uid))
+        .set(password_hash.eq(new_hash))
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    // Log out other sessions, in case the password was changed because of a leak
+    let current_session_id = get_session_id(&session)?;
+    let mut db_query = diesel::delete(sessions::table)
+        .filter(sessions::columns::user_id.eq(uid))
+        .into_boxed();
+    if let Some(current_session_id) = current_session_id {
+        db_query = db_query.filter(sessions::columns::id.ne(current_session_id));
+    }
+    db_query
+        .execute(&mut con)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteUserQuery {
+    pub password: String,
+}
+
+/// Closes the account of the user logged in. The user row is anonymised
+/// instead of being removed, so items, attachments and transactions
+/// referring to it stay intact. The account balance has to be zero, as
+/// money can't be recovered from a closed account.
+#[post("/user/delete")]
+pub async fn delete_user(
+    pool: web::Data<BB8Pool>,
+    query: web::Json<DeleteUserQuery>,
+    session: Session,
+) -> Result<HttpResponse, Error> {
+    use crate::schema::{cart_items, items, payment_requests, roles, sessions, user_roles, users};
+
+    let uid = get_login_uid(&session)?.ok_or_else(|| error::ErrorUnauthorized("Not logged in"))?;
+
+    let mut con = pool.get().await.map_err(error::ErrorInternalServerError)?;
+
+    let user = users::table
+        .filter(users::columns::id.eq(uid))
+        .filter(users::columns::deleted_at.is_null())
+        .select(User::as_select())
+        .get_result(&mut con)
+        .await
+        .optional()
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorBadRequest("User not found"))?;
+    if verify_blocking(&query.password, &user.password_hash).await? == Verification::Invalid {
+        return Err(error::ErrorUnauthorized("Incorrect password"));
+    }
+
+    let result: Result<Result<(), &str>, diesel::result::Error> = con
+        .transaction(move |con| {
+            Box::pin(async move {
+                // Check balance inside the transaction, so that money can't slip in meanwhile
+                let balance: i32 = users::table
+                    .filter(users::columns::id.eq(uid))
+                    .select(users::columns::balance_cents)
+                    .for_update()
+                    .get_result(con)
+                    .await?;
+                if balance != 0 {
+                    return Ok(Err(
+                        "Account balance must be zero before the account can be deleted",
+                    ));
+                }
+
+                // Lock the admins' rows, so that the last two admins can't both leave at once
+                let admins: Vec<i32> = user_roles::table
+                    .inner_join(roles::table)
+                    .filter(roles::columns::name.eq(Role::Admin.name()))
+                    .select(user_roles::columns::user_id)
+                    .for_update()
+                    .load(con)
+                    .await?;
+                if admins == [uid] {
+                    return Ok(Err("The last admin can't delete their account"));
+                }
+
+                // Anonymise user. Dashes are not allowed in usernames, so the
+                // placeholder name can't collide with a registered one.
+                diesel::update(users::table)
+                    .filter(users::columns::id.eq(uid))
+                    .set((
+                        users::columns::username.eq(format!("deleted-{uid}")),
+                        users::columns::password_hash.eq(""),
+                        users::columns::deleted_at.eq(chrono::offset::Utc::now()),
+                    ))
+                    .execute(con)
+                    .await?;
+
+                // Take users items off sale
+                diesel::update(items::table)
+                    .filter(items::columns::seller_id.eq(uid))
+                    .set(items::columns::amount.eq(0))
+                    .execute(con)
+                    .await?;
+
+                // Release stock reserved in users cart
+                diesel::delete(cart_items::table)
+                    .filter(cart_items::columns::user_id.eq(uid))
+                    .execute(con)
+                    .await?;
+
+                // Nobody can pay or be paid by a closed account
+                diesel::update(payment_requests::table)
+                    .filter(
+                        payment_requests::columns::requester_id
+                            .eq(uid)
+                            .or(payment_requests::columns::payer_id.eq(uid)),
+                    )
+                    .filter(
+                        payment_requests::columns::status.eq(PaymentRequestStatus::Pending.name()),
+                    )
+                    .set((
+                        payment_requests::columns::status
+                            .eq(PaymentRequestStatus::Cancelled.name()),
+                        payment_requests::columns::resolved_at.eq(chrono::offset::Utc::now()),
+                    ))
+                    .execute(con)
+                    .await?;
+
+                // Log user out everywhere and strip privileges
+                diesel::delete(sessions::table)
+                    .filter(sessions::columns::user_id.eq(uid))
+                    .execute(con)
+                    .await?;
+                diesel::delete(user_roles::table)
+                    .filter(user_roles::columns::user_id.eq(uid))
+                    .execute(con)
+                    .await?;
+
+                Ok(Ok(()))
+            })
+        })
+        .await;
+
+    // Propagate errors from transaction
+    result
+        .map_err(error::ErrorInternalServerError)? // Error executing the transaction
+        .map_err(error::ErrorBadRequest)?; // Error from inside of the transaction
+
+    session.purge();
+    Ok(HttpResponse::Ok().body("OK"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserResult {
+    #[serde(flatten)]
+    pub user: User,
+    pub roles: Vec<Role>,
+    /// Whether the balance is below zero
+    pub in_debt: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 enum GetUserQuery {
     Username(String),
@@ -200,7 +665,14 @@ pub async fn user_info(
         .get_result(&mut con)
         .await
         .map_err(error::ErrorInternalServerError)?;
-    Ok(HttpResponse::Ok().json(user))
+    let roles = get_roles(&mut con, uid)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(UserResult {
+        in_debt: user.balance_cents < 0,
+        user,
+        roles,
+    }))
 }
 
 #[cfg(test)]
@@ -209,7 +681,25 @@ mod tests {
     use std::sync::Arc;
 
     use super::*;
-    const URL: &str = "http://backend:3030";
+    const URL: &str = "http://localhost:3030";
+
+    // Password hashing and verification tests
+    #[test]
+    fn password_hashing_works() {
+        let password = "Password1!";
+        let hashed = hash(password).unwrap();
+        assert!(hashed.starts_with("$argon2id$"));
+        assert_ne!(hashed, hash(password).unwrap(), "Salt is not unique");
+        assert_eq!(verify(password, &hashed), Verification::Valid);
+        assert_eq!(verify("password1!", &hashed), Verification::Invalid);
+
+        // Legacy hashes are accepted, but flagged for upgrading
+        let legacy = legacy_hash(password).unwrap().to_string();
+        assert_eq!(verify(password, &legacy), Verification::ValidNeedsRehash);
+        assert_eq!(verify("password1!", &legacy), Verification::Invalid);
+
+        assert_eq!(verify(password, "garbage"), Verification::Invalid);
+    }
 
     // Test account operations
     #[test]
@@ -229,7 +719,7 @@ mod tests {
         assert_eq!(
             result.status(),
             200,
-            "Could not clear db. Make sure the server is compiled in debug mode."
+            "Could not clear db. Make sure the server is running in development mode."
         );
 
         // Log in with nonexsistent user
@@ -277,6 +767,302 @@ mod tests {
             "Logout returned wrong status without a valid session"
         );
 
+        // Log back in
+        let result = client
+            .post(format!("{URL}/api/user/login"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not log in");
+
+        // Change password with incorrect current password
+        let new_password = "Password1!".to_string();
+        let result = client
+            .post(format!("{URL}/api/user/password"))
+            .json(&ChangePasswordQuery {
+                current_password: "wrong".to_string(),
+                new_password: new_password.clone(),
+            })
+            .send()?;
+        assert_eq!(
+            result.status(),
+            401,
+            "Password could be changed without the current password"
+        );
+
+        // Change password to a weak one
+        let result = client
+            .post(format!("{URL}/api/user/password"))
+            .json(&ChangePasswordQuery {
+                current_password: user_query.password.clone(),
+                new_password: "weak".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Password could be changed to a weak one");
+
+        // Change password
+        let result = client
+            .post(format!("{URL}/api/user/password"))
+            .json(&ChangePasswordQuery {
+                current_password: user_query.password.clone(),
+                new_password: new_password.clone(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not change password");
+
+        // Log in with the old and the new password
+        let result = client
+            .post(format!("{URL}/api/user/login"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 401, "Could log in with the old password");
+        let user_query = UserQuery {
+            username: user_query.username,
+            password: new_password,
+        };
+        let result = client
+            .post(format!("{URL}/api/user/login"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not log in with the new password");
+
+        // Delete account with incorrect password
+        let result = client
+            .post(format!("{URL}/api/user/delete"))
+            .json(&DeleteUserQuery {
+                password: "wrong".to_string(),
+            })
+            .send()?;
+        assert_eq!(
+            result.status(),
+            401,
+            "Account could be deleted without the password"
+        );
+
+        // Delete account
+        let result = client
+            .post(format!("{URL}/api/user/delete"))
+            .json(&DeleteUserQuery {
+                password: user_query.password.clone(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not delete account");
+
+        // Deleting an account logs user out
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        assert_eq!(result.status(), 401, "User stayed logged in after deletion");
+
+        // Deleted account can't be logged in to or looked up by username
+        let result = client
+            .post(format!("{URL}/api/user/login"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 401, "Could log in to a deleted account");
+        let result = client
+            .post(format!("{URL}/api/user"))
+            .json(&GetUserQuery::Username(user_query.username.clone()))
+            .send()?;
+        assert_eq!(
+            result.status(),
+            400,
+            "Deleted account could be looked up by username"
+        );
+
+        Ok(())
+    }
+
+    // Test listing and revoking sessions
+    #[test]
+    fn session_operations() -> Result<()> {
+        // Set things up for testing, with two clients sharing a user
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let cookie_provider2 = Arc::new(reqwest::cookie::Jar::default());
+        let client2 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider2.clone())
+            .build()?;
+        let user_query = UserQuery {
+            username: "test".to_string(),
+            password: "test".to_string(),
+        };
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Create a new user and log it in from the other client, which
+        // tries to pass off as coming from elsewhere
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+        let result = client2
+            .post(format!("{URL}/api/user/login"))
+            .header("X-Forwarded-For", "203.0.113.7")
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not log in");
+
+        // List sessions
+        let result = client.get(format!("{URL}/api/user/sessions")).send()?;
+        assert_eq!(result.status(), 200, "Could not list sessions");
+        let sessions: Vec<SessionResult> = result.json()?;
+        assert_eq!(sessions.len(), 2, "Unexpected amount of sessions");
+        let [current] = &sessions.iter().filter(|s| s.current).collect::<Vec<_>>()[..] else {
+            panic!("Current session was not flagged");
+        };
+        let other = sessions.iter().find(|s| !s.current).unwrap();
+        assert_eq!(
+            other.session.ip_address.as_deref(),
+            Some("127.0.0.1"),
+            "Forwarding header of an untrusted client was believed"
+        );
+
+        // Revoke the other session
+        let result = client
+            .post(format!("{URL}/api/user/sessions/revoke"))
+            .json(&RevokeSessionQuery {
+                session_id: other.session.id,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not revoke session");
+        let result = client2.post(format!("{URL}/api/user")).send()?;
+        assert_eq!(result.status(), 401, "Revoked session stayed logged in");
+        let result = client.post(format!("{URL}/api/user")).send()?;
+        assert_eq!(result.status(), 200, "Revoking a session logged out the wrong one");
+
+        // Sessions of other users can't be revoked
+        let result = client2
+            .post(format!("{URL}/api/user/login"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not log in");
+        let result = client2
+            .post(format!("{URL}/api/user/sessions/revoke"))
+            .json(&RevokeSessionQuery {
+                session_id: current.session.id + 1000,
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could revoke a nonexistent session");
+        let cookie_provider3 = Arc::new(reqwest::cookie::Jar::default());
+        let client3 = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider3.clone())
+            .build()?;
+        let result = client3
+            .post(format!("{URL}/api/user/new"))
+            .json(&UserQuery {
+                username: "test2".to_string(),
+                password: "test2".to_string(),
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+        let result = client3.get(format!("{URL}/api/user/sessions")).send()?;
+        let sessions: Vec<SessionResult> = result.json()?;
+        let result = client2
+            .post(format!("{URL}/api/user/sessions/revoke"))
+            .json(&RevokeSessionQuery {
+                session_id: sessions[0].session.id,
+            })
+            .send()?;
+        assert_eq!(result.status(), 400, "Could revoke a session of another user");
+        let result = client3.post(format!("{URL}/api/user")).send()?;
+        assert_eq!(result.status(), 200, "Session of another user was revoked");
+
+        // Log out everywhere
+        let result = client.get(format!("{URL}/api/user/logout/all")).send()?;
+        assert_eq!(result.status(), 200, "Could not log out everywhere");
+        for client in [&client, &client2] {
+            let result = client.post(format!("{URL}/api/user")).send()?;
+            assert_eq!(
+                result.status(),
+                401,
+                "Session stayed logged in after logging out everywhere"
+            );
+        }
+
+        Ok(())
+    }
+
+    // Test throttling failed logins
+    #[test]
+    fn login_throttling() -> Result<()> {
+        // Set things up for testing
+        let cookie_provider = Arc::new(reqwest::cookie::Jar::default());
+        let client = reqwest::blocking::ClientBuilder::new()
+            .cookie_provider(cookie_provider.clone())
+            .build()?;
+        let user_query = UserQuery {
+            username: "test".to_string(),
+            password: "test".to_string(),
+        };
+        let bad_query = UserQuery {
+            username: "test".to_string(),
+            password: "wrong".to_string(),
+        };
+
+        // Clear database for testing
+        let result = client.get(format!("{URL}/api/admin/db/clear")).send()?;
+        assert_eq!(
+            result.status(),
+            200,
+            "Could not clear db. Make sure the server is running in development mode."
+        );
+
+        // Create a new user
+        let result = client
+            .post(format!("{URL}/api/user/new"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not create a new user");
+
+        // Fail logging in until back-off kicks in
+        let mut status = reqwest::StatusCode::UNAUTHORIZED;
+        for _ in 0..5 {
+            let result = client
+                .post(format!("{URL}/api/user/login"))
+                .json(&bad_query)
+                .send()?;
+            status = result.status();
+            if status == 429 {
+                assert!(
+                    result.headers().contains_key("retry-after"),
+                    "Throttled login didn't tell when to retry"
+                );
+                break;
+            }
+            assert_eq!(status, 401, "Login returned ok status with bad password");
+        }
+        assert_eq!(status, 429, "Failed logins were not throttled");
+
+        // Throttling applies to correct passwords too
+        let result = client
+            .post(format!("{URL}/api/user/login"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 429, "Throttled user could log in");
+
+        // Unlock user as admin
+        let result = client
+            .post(format!("{URL}/api/admin/login/unlock"))
+            .json(&crate::api::admin::AdminUnlockLoginQuery {
+                username: Some(user_query.username.clone()),
+                ip_address: None,
+            })
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not unlock login");
+
+        let result = client
+            .post(format!("{URL}/api/user/login"))
+            .json(&user_query)
+            .send()?;
+        assert_eq!(result.status(), 200, "Could not log in after unlocking");
+
         Ok(())
     }
 }
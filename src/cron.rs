@@ -5,12 +5,21 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::time::{self, Duration};
 
+use crate::api::login_throttle::FAILURE_MEMORY;
+use crate::api::reconciliation;
 use crate::models::Attachment;
 use crate::BB8Pool;
 
 const CRON_FREQUENCY: usize = 300;
 /// Remove dangling attachments after they are older than this, in seconds
 const DANGLING_ATTACHMENT_TIMEOUT: u64 = 60 * 10;
+/// Remove attachments of archived and deleted items after they have been
+/// archived for this long, in seconds
+const ARCHIVED_ATTACHMENT_RETENTION: u64 = 60 * 60 * 24 * 30;
+/// Remove cart lines after their reservation has been expired for this long, in seconds
+const ABANDONED_CART_TIMEOUT: u64 = 60 * 60 * 24 * 7;
+/// Reconcile balances against the ledger this often, in seconds
+const RECONCILIATION_INTERVAL: u64 = 60 * 60 * 24;
 
 /// Spawns a new task which invokes cron() periodically until `stop` evaluates true
 pub async fn start(stop_flag: Arc<AtomicBool>, pool: BB8Pool) -> Result<(), ()> {
@@ -33,12 +42,84 @@ pub async fn start(stop_flag: Arc<AtomicBool>, pool: BB8Pool) -> Result<(), ()>
 /// The actual cron
 async fn cron(pool: BB8Pool) -> Result<(), String> {
     use crate::schema::attachments::dsl::*;
+    use crate::schema::{
+        cart_items, idempotency_keys, items, login_throttles, reconciliation_runs, sessions,
+    };
 
     // Aquire connection to db
     let mut con = pool.get().await.map_err(|a| a.to_string())?;
 
-    // Remove old enough attachments not bound to any item
     let now = chrono::offset::Utc::now();
+
+    // Remove expired sessions
+    let removed_sessions = diesel_async::RunQueryDsl::execute(
+        diesel::delete(sessions::table).filter(sessions::columns::expires_at.lt(now)),
+        &mut con,
+    )
+    .await
+    .map_err(|s| s.to_string())?;
+    if removed_sessions > 0 {
+        info!("Cleaned {removed_sessions} expired sessions");
+    }
+
+    // Forget failed logins after a quiet period, unless the subject is still blocked
+    diesel_async::RunQueryDsl::execute(
+        diesel::delete(login_throttles::table)
+            .filter(login_throttles::columns::last_failure_at.lt(now - FAILURE_MEMORY))
+            .filter(
+                login_throttles::columns::blocked_until
+                    .is_null()
+                    .or(login_throttles::columns::blocked_until.lt(now)),
+            ),
+        &mut con,
+    )
+    .await
+    .map_err(|s| s.to_string())?;
+
+    // Remove idempotency keys past their replay window
+    diesel_async::RunQueryDsl::execute(
+        diesel::delete(idempotency_keys::table)
+            .filter(idempotency_keys::columns::expires_at.lt(now)),
+        &mut con,
+    )
+    .await
+    .map_err(|s| s.to_string())?;
+
+    // Remove lines of abandoned carts
+    diesel_async::RunQueryDsl::execute(
+        diesel::delete(cart_items::table).filter(
+            cart_items::columns::reserved_until
+                .lt(now - Duration::from_secs(ABANDONED_CART_TIMEOUT)),
+        ),
+        &mut con,
+    )
+    .await
+    .map_err(|s| s.to_string())?;
+
+    // Reconcile balances against the ledger, unless cron has done so recently
+    let last_reconciled: Option<chrono::DateTime<chrono::Utc>> = diesel_async::RunQueryDsl::get_result(
+        reconciliation_runs::table
+            .filter(reconciliation_runs::columns::triggered_by.is_null())
+            .select(diesel::dsl::max(reconciliation_runs::columns::ran_at)),
+        &mut con,
+    )
+    .await
+    .map_err(|s| s.to_string())?;
+    if last_reconciled
+        .is_none_or(|ran_at| ran_at < now - Duration::from_secs(RECONCILIATION_INTERVAL))
+    {
+        let result = reconciliation::reconcile(&mut con, None)
+            .await
+            .map_err(|s| s.to_string())?;
+        let discrepant_users = result.run.discrepant_users;
+        if discrepant_users > 0 {
+            warn!("Reconciliation found {discrepant_users} users with balances not matching the ledger");
+        } else {
+            info!("Reconciliation found no discrepancies");
+        }
+    }
+
+    // Remove old enough attachments not bound to any item
     let oldest_accepted_timestamp = now - Duration::from_secs(DANGLING_ATTACHMENT_TIMEOUT);
 
     let removed_db_rows: Vec<Attachment> = diesel_async::RunQueryDsl::get_results(
@@ -50,19 +131,41 @@ async fn cron(pool: BB8Pool) -> Result<(), String> {
     .await
     .map_err(|s| s.to_string())?;
 
-    // Remove associated files
-    for row in &removed_db_rows {
-        for attachment_file in [&row.file_path, &row.thumbnail_path] {
-            async_fs::remove_file(attachment_file)
-                .await
-                .map_err(|s| s.to_string())?;
-        }
-    }
-
     let removed_amount = removed_db_rows.len();
     if removed_amount > 0 {
         info!("Cleaned {removed_amount} attachments without associated items");
     }
 
+    // Remove attachments of items archived long enough ago. The items
+    // themselves are kept for transaction history.
+    let archived_before = now - Duration::from_secs(ARCHIVED_ATTACHMENT_RETENTION);
+    let expired_db_rows: Vec<Attachment> = diesel_async::RunQueryDsl::get_results(
+        diesel::delete(attachments).filter(
+            item_id.eq_any(
+                items::table
+                    .filter(items::columns::archived_at.lt(archived_before))
+                    .select(items::columns::id.nullable()),
+            ),
+        ),
+        &mut con,
+    )
+    .await
+    .map_err(|s| s.to_string())?;
+
+    let expired_amount = expired_db_rows.len();
+    if expired_amount > 0 {
+        info!("Cleaned {expired_amount} attachments of archived items");
+    }
+
+    // Remove associated files. Their rows are gone already, so a file which
+    // can't be removed is only logged, and the rest are still removed.
+    for row in removed_db_rows.iter().chain(&expired_db_rows) {
+        for attachment_file in [&row.file_path, &row.thumbnail_path] {
+            if let Err(err) = async_fs::remove_file(attachment_file).await {
+                warn!("Could not remove attachment file {attachment_file}: {err}");
+            }
+        }
+    }
+
     Ok(())
 }
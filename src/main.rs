@@ -1,6 +1,5 @@
 use actix_files::Files;
-use actix_session::config::{CookieContentSecurity, PersistentSession};
-use actix_session::storage::CookieSessionStore;
+use actix_session::config::{CookieContentSecurity, PersistentSession, TtlExtensionPolicy};
 use actix_session::SessionMiddleware;
 use actix_web::cookie::time::Duration;
 use actix_web::cookie::Key;
@@ -19,6 +18,7 @@ use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use diesel_migrations::MigrationHarness;
 use diesel::Connection;
+use log::*;
 
 pub type BB8Pool = Pool<diesel_async::AsyncPgConnection>;
 
@@ -26,6 +26,8 @@ mod api;
 mod cron;
 mod models;
 mod schema;
+mod session_store;
+mod settings;
 
 /// Run database migrations
 fn run_migrations(db_url: &str) {
@@ -40,14 +42,29 @@ async fn main() -> std::io::Result<()> {
     // TODO: More secure logging
     pretty_env_logger::init();
 
+    // Refuse to expose development mode to the network, and make it obvious when it's on
+    let bind_address = settings::bind_address()
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+    if settings::dev_mode() {
+        if !bind_address.is_loopback() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Refusing to run in development mode on non-loopback address {bind_address}"),
+            ));
+        }
+        warn!("!!! DEVELOPMENT MODE IS ON !!!");
+        warn!("Every user has every role, input validation is off and the database can be cleared by anyone.");
+        warn!("Never run development mode on a server reachable by others.");
+    }
+
     // Generate data directories if they don't exist
     let _ = DirBuilder::new().create("./public").await;
 
-    // Cookie session middleware vars
+    // Session middleware vars
     let secret_key_str = std::env::var("SESSION_SECRET")
         .unwrap_or_else(|_| {
-            // Allow use of development values only in debug builds
-            if !cfg!(debug_assertions) {
+            // Allow use of development values only in development mode
+            if !settings::dev_mode() {
                 panic!("Environment variable SESSION_SECRET not set! Session secret is used for cookie cryptography and is mandatory for security.");
             }
             "sessionsecretsecretsecretsecretsecret".to_string()
@@ -91,14 +108,19 @@ async fn main() -> std::io::Result<()> {
     // Spawn actix server task
     let actix = tokio::task::spawn(
         HttpServer::new(move || {
-            let cookie_middleware = SessionMiddleware::builder(
-                CookieSessionStore::default(),
+            let session_middleware = SessionMiddleware::builder(
+                session_store::PgSessionStore::new(diesel_connection_pool.clone()),
                 cookie_secret_key.clone(),
             )
-            .session_lifecycle(PersistentSession::default().session_ttl(COOKIE_TTL))
+            // Extending ttl on every request keeps track of when sessions were last used
+            .session_lifecycle(
+                PersistentSession::default()
+                    .session_ttl(COOKIE_TTL)
+                    .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+            )
             .cookie_content_security(CookieContentSecurity::Private)
-            // Don't use secure cookies in debug builds as they require use of https, which would complicate testing
-            .cookie_secure(!cfg!(debug_assertions))
+            // Don't use secure cookies in development mode as they require use of https, which would complicate testing
+            .cookie_secure(!settings::dev_mode())
             .build();
             let headers_middleware =
                 middleware::DefaultHeaders::new().add(("content-type", "text/html; charset=UTF-8"));
@@ -107,13 +129,13 @@ async fn main() -> std::io::Result<()> {
             App::new()
                 .app_data(web::Data::new(diesel_connection_pool.clone()))
                 .wrap(headers_middleware)
-                .wrap(cookie_middleware)
+                .wrap(session_middleware)
                 .wrap(logger_middleware)
                 .configure(api::config)
                 .service(Files::new("/public", "public"))
                 .service(Files::new("/", "dist").index_file("index.html"))
         })
-        .bind(("0.0.0.0", 3030))?
+        .bind((bind_address, settings::PORT))?
         .run(),
     );
 
@@ -13,7 +13,16 @@ pub struct User {
     pub password_hash: String,
     pub balance_cents: i32,
     pub created_at: chrono::DateTime<chrono::Local>,
-    pub is_admin: bool,
+    pub deleted_at: Option<chrono::DateTime<chrono::Local>>,
+    /// How far below zero the balance may go
+    pub credit_limit_cents: i32,
+}
+
+impl User {
+    /// Amount the user can spend, counting in their credit
+    pub fn spendable_cents(&self) -> i64 {
+        self.balance_cents as i64 + self.credit_limit_cents as i64
+    }
 }
 
 #[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
@@ -29,6 +38,35 @@ pub struct Item {
     pub amount: i32,
     pub seller_id: i32,
     pub created_at: chrono::DateTime<chrono::Local>,
+    pub version: i32,
+    pub archived_at: Option<chrono::DateTime<chrono::Local>>,
+    pub deleted_at: Option<chrono::DateTime<chrono::Local>>,
+    pub category_id: Option<i32>,
+    pub units_sold: i32,
+    pub last_sold_at: Option<chrono::DateTime<chrono::Local>>,
+    /// EAN-13 barcode of the good
+    pub barcode: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::categories)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct Category {
+    pub id: i32,
+    pub name: String,
+    pub created_at: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(Item, foreign_key = item_id))]
+#[diesel(primary_key(item_id, tag))]
+#[diesel(table_name = crate::schema::item_tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct ItemTag {
+    pub item_id: i32,
+    pub tag: String,
 }
 
 #[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
@@ -56,7 +94,191 @@ pub struct Transaction {
     pub id: i32,
     pub item_id: Option<i32>,
     pub payer_id: Option<i32>,
-    pub receiver_id: i32,
+    pub receiver_id: Option<i32>,
     pub item_amount: i32,
     pub transacted_at: chrono::DateTime<chrono::Local>,
+    pub amount_cents: i32,
+    pub kind: String,
+    /// Admin who made the adjustment
+    pub actor_id: Option<i32>,
+    pub adjustment_kind: Option<String>,
+    pub reason: Option<String>,
+    /// Order the transaction was made in, if it was bought through a cart
+    pub order_id: Option<i32>,
+    /// Purchase the transaction refunds
+    pub reverses_id: Option<i32>,
+    /// What a transfer was made for
+    pub memo: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::payment_requests)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct PaymentRequest {
+    pub id: i32,
+    pub requester_id: i32,
+    pub payer_id: i32,
+    pub amount_cents: i32,
+    pub memo: Option<String>,
+    pub status: String,
+    pub created_at: chrono::DateTime<chrono::Local>,
+    pub resolved_at: Option<chrono::DateTime<chrono::Local>>,
+    /// Transfer made when the request was accepted
+    pub transaction_id: Option<i32>,
+    /// Bill the request is a share of
+    pub bill_id: Option<i32>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(User, foreign_key = creator_id))]
+#[diesel(table_name = crate::schema::bills)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct Bill {
+    pub id: i32,
+    pub creator_id: i32,
+    pub total_cents: i32,
+    pub memo: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(primary_key(barcode))]
+#[diesel(table_name = crate::schema::products)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct Product {
+    pub barcode: String,
+    pub title: String,
+    pub description: String,
+    pub imported_at: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::product_imports)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct ProductImport {
+    pub id: i32,
+    pub file_name: String,
+    pub started_by: i32,
+    pub started_at: chrono::DateTime<chrono::Local>,
+    /// Empty while the import is still running
+    pub finished_at: Option<chrono::DateTime<chrono::Local>>,
+    /// Distinct products added or updated
+    pub imported: i32,
+    /// Lines which weren't valid text or lacked a valid barcode or a name
+    pub skipped: i32,
+    /// Why the import stopped early, if it did
+    pub failure: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(belongs_to(Item, foreign_key = item_id))]
+#[diesel(table_name = crate::schema::cart_items)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct CartItem {
+    pub id: i32,
+    pub user_id: i32,
+    pub item_id: i32,
+    pub amount: i32,
+    pub added_at: chrono::DateTime<chrono::Local>,
+    pub reserved_until: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(User, foreign_key = buyer_id))]
+#[diesel(table_name = crate::schema::orders)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct Order {
+    pub id: i32,
+    pub buyer_id: i32,
+    pub total_cents: i32,
+    pub created_at: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::login_throttles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct LoginThrottle {
+    pub id: i32,
+    pub kind: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: chrono::DateTime<chrono::Local>,
+    pub blocked_until: Option<chrono::DateTime<chrono::Local>>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(table_name = crate::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct UserSession {
+    pub id: i32,
+    #[serde(skip)]
+    pub key_hash: String,
+    #[serde(skip)]
+    pub state: String,
+    pub user_id: Option<i32>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Local>,
+    pub last_seen_at: chrono::DateTime<chrono::Local>,
+    pub expires_at: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(table_name = crate::schema::idempotency_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct IdempotencyKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub key: String,
+    /// Hash of the path and body of the first request made with the key
+    pub request_hash: String,
+    pub response_status: Option<i32>,
+    pub response_content_type: Option<String>,
+    pub response_body: Option<Vec<u8>>,
+    pub created_at: chrono::DateTime<chrono::Local>,
+    pub expires_at: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, PartialEq)]
+#[diesel(table_name = crate::schema::reconciliation_runs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct ReconciliationRun {
+    pub id: i32,
+    pub ran_at: chrono::DateTime<chrono::Local>,
+    pub triggered_by: Option<i32>,
+    /// Sum of all user balances
+    pub money_supply_cents: i64,
+    /// Sum of all user balances according to the ledger
+    pub ledger_supply_cents: i64,
+    pub discrepant_users: i32,
+    pub money_supply_drift_cents: Option<i64>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(ReconciliationRun, foreign_key = run_id))]
+#[diesel(table_name = crate::schema::reconciliation_discrepancies)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Serialize, Deserialize)]
+pub struct ReconciliationDiscrepancy {
+    pub id: i32,
+    pub run_id: i32,
+    pub user_id: i32,
+    pub balance_cents: i64,
+    pub ledger_cents: i64,
+    /// Balance minus ledger
+    pub discrepancy_cents: i64,
+    /// Change of discrepancy since the previous run
+    pub drift_cents: i64,
 }
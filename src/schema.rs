@@ -11,6 +11,49 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    bills (id) {
+        id -> Int4,
+        creator_id -> Int4,
+        total_cents -> Int4,
+        memo -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    cart_items (id) {
+        id -> Int4,
+        user_id -> Int4,
+        item_id -> Int4,
+        amount -> Int4,
+        added_at -> Timestamptz,
+        reserved_until -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    categories (id) {
+        id -> Int4,
+        name -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    idempotency_keys (id) {
+        id -> Int4,
+        user_id -> Int4,
+        key -> Varchar,
+        request_hash -> Varchar,
+        response_status -> Nullable<Int4>,
+        response_content_type -> Nullable<Varchar>,
+        response_body -> Nullable<Bytea>,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     items (id) {
         id -> Int4,
@@ -20,6 +63,123 @@ diesel::table! {
         amount -> Int4,
         seller_id -> Int4,
         created_at -> Timestamptz,
+        version -> Int4,
+        archived_at -> Nullable<Timestamptz>,
+        deleted_at -> Nullable<Timestamptz>,
+        category_id -> Nullable<Int4>,
+        units_sold -> Int4,
+        last_sold_at -> Nullable<Timestamptz>,
+        barcode -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    item_tags (item_id, tag) {
+        item_id -> Int4,
+        tag -> Varchar,
+    }
+}
+
+diesel::table! {
+    login_throttles (id) {
+        id -> Int4,
+        kind -> Varchar,
+        subject -> Varchar,
+        failures -> Int4,
+        last_failure_at -> Timestamptz,
+        blocked_until -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    orders (id) {
+        id -> Int4,
+        buyer_id -> Int4,
+        total_cents -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    payment_requests (id) {
+        id -> Int4,
+        requester_id -> Int4,
+        payer_id -> Int4,
+        amount_cents -> Int4,
+        memo -> Nullable<Varchar>,
+        status -> Varchar,
+        created_at -> Timestamptz,
+        resolved_at -> Nullable<Timestamptz>,
+        transaction_id -> Nullable<Int4>,
+        bill_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    product_imports (id) {
+        id -> Int4,
+        file_name -> Varchar,
+        started_by -> Int4,
+        started_at -> Timestamptz,
+        finished_at -> Nullable<Timestamptz>,
+        imported -> Int4,
+        skipped -> Int4,
+        failure -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    products (barcode) {
+        barcode -> Varchar,
+        title -> Varchar,
+        description -> Varchar,
+        imported_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    reconciliation_discrepancies (id) {
+        id -> Int4,
+        run_id -> Int4,
+        user_id -> Int4,
+        balance_cents -> Int8,
+        ledger_cents -> Int8,
+        discrepancy_cents -> Int8,
+        drift_cents -> Int8,
+    }
+}
+
+diesel::table! {
+    reconciliation_runs (id) {
+        id -> Int4,
+        ran_at -> Timestamptz,
+        triggered_by -> Nullable<Int4>,
+        money_supply_cents -> Int8,
+        ledger_supply_cents -> Int8,
+        discrepant_users -> Int4,
+        money_supply_drift_cents -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> Int4,
+        name -> Varchar,
+        description -> Varchar,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Int4,
+        key_hash -> Varchar,
+        state -> Varchar,
+        user_id -> Nullable<Int4>,
+        user_agent -> Nullable<Varchar>,
+        ip_address -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        expires_at -> Timestamptz,
     }
 }
 
@@ -30,8 +190,24 @@ diesel::table! {
         payer_id -> Nullable<Int4>,
         item_amount -> Int4,
         transacted_at -> Timestamptz,
-        receiver_id -> Int4,
+        receiver_id -> Nullable<Int4>,
         amount_cents -> Int4,
+        kind -> Varchar,
+        actor_id -> Nullable<Int4>,
+        adjustment_kind -> Nullable<Varchar>,
+        reason -> Nullable<Varchar>,
+        order_id -> Nullable<Int4>,
+        reverses_id -> Nullable<Int4>,
+        memo -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role_id) {
+        user_id -> Int4,
+        role_id -> Int4,
+        granted_by -> Nullable<Int4>,
+        granted_at -> Timestamptz,
     }
 }
 
@@ -41,19 +217,50 @@ diesel::table! {
         username -> Varchar,
         password_hash -> Varchar,
         balance_cents -> Int4,
-        is_admin -> Bool,
         created_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+        credit_limit_cents -> Int4,
     }
 }
 
 diesel::joinable!(attachments -> items (item_id));
 diesel::joinable!(attachments -> users (uploader_id));
+diesel::joinable!(bills -> users (creator_id));
+diesel::joinable!(cart_items -> items (item_id));
+diesel::joinable!(cart_items -> users (user_id));
+diesel::joinable!(idempotency_keys -> users (user_id));
+diesel::joinable!(item_tags -> items (item_id));
+diesel::joinable!(items -> categories (category_id));
 diesel::joinable!(items -> users (seller_id));
+diesel::joinable!(orders -> users (buyer_id));
+diesel::joinable!(payment_requests -> bills (bill_id));
+diesel::joinable!(payment_requests -> transactions (transaction_id));
+diesel::joinable!(product_imports -> users (started_by));
+diesel::joinable!(reconciliation_discrepancies -> reconciliation_runs (run_id));
+diesel::joinable!(reconciliation_discrepancies -> users (user_id));
+diesel::joinable!(sessions -> users (user_id));
 diesel::joinable!(transactions -> items (item_id));
+diesel::joinable!(transactions -> orders (order_id));
+diesel::joinable!(user_roles -> roles (role_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     attachments,
+    bills,
+    cart_items,
+    categories,
+    idempotency_keys,
+    item_tags,
     items,
+    login_throttles,
+    orders,
+    payment_requests,
+    product_imports,
+    products,
+    reconciliation_discrepancies,
+    reconciliation_runs,
+    roles,
+    sessions,
     transactions,
+    user_roles,
     users,
 );
@@ -0,0 +1,184 @@
+use actix_session::storage::{
+    generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use diesel::prelude::*;
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use std::collections::HashMap;
+
+use crate::models::UserSession;
+use crate::BB8Pool;
+
+/// Session state key holding the id of the user logged in
+pub const LOGGED_IN_KEY: &str = "logged_in";
+/// Session state key holding the user agent the session was created with
+pub const USER_AGENT_KEY: &str = "user_agent";
+/// Session state key holding the ip address the session was created from
+pub const IP_ADDRESS_KEY: &str = "ip_address";
+/// Session state key holding the id of the session's row in db. It is
+/// inserted on load and never persisted.
+pub const SESSION_ID_KEY: &str = "session_id";
+
+type SessionState = HashMap<String, String>;
+
+/// Session store keeping sessions in Postgres. Unlike cookie sessions, these
+/// can be listed and revoked server side.
+///
+/// Session keys are stored hashed, so that a leaked database can't be used
+/// to hijack sessions. Known values of session state, such as the user id,
+/// are copied to their own columns to make them queryable.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: BB8Pool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: BB8Pool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Hashes a session key for storing and looking up
+fn hash_key(session_key: &SessionKey) -> String {
+    blake3::hash(session_key.as_ref().as_bytes()).to_string()
+}
+
+fn expiry(ttl: &Duration) -> chrono::DateTime<chrono::Utc> {
+    chrono::offset::Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+/// Reads a JSON encoded value from session state
+fn state_value<T: serde::de::DeserializeOwned>(state: &SessionState, key: &str) -> Option<T> {
+    state
+        .get(key)
+        .and_then(|value| serde_json::from_str(value).ok())
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        use crate::schema::sessions::dsl::*;
+
+        let mut con = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| LoadError::Other(e.into()))?;
+        let session = sessions
+            .filter(key_hash.eq(hash_key(session_key)))
+            .filter(expires_at.gt(chrono::offset::Utc::now()))
+            .select(UserSession::as_select())
+            .get_result(&mut con)
+            .await
+            .optional()
+            .map_err(|e| LoadError::Other(e.into()))?;
+
+        match session {
+            Some(session) => {
+                let mut session_state: SessionState = serde_json::from_str(&session.state)
+                    .map_err(|e| LoadError::Deserialization(e.into()))?;
+                session_state.insert(SESSION_ID_KEY.to_string(), session.id.to_string());
+                Ok(Some(session_state))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        mut session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        use crate::schema::sessions::dsl::*;
+
+        session_state.remove(SESSION_ID_KEY);
+        let session_key = generate_session_key();
+        let now = chrono::offset::Utc::now();
+
+        let mut con = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| SaveError::Other(e.into()))?;
+        diesel::insert_into(sessions)
+            .values((
+                key_hash.eq(hash_key(&session_key)),
+                state.eq(serde_json::to_string(&session_state)
+                    .map_err(|e| SaveError::Serialization(e.into()))?),
+                user_id.eq(state_value::<i32>(&session_state, LOGGED_IN_KEY)),
+                user_agent.eq(state_value::<String>(&session_state, USER_AGENT_KEY)),
+                ip_address.eq(state_value::<String>(&session_state, IP_ADDRESS_KEY)),
+                created_at.eq(now),
+                last_seen_at.eq(now),
+                expires_at.eq(expiry(ttl)),
+            ))
+            .execute(&mut con)
+            .await
+            .map_err(|e| SaveError::Other(e.into()))?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        mut session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        use crate::schema::sessions::dsl::*;
+
+        session_state.remove(SESSION_ID_KEY);
+
+        // A session revoked while the request was being handled stays
+        // revoked, as nothing gets updated
+        let mut con = self
+            .pool
+            .get()
+            .await
+            .map_err(|e| UpdateError::Other(e.into()))?;
+        diesel::update(sessions)
+            .filter(key_hash.eq(hash_key(&session_key)))
+            .set((
+                state.eq(serde_json::to_string(&session_state)
+                    .map_err(|e| UpdateError::Serialization(e.into()))?),
+                user_id.eq(state_value::<i32>(&session_state, LOGGED_IN_KEY)),
+                user_agent.eq(state_value::<String>(&session_state, USER_AGENT_KEY)),
+                ip_address.eq(state_value::<String>(&session_state, IP_ADDRESS_KEY)),
+                last_seen_at.eq(chrono::offset::Utc::now()),
+                expires_at.eq(expiry(ttl)),
+            ))
+            .execute(&mut con)
+            .await
+            .map_err(|e| UpdateError::Other(e.into()))?;
+
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        use crate::schema::sessions::dsl::*;
+
+        let mut con = self.pool.get().await?;
+        diesel::update(sessions)
+            .filter(key_hash.eq(hash_key(session_key)))
+            .set((
+                last_seen_at.eq(chrono::offset::Utc::now()),
+                expires_at.eq(expiry(ttl)),
+            ))
+            .execute(&mut con)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        use crate::schema::sessions::dsl::*;
+
+        let mut con = self.pool.get().await?;
+        diesel::delete(sessions)
+            .filter(key_hash.eq(hash_key(session_key)))
+            .execute(&mut con)
+            .await?;
+
+        Ok(())
+    }
+}
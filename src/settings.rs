@@ -0,0 +1,74 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Port the http server listens on
+pub const PORT: u16 = 3030;
+
+// Read development mode from environment on first access. Development mode
+// turns off security measures which would get in the way of testing, and
+// must never be enabled on a publicly reachable server.
+static DEV_MODE: LazyLock<bool> = LazyLock::new(|| match std::env::var("DEV_MODE") {
+    Ok(val) => matches!(val.to_lowercase().as_str(), "1" | "true" | "yes" | "on"),
+    Err(_) => false,
+});
+
+/// Returns true if the server is running in development mode
+pub fn dev_mode() -> bool {
+    *DEV_MODE
+}
+
+// Read the cancellation window from environment on first access
+static CANCELLATION_WINDOW: LazyLock<Duration> = LazyLock::new(|| {
+    std::env::var("CANCELLATION_WINDOW")
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .map_or(Duration::from_secs(2 * 60), Duration::from_secs)
+});
+
+/// Returns how long buyers can cancel their purchases for. Can be set in
+/// seconds with the `CANCELLATION_WINDOW` environment variable, which
+/// defaults to two minutes.
+pub fn cancellation_window() -> Duration {
+    *CANCELLATION_WINDOW
+}
+
+// Read trusted proxies from environment on first access
+static TRUSTED_PROXIES: LazyLock<Vec<IpAddr>> = LazyLock::new(|| {
+    std::env::var("TRUSTED_PROXIES")
+        .map(|val| {
+            val.split(',')
+                .filter_map(|address| address.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+});
+
+/// Returns addresses of reverse proxies whose `Forwarded` and
+/// `X-Forwarded-For` headers are believed. Can be set as a comma separated
+/// list with the `TRUSTED_PROXIES` environment variable, which defaults to none.
+pub fn trusted_proxies() -> &'static [IpAddr] {
+    &TRUSTED_PROXIES
+}
+
+/// Returns the directory product catalogues are imported from. Can be set
+/// with the `CATALOGUE_DIR` environment variable, which defaults to `catalogue`.
+pub fn catalogue_dir() -> PathBuf {
+    std::env::var_os("CATALOGUE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("catalogue"))
+}
+
+/// Returns the address to bind the http server to. Can be set with the
+/// `BIND_ADDRESS` environment variable, which defaults to loopback in
+/// development mode and to every interface otherwise.
+pub fn bind_address() -> Result<IpAddr, String> {
+    match std::env::var("BIND_ADDRESS") {
+        Ok(val) => val
+            .parse()
+            .map_err(|_| format!("BIND_ADDRESS `{val}` is not a valid ip address")),
+        Err(_) if dev_mode() => Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        Err(_) => Ok(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+    }
+}
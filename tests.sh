@@ -6,8 +6,8 @@ pg_isready -h postgres || exit 1
 # Build backend
 cargo build || exit 1
 
-# Start backend server in background
-cargo run &
+# Start backend server in background, in development mode for test helpers
+DEV_MODE=1 cargo run &
 # Store backend process id for later use
 BACKEND_PID=$!
 # Allow some time for the backend to start